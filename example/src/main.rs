@@ -6,6 +6,7 @@ use std::{net::SocketAddr, time::Duration};
 async fn list_meta_data() {
     let cube = MaxCube::new("172.22.51.191:62910").await.unwrap();
     println!("{:?}", cube);
+    cube.close().await.unwrap();
 }
 
 #[allow(dead_code)]
@@ -23,11 +24,18 @@ async fn pair() {
     cube.pair(Duration::from_secs(60)).await.unwrap();
 }
 
+#[allow(dead_code)]
+async fn discover() {
+    let cubes = eq3_max_cube_rs::discover(Duration::from_secs(2)).await.unwrap();
+    println!("{:?}", cubes);
+}
+
 #[tokio::main]
 async fn main() {
     simple_logger::init_with_level(log::Level::Debug).unwrap();
 
     // list_meta_data().await;
     // change_temp().await;
+    // discover().await;
     pair().await;
 }
@@ -0,0 +1,38 @@
+#![no_main]
+
+use base64::{engine::general_purpose, Engine as _};
+use eq3_max_cube_rs::messages::{
+    from_message_l, Device, Devices, HeaterThermostat, ShutterContact, WallThermostat,
+};
+use libfuzzer_sys::fuzz_target;
+
+/// one device of every type, with the RF addresses used in the corpus
+fn devices() -> Devices {
+    vec![
+        Device::HeaterThermostat(HeaterThermostat {
+            rf_address: 0x1ae72e,
+            ..Default::default()
+        }),
+        Device::WallThermostat(WallThermostat {
+            rf_address: 0x1ae518,
+            ..Default::default()
+        }),
+        Device::ShutterContact(ShutterContact {
+            rf_address: 0x1aea01,
+            ..Default::default()
+        }),
+    ]
+}
+
+fuzz_target!(|data: &[u8]| {
+    // the raw line, as received from the cube
+    if let Ok(line) = std::str::from_utf8(data) {
+        let _ = from_message_l(line, &mut devices());
+    }
+
+    // the data as payload of a well-formed line, to reach the binary decoding
+    let _ = from_message_l(
+        &format!("L:{}", general_purpose::STANDARD.encode(data)),
+        &mut devices(),
+    );
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+use base64::{engine::general_purpose, Engine as _};
+use eq3_max_cube_rs::messages::from_message_n;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // the raw line, as received from the cube
+    if let Ok(line) = std::str::from_utf8(data) {
+        let _ = from_message_n(line);
+    }
+
+    // the data as payload of a well-formed line, to reach the binary decoding
+    let _ = from_message_n(&format!("N:{}", general_purpose::STANDARD.encode(data)));
+});
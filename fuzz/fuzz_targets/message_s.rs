@@ -0,0 +1,14 @@
+#![no_main]
+
+use eq3_max_cube_rs::messages::from_message_s;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // the raw line, as received from the cube
+    if let Ok(line) = std::str::from_utf8(data) {
+        let _ = from_message_s(line, 0x1ae72e);
+    }
+
+    // the data as fields of a line with the right prefix
+    let _ = from_message_s(&format!("S:{}", String::from_utf8_lossy(data)), 0x1ae72e);
+});
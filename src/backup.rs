@@ -0,0 +1,294 @@
+//! Backup of the configuration of the cube, e.g. to replace a failed cube.
+//! `MaxCube::export_backup()` collects the rooms, the devices with their names, the link partners and
+//! the settings and time scheduling of the heater thermostats. `MaxCube::import_backup()` writes them
+//! to a cube again, after the devices are paired with it.
+//!
+//! The backup is a plain serde structure with a version, so it can be stored e.g. as JSON or TOML.
+//! The cube does not report the link partners, they are the other devices in the same room, as the
+//! cube links them when the devices are assigned to a room.
+//! The settings of the wall thermostats are not decoded yet, so they are not part of the backup.
+//! Devices of other types, e.g. the eco button, keep their meta data only, they are not configured.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::messages::{
+    config_temperatures_command, config_valve_command, group_command, link_partner_command,
+    program_command, Device, DeviceSettings, Devices, HeaterThermostat, OtherDevice, Room, Rooms,
+    ShutterContact, ThermostatSettings, WallThermostat,
+};
+
+/// version of the backup format, written by `export_backup()`
+pub const BACKUP_VERSION: u32 = 1;
+
+/// configuration of the cube
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Backup {
+    /// version of the backup format, see `BACKUP_VERSION`
+    pub version: u32,
+    /// serial number of the cube, which the backup was taken from
+    pub serial: String,
+    pub rooms: Vec<RoomBackup>,
+    pub devices: Vec<DeviceBackup>,
+}
+
+/// configuration of a room
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomBackup {
+    /// room id (group id)
+    pub room_id: u8,
+    pub name: String,
+    /// RF address of the room
+    pub rf_address: u32,
+}
+
+/// configuration of a device
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceBackup {
+    /// device type, 1 = heater thermostat, 3 = wall thermostat, 4 = shutter contact,
+    /// other types as received from the cube
+    pub device_type: u8,
+    pub rf_address: u32,
+    pub serial: String,
+    pub name: String,
+    /// room id (group id), 0 if the device is not assigned to a room
+    pub room_id: u8,
+    /// the RF addresses of the devices, which the device talks to directly
+    #[serde(default)]
+    pub link_partners: Vec<u32>,
+    /// the settings and the time scheduling of the heater thermostats
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thermostat: Option<ThermostatSettings>,
+}
+
+impl Backup {
+    /// returns the backup of the rooms and devices, with the settings as received by `device_settings()`
+    pub fn new(
+        serial: &str,
+        rooms: &Rooms,
+        devices: &Devices,
+        settings: &[DeviceSettings],
+    ) -> Self {
+        let devices = devices
+            .iter()
+            .filter_map(|dev| {
+                let (device_type, rf_address, room_id) =
+                    (dev.device_type()?, dev.rf_address()?, dev.room_id()?);
+                let link_partners = devices
+                    .iter()
+                    .filter(|e| room_id != 0 && e.room_id() == Some(room_id))
+                    .filter(|e| is_supported(dev) && is_supported(e))
+                    .filter_map(Device::rf_address)
+                    .filter(|e| *e != rf_address)
+                    .collect();
+                Some(DeviceBackup {
+                    device_type,
+                    rf_address,
+                    serial: dev.serial()?.to_string(),
+                    name: dev.name().unwrap_or_default().to_string(),
+                    room_id,
+                    link_partners,
+                    thermostat: settings
+                        .iter()
+                        .find(|e| e.rf_address == rf_address)
+                        .and_then(|e| e.thermostat.clone()),
+                })
+            })
+            .collect();
+
+        Self {
+            version: BACKUP_VERSION,
+            serial: serial.to_string(),
+            rooms: rooms
+                .iter()
+                .map(|e| RoomBackup {
+                    room_id: e.room_id,
+                    name: e.name.clone(),
+                    rf_address: e.rf_address,
+                })
+                .collect(),
+            devices,
+        }
+    }
+
+    /// returns an error, if the backup was written by another version
+    pub fn check_version(&self) -> Result<()> {
+        if self.version != BACKUP_VERSION {
+            return Err(Error::BackupVersion(self.version));
+        }
+        Ok(())
+    }
+
+    /// returns the rooms and devices of the backup, as written to the cube by the meta data.
+    /// The devices have no state yet.
+    pub fn meta_data(&self) -> (Rooms, Devices) {
+        let rooms = self
+            .rooms
+            .iter()
+            .map(|e| Room {
+                room_id: e.room_id,
+                name: e.name.clone(),
+                rf_address: e.rf_address,
+                ..Default::default()
+            })
+            .collect();
+        let devices = self
+            .devices
+            .iter()
+            .map(|e| match e.device_type {
+                1 => Device::HeaterThermostat(HeaterThermostat {
+                    rf_address: e.rf_address,
+                    serial: e.serial.clone(),
+                    name: e.name.clone(),
+                    room_id: e.room_id,
+                    ..Default::default()
+                }),
+                3 => Device::WallThermostat(WallThermostat {
+                    rf_address: e.rf_address,
+                    serial: e.serial.clone(),
+                    name: e.name.clone(),
+                    room_id: e.room_id,
+                    ..Default::default()
+                }),
+                4 => Device::ShutterContact(ShutterContact {
+                    rf_address: e.rf_address,
+                    serial: e.serial.clone(),
+                    name: e.name.clone(),
+                    room_id: e.room_id,
+                    ..Default::default()
+                }),
+                device_type => Device::Other(OtherDevice {
+                    device_type,
+                    rf_address: e.rf_address,
+                    serial: e.serial.clone(),
+                    name: e.name.clone(),
+                    room_id: e.room_id,
+                }),
+            })
+            .collect();
+        (rooms, devices)
+    }
+
+    /// returns the commands, which configure the devices after the meta data is written:
+    /// the group, the link partners, the settings and the time scheduling of every device.
+    /// Every command is returned with the RF address of the device.
+    pub(crate) fn commands(&self, devices: &Devices) -> Result<Vec<(u32, String)>> {
+        let mut commands = Vec::new();
+        for dev in &self.devices {
+            // devices of other types keep their meta data only
+            if !matches!(dev.device_type, 1 | 3 | 4) {
+                continue;
+            }
+            if dev.room_id != 0 {
+                commands.push((dev.rf_address, group_command(dev.rf_address, dev.room_id)));
+            }
+            for partner in &dev.link_partners {
+                if let Some(partner_type) = self
+                    .devices
+                    .iter()
+                    .find(|e| e.rf_address == *partner)
+                    .map(|e| e.device_type)
+                {
+                    commands.push((
+                        dev.rf_address,
+                        link_partner_command(dev.rf_address, dev.room_id, *partner, partner_type),
+                    ));
+                }
+            }
+            if let Some(settings) = &dev.thermostat {
+                commands.push((
+                    dev.rf_address,
+                    config_temperatures_command(dev.rf_address, dev.room_id, settings),
+                ));
+                commands.push((
+                    dev.rf_address,
+                    config_valve_command(dev.rf_address, dev.room_id, settings),
+                ));
+                for (day, program) in settings.weekly_program.iter().enumerate() {
+                    commands.push((
+                        dev.rf_address,
+                        program_command(devices, dev.rf_address, day as u8, program)?,
+                    ));
+                }
+            }
+        }
+        Ok(commands)
+    }
+}
+
+/// returns if the device is configured by the backup
+fn is_supported(dev: &Device) -> bool {
+    !matches!(dev, Device::Other(_) | Device::NotSupported)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::test::MESSAGE_M;
+    use crate::messages::{from_message_c, from_message_m, to_message_c};
+
+    #[test]
+    fn test_backup() {
+        let (rooms, mut devices) = from_message_m(MESSAGE_M).unwrap();
+        // a second device in the bedroom
+        devices.push(Device::ShutterContact(ShutterContact {
+            rf_address: 0x1b0001,
+            serial: "OEQ0000001".to_string(),
+            name: "Bedroom window".to_string(),
+            room_id: 1,
+            ..Default::default()
+        }));
+        // an eco button in the bedroom, which is not configured
+        devices.push(Device::Other(OtherDevice {
+            device_type: 5,
+            rf_address: 0x0abcde,
+            serial: "KEQ0000002".to_string(),
+            name: "Eco".to_string(),
+            room_id: 1,
+        }));
+        let settings = from_message_c(&to_message_c(&DeviceSettings {
+            rf_address: 0x1ae5d3,
+            device_type: 1,
+            room_id: 1,
+            serial: "OEQ2121644".to_string(),
+            thermostat: Some(ThermostatSettings::default()),
+            ..Default::default()
+        }))
+        .unwrap();
+
+        let backup = Backup::new("KEQ0523864", &rooms, &devices, &[settings]);
+        assert_eq!(backup.version, BACKUP_VERSION);
+        assert_eq!(backup.rooms.len(), 5);
+        assert_eq!(backup.devices.len(), 7);
+        assert_eq!(backup.devices[0].link_partners, vec![0x1b0001]);
+        assert_eq!(backup.devices[5].link_partners, vec![0x1ae5d3]);
+        assert_eq!(backup.devices[6].device_type, 5);
+        assert!(backup.devices[6].link_partners.is_empty());
+        assert!(backup.devices[0].thermostat.is_some());
+        assert!(backup.devices[1].thermostat.is_none());
+
+        // the meta data is the same as before
+        assert_eq!(backup.meta_data(), (rooms, devices.clone()));
+
+        let commands = backup.commands(&devices).unwrap();
+        // group, partner, 2 settings and 7 days for the thermostat in the bedroom, group and partner for the
+        // shutter contact, group for the other rooms
+        assert_eq!(commands.len(), 11 + 2 + 4);
+        assert_eq!(
+            commands[0],
+            (0x1ae5d3, "s:AAAiAAAAGuXTAAE=\r\n".to_string())
+        );
+        assert_eq!(
+            commands[1],
+            (0x1ae5d3, "s:AAAgAAAAGuXTARsAAQQ=\r\n".to_string())
+        );
+        assert_eq!(
+            commands[2],
+            (0x1ae5d3, "s:AAARAAAAGuXTASoiPQkHGAM=\r\n".to_string())
+        );
+
+        let mut old = backup.clone();
+        old.version = 0;
+        assert!(matches!(old.check_version(), Err(Error::BackupVersion(0))));
+    }
+}
@@ -0,0 +1,38 @@
+//! Simulator of the MAX! Cube for testing and development without the physical cube.
+//!
+//! Usage: `max-cube-sim [ADDRESS]`, the default address is `0.0.0.0:62910`.
+
+use std::future::Future;
+
+use eq3_max_cube_rs::sim::Simulator;
+
+fn main() {
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "0.0.0.0:62910".to_string());
+
+    let mut sim = Simulator::default();
+    println!("Simulated cube listening on {}", addr);
+    for room in sim.rooms() {
+        println!("Room {}: {}", room.room_id, room.name);
+    }
+
+    if let Err(e) = block_on(sim.serve(addr.as_str())) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(feature = "runtime-tokio")]
+fn block_on<F: Future>(fut: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(fut)
+}
+
+#[cfg(not(feature = "runtime-tokio"))]
+fn block_on<F: Future>(fut: F) -> F::Output {
+    async_std::task::block_on(fut)
+}
@@ -0,0 +1,742 @@
+//! Command-line tool for the MAX! Cube.
+//!
+//! Usage: `maxcube [--cube ADDRESS | --serial SERIAL] [--json] <COMMAND>`, see `maxcube --help`.
+//! Devices and rooms are addressed by their name or by the RF address in hex, e.g. `1ae5d3`.
+//! If a device and a room have the same name, the device is used.
+
+use std::future::Future;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::json;
+
+use eq3_max_cube_rs::backup::Backup;
+use eq3_max_cube_rs::history::{HistoryFormat, HistoryLogger};
+use eq3_max_cube_rs::messages::{DateTime, DayProgram, Device, DeviceMode, DAYS};
+use eq3_max_cube_rs::plan::{parse_program, DesiredState, Plan};
+use eq3_max_cube_rs::{discover, Error, MaxCube, DEFAULT_DISCOVERY_TIMEOUT, TCP_PORT};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Parser)]
+#[command(
+    name = "maxcube",
+    version,
+    about = "Command-line tool for the eQ3 MAX! Cube"
+)]
+struct Cli {
+    /// address of the cube, e.g. `192.168.0.10`. The first cube found by discovery is used by default.
+    #[arg(long, global = true)]
+    cube: Option<String>,
+
+    /// serial number of the cube, which is searched by discovery
+    #[arg(long, global = true, conflicts_with = "cube")]
+    serial: Option<String>,
+
+    /// prints the output as JSON
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// discovers the cubes in the local network
+    Discover {
+        /// time to wait for answers, in seconds
+        #[arg(long, default_value_t = 2)]
+        timeout: u64,
+    },
+    /// shows the rooms and devices with their current state
+    Status,
+    /// sets the temperature of a device or a room in the manual mode
+    Set { target: String, temperature: f64 },
+    /// sets the mode of a device or a room
+    Mode {
+        target: String,
+        mode: Mode,
+        /// temperature set point, the current set point is kept by default
+        #[arg(long)]
+        temperature: Option<f64>,
+        /// end of the vacation, e.g. `2024-08-29T14:00`
+        #[arg(long, required_if_eq("mode", "vacation"))]
+        until: Option<String>,
+    },
+    /// pairs a new device
+    Pair {
+        /// time to wait for a new device, in seconds
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+    },
+    /// renames a device or a room
+    Rename { target: String, name: String },
+    /// deletes a device from the cube
+    Delete { device: String },
+    /// reads or writes the time scheduling of a thermostat
+    Schedule {
+        #[command(subcommand)]
+        command: Schedule,
+    },
+    /// writes the configuration of the rooms and devices to a JSON file
+    Backup { file: String },
+    /// writes the configuration from a JSON file to the cube, the devices shall be paired before
+    Restore { file: String },
+    /// prints the differences between the desired state in a TOML file and the cube
+    Plan { file: String },
+    /// changes the cube to the desired state in a TOML file, as far as the duty cycle allows
+    Apply { file: String },
+    /// writes the history of the heater thermostats until the connection to the cube is lost
+    History {
+        /// format of the history
+        #[arg(long, value_enum, default_value_t = Format::Influx)]
+        format: Format,
+        /// file, which the history is appended to. It is written to stdout by default.
+        #[arg(long)]
+        output: Option<String>,
+        /// interval of the status refresh, in seconds
+        #[arg(long, default_value_t = 60)]
+        interval: u64,
+    },
+    /// serves the REST API until the connection to the cube is lost
+    #[cfg(feature = "http")]
+    Serve {
+        /// address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+    /// bridges the cube to a MQTT broker until a connection is lost
+    #[cfg(feature = "mqtt")]
+    Mqtt {
+        /// address of the broker
+        #[arg(long, default_value = "127.0.0.1:1883")]
+        broker: String,
+        /// prefix of the topics
+        #[arg(long, default_value = "maxcube")]
+        prefix: String,
+        /// user name for the broker
+        #[arg(long, requires = "password")]
+        username: Option<String>,
+        /// password for the broker
+        #[arg(long, requires = "username")]
+        password: Option<String>,
+        /// interval of the status refresh, in seconds
+        #[arg(long, default_value_t = 30)]
+        interval: u64,
+        /// publishes the discovery configs of Home Assistant with the prefix
+        #[arg(long, num_args = 0..=1, default_missing_value = "homeassistant")]
+        discovery: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum Schedule {
+    /// shows the time scheduling of the week
+    Get { device: String },
+    /// sets the time scheduling of one day, e.g. `schedule set Bedroom monday 06:00=17 22:00=21 24:00=17`
+    Set {
+        device: String,
+        day: Day,
+        /// switch points `HH:MM=TEMPERATURE`, the temperature is kept until the time.
+        /// The last point shall end at 24:00.
+        #[arg(required = true)]
+        points: Vec<String>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Mode {
+    Auto,
+    Manual,
+    Boost,
+    Vacation,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Influx,
+    Csv,
+}
+
+/// days of the week in the order of the cube
+#[derive(Clone, Copy, ValueEnum)]
+enum Day {
+    Saturday,
+    Sunday,
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+}
+
+/// a device or a room, which is addressed on the command line
+#[derive(Clone, Copy)]
+enum Target {
+    Device(u32),
+    Room(u8),
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = block_on(run(cli)) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    if let Command::Discover { timeout } = cli.command {
+        let cubes = discover(Duration::from_secs(timeout)).await?;
+        if cli.json {
+            println!("{}", serde_json::to_string_pretty(&cubes)?);
+        } else {
+            println!("{:<12} {:<16} {:<8} Firmware", "Serial", "IP", "RF");
+            for cube in cubes {
+                println!(
+                    "{:<12} {:<16} {:06x}   {:04x}",
+                    cube.serial,
+                    cube.ip.to_string(),
+                    cube.rf_address,
+                    cube.firmware
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    let mut cube = connect(&cli).await?;
+    match &cli.command {
+        Command::Discover { .. } => unreachable!(),
+        Command::Status => print_status(&cube, cli.json)?,
+        Command::Backup { file } => {
+            let backup = cube.export_backup().await?;
+            std::fs::write(file, serde_json::to_string_pretty(&backup)?)?;
+            print_done(
+                cli.json,
+                &format!(
+                    "Backup of {} devices written to {}.",
+                    backup.devices.len(),
+                    file
+                ),
+            );
+        }
+        Command::Restore { file } => {
+            let backup: Backup = serde_json::from_str(&std::fs::read_to_string(file)?)?;
+            cube.import_backup(&backup).await?;
+            print_done(
+                cli.json,
+                &format!("Backup of {} devices restored.", backup.devices.len()),
+            );
+        }
+        Command::Plan { file } => {
+            let plan = cube.plan(&read_desired_state(file)?).await?;
+            print_plan(&plan, cli.json);
+        }
+        Command::Apply { file } => {
+            let plan = cube.plan(&read_desired_state(file)?).await?;
+            print_plan(&plan, cli.json);
+            if !plan.is_empty() {
+                cube.apply(&plan).await?;
+                print_done(
+                    cli.json,
+                    &format!("{} changes applied.", plan.changes.len()),
+                );
+            }
+        }
+        Command::History {
+            format,
+            output,
+            interval,
+        } => {
+            let format = match format {
+                Format::Influx => HistoryFormat::Influx,
+                Format::Csv => HistoryFormat::Csv,
+            };
+            let history = match output {
+                Some(path) => HistoryLogger::append(format, path)?,
+                None => HistoryLogger::new(format, std::io::stdout()),
+            };
+            cube.set_history(Some(history));
+            loop {
+                cube.refresh().await?;
+                sleep(Duration::from_secs(*interval)).await;
+            }
+        }
+        #[cfg(feature = "http")]
+        Command::Serve { listen } => {
+            eq3_max_cube_rs::http::serve(&mut cube, listen.as_str()).await?
+        }
+        #[cfg(feature = "mqtt")]
+        Command::Mqtt {
+            broker,
+            prefix,
+            username,
+            password,
+            interval,
+            discovery,
+        } => {
+            let mut bridge = eq3_max_cube_rs::mqtt::MqttBridge::new()
+                .set_prefix(prefix)
+                .set_interval(Duration::from_secs(*interval));
+            if let (Some(username), Some(password)) = (username, password) {
+                bridge = bridge.set_credentials(username, password);
+            }
+            if let Some(discovery) = discovery {
+                bridge = bridge.set_discovery(discovery);
+            }
+            bridge.run(broker.as_str(), &mut cube).await?
+        }
+        Command::Set {
+            target,
+            temperature,
+        } => {
+            cube.set_temperature(thermostat(&cube, target)?, *temperature)
+                .await?;
+            print_done(
+                cli.json,
+                &format!("Temperature set to {:.1}°C.", temperature),
+            );
+        }
+        Command::Mode {
+            target,
+            mode,
+            temperature,
+            until,
+        } => {
+            let rf_address = thermostat(&cube, target)?;
+            let temperature = match (mode, temperature) {
+                (_, Some(temperature)) => *temperature,
+                // the temperature of the time scheduling is used
+                (Mode::Auto, None) => 0.0,
+                (_, None) => temperature_set(&cube, rf_address),
+            };
+            let mode = match mode {
+                Mode::Auto => DeviceMode::Auto,
+                Mode::Manual => DeviceMode::Manual,
+                Mode::Boost => DeviceMode::Boost,
+                Mode::Vacation => DeviceMode::Vacation,
+            };
+            if mode == DeviceMode::Vacation {
+                let until = parse_date_time(until.as_deref().unwrap_or_default())?;
+                cube.set_vacation(rf_address, temperature, until).await?;
+            } else {
+                cube.set_mode(rf_address, mode, temperature).await?;
+            }
+            print_done(cli.json, "Mode set.");
+        }
+        Command::Pair { timeout } => {
+            let count = cube.devices.len();
+            cube.pair(Duration::from_secs(*timeout)).await?;
+            let new = &cube.devices[count..];
+            if cli.json {
+                println!("{}", serde_json::to_string_pretty(new)?);
+            } else if new.is_empty() {
+                println!("No new device found.");
+            } else {
+                for dev in new {
+                    let (kind, serial) = match dev {
+                        Device::HeaterThermostat(ts) => ("thermostat", &ts.serial),
+                        Device::WallThermostat(wt) => ("wall thermostat", &wt.serial),
+                        Device::ShutterContact(sc) => ("shutter contact", &sc.serial),
+                        Device::Other(_) | Device::NotSupported => continue,
+                    };
+                    println!(
+                        "New {} {:06x} ({}) paired.",
+                        kind,
+                        dev.rf_address().unwrap_or_default(),
+                        serial
+                    );
+                }
+            }
+        }
+        Command::Rename { target, name } => {
+            match resolve(&cube, target)? {
+                Target::Device(rf_address) => cube.rename_device(rf_address, name).await?,
+                Target::Room(room_id) => cube.rename_room(room_id, name).await?,
+            }
+            print_done(cli.json, &format!("Renamed to {:?}.", name));
+        }
+        Command::Delete { device } => match resolve(&cube, device)? {
+            Target::Device(rf_address) => {
+                cube.delete_device(rf_address).await?;
+                print_done(cli.json, &format!("Device {:06x} deleted.", rf_address));
+            }
+            Target::Room(_) => return Err("Only devices can be deleted.".into()),
+        },
+        Command::Schedule {
+            command: Schedule::Get { device },
+        } => {
+            let rf_address = thermostat(&cube, device)?;
+            let settings = cube.device_settings(rf_address).await?;
+            let program = settings
+                .thermostat
+                .ok_or("The device has no time scheduling.")?
+                .weekly_program;
+            print_program(&program, cli.json)?;
+        }
+        Command::Schedule {
+            command:
+                Schedule::Set {
+                    device,
+                    day,
+                    points,
+                },
+        } => {
+            let rf_address = thermostat(&cube, device)?;
+            let program = parse_program(points)?;
+            cube.set_program(rf_address, *day as u8, &program).await?;
+            print_done(cli.json, "Time scheduling set.");
+        }
+    }
+
+    cube.close().await?;
+    Ok(())
+}
+
+/// connects to the cube given by address or serial number, otherwise to the first cube found
+async fn connect(cli: &Cli) -> Result<MaxCube> {
+    let cube = match (&cli.cube, &cli.serial) {
+        (Some(addr), _) => match addr.parse::<IpAddr>() {
+            Ok(ip) => MaxCube::new((ip, TCP_PORT)).await?,
+            Err(_) if addr.contains(':') => MaxCube::new(addr.as_str()).await?,
+            Err(_) => MaxCube::new(format!("{}:{}", addr, TCP_PORT).as_str()).await?,
+        },
+        (None, Some(serial)) => MaxCube::connect_serial(serial).await?,
+        (None, None) => {
+            let found = discover(DEFAULT_DISCOVERY_TIMEOUT).await?;
+            let info = found.first().ok_or("No cube found.")?;
+            MaxCube::new((info.ip, TCP_PORT)).await?
+        }
+    };
+    Ok(cube)
+}
+
+/// finds the thermostat or room with the name, or the device with the RF address
+fn resolve(cube: &MaxCube, name: &str) -> Result<Target> {
+    match cube.device_by_name(name) {
+        Ok(ts) => return Ok(Target::Device(ts.rf_address)),
+        Err(Error::NameNotFound(_)) => (),
+        Err(e) => return Err(e.into()),
+    }
+    match (cube.room_by_name(name), parse_address(name)) {
+        (Ok(room), _) => Ok(Target::Room(room.room_id)),
+        (Err(Error::NameNotFound(_)), Some(rf_address)) => Ok(Target::Device(rf_address)),
+        (Err(e), _) => Err(e.into()),
+    }
+}
+
+/// returns the RF address of the thermostat with the name or address, or of the first thermostat in the
+/// room with the name. The commands to a thermostat are applied to the whole room by the cube.
+fn thermostat(cube: &MaxCube, name: &str) -> Result<u32> {
+    match (cube.thermostat_by_name(name), parse_address(name)) {
+        (Err(Error::NameNotFound(_)), Some(rf_address)) => Ok(rf_address),
+        (result, _) => Ok(result?),
+    }
+}
+
+/// parses the RF address, given with the prefix `0x` or as exactly 6 hex digits, e.g. `1ae5d3`.
+/// Other names are not taken as address, so that a typo like `bed` is reported as unknown name.
+fn parse_address(s: &str) -> Option<u32> {
+    let digits = match s.strip_prefix("0x") {
+        Some(digits) if !digits.is_empty() && digits.len() <= 6 => digits,
+        None if s.len() == 6 => s,
+        _ => return None,
+    };
+    digits
+        .chars()
+        .all(|e| e.is_ascii_hexdigit())
+        .then(|| u32::from_str_radix(digits, 16).ok())
+        .flatten()
+}
+
+/// returns the current temperature set point of the thermostat
+fn temperature_set(cube: &MaxCube, rf_address: u32) -> f64 {
+    cube.devices
+        .iter()
+        .find_map(|e| match e {
+            Device::HeaterThermostat(ts) if ts.rf_address == rf_address => Some(ts.temperature_set),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+/// parses the date and time, e.g. `2024-08-29T14:00` or `2024-08-29 14:00`
+fn parse_date_time(s: &str) -> Result<DateTime> {
+    let invalid = || format!("Invalid date {:?}, e.g. 2024-08-29T14:00 expected.", s);
+    let (date, time) = s.split_once(['T', ' ']).ok_or_else(invalid)?;
+    let date = date
+        .split('-')
+        .map(|e| e.parse::<u16>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?;
+    let (hour, minute) = parse_time(time).ok_or_else(invalid)?;
+
+    let date = match date.as_slice() {
+        [year, month @ 0..=0xff, day @ 0..=0xff] => DateTime {
+            year: *year,
+            month: *month as u8,
+            day: *day as u8,
+            hour,
+            minute,
+        },
+        _ => return Err(invalid().into()),
+    };
+    date.validate().map_err(|_| invalid())?;
+    Ok(date)
+}
+
+/// parses the time `HH:MM`, 24:00 is allowed as end of the day
+fn parse_time(s: &str) -> Option<(u8, u8)> {
+    let (hour, minute) = s.split_once(':')?;
+    let (hour, minute) = (hour.parse::<u8>().ok()?, minute.parse::<u8>().ok()?);
+    (minute < 60 && (hour < 24 || (hour == 24 && minute == 0))).then_some((hour, minute))
+}
+
+/// prints the rooms with their devices as table, or the whole system as JSON
+fn print_status(cube: &MaxCube, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(cube)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:<16} {:<8} {:>6} {:>9} {:>6}  State",
+        "Room", "Mode", "Set", "Measured", "Valve"
+    );
+    for room in &cube.rooms {
+        let mut state = Vec::new();
+        if room.window_open {
+            state.push("window open");
+        }
+        if room.battery_low {
+            state.push("battery low");
+        }
+        println!(
+            "{:<16} {:<8} {:>6.1} {:>9.1} {:>5}%  {}",
+            room.name,
+            format!("{:?}", room.mode),
+            room.temperature_set,
+            room.temperature_measured,
+            room.valve_position,
+            state_of(state)
+        );
+    }
+    println!();
+
+    println!(
+        "{:<16} {:<24} {:<8} {:>6} {:>9} {:>6}  State",
+        "Room", "Device", "RF", "Set", "Measured", "Valve"
+    );
+    for dev in &cube.devices {
+        let (set, measured, valve, mut state, error, valid) = match dev {
+            Device::HeaterThermostat(ts) => (
+                format!("{:.1}", ts.temperature_set),
+                format!("{:.1}", ts.temperature_measured),
+                format!("{}%", ts.valve_position),
+                Vec::new(),
+                ts.error,
+                ts.valid,
+            ),
+            Device::WallThermostat(wt) => (
+                format!("{:.1}", wt.temperature_set),
+                format!("{:.1}", wt.temperature_measured),
+                "-".to_string(),
+                Vec::new(),
+                wt.error,
+                wt.valid,
+            ),
+            Device::ShutterContact(sc) => (
+                "-".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+                vec![if sc.open { "open" } else { "closed" }],
+                sc.error,
+                sc.valid,
+            ),
+            Device::Other(_) | Device::NotSupported => continue,
+        };
+        if dev.battery_low() {
+            state.push("battery low");
+        }
+        if error {
+            state.push("error");
+        }
+        if !valid {
+            state.push("invalid");
+        }
+        let room = cube
+            .rooms
+            .iter()
+            .find(|e| Some(e.room_id) == dev.room_id())
+            .map(|e| e.name.as_str())
+            .unwrap_or("-");
+        println!(
+            "{:<16} {:<24} {:06x}   {:>6} {:>9} {:>6}  {}",
+            room,
+            dev.name().unwrap_or_default(),
+            dev.rf_address().unwrap_or_default(),
+            set,
+            measured,
+            valve,
+            state_of(state)
+        );
+    }
+    Ok(())
+}
+
+/// joins the state flags, or returns "ok" if there is none
+fn state_of(state: Vec<&str>) -> String {
+    if state.is_empty() {
+        "ok".to_string()
+    } else {
+        state.join(", ")
+    }
+}
+
+/// prints the time scheduling of the week
+fn print_program(program: &[DayProgram; 7], json: bool) -> Result<()> {
+    if json {
+        let days = DAYS
+            .iter()
+            .zip(program)
+            .map(|(day, points)| json!({ "day": day, "points": points }))
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&days)?);
+        return Ok(());
+    }
+
+    for (day, points) in DAYS.iter().zip(program) {
+        let points = points
+            .iter()
+            .map(|e| {
+                format!(
+                    "{:02}:{:02}={:.1}",
+                    e.until / 60,
+                    e.until % 60,
+                    e.temperature
+                )
+            })
+            .collect::<Vec<_>>();
+        println!("{:<10} {}", day, points.join(" "));
+    }
+    Ok(())
+}
+
+/// reads the desired state from the TOML file
+fn read_desired_state(file: &str) -> Result<DesiredState> {
+    Ok(toml::from_str(&std::fs::read_to_string(file)?)?)
+}
+
+/// prints the changes of the plan, one per line
+fn print_plan(plan: &Plan, json: bool) {
+    if json {
+        let changes = plan
+            .changes
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        println!("{}", json!({ "changes": changes }));
+    } else {
+        print!("{}", plan);
+    }
+}
+
+/// prints the confirmation of a command
+fn print_done(json: bool, message: &str) {
+    if json {
+        println!("{}", json!({ "result": "ok", "message": message }));
+    } else {
+        println!("{}", message);
+    }
+}
+
+#[cfg(feature = "runtime-tokio")]
+fn block_on<F: Future>(fut: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(fut)
+}
+
+#[cfg(not(feature = "runtime-tokio"))]
+fn block_on<F: Future>(fut: F) -> F::Output {
+    async_std::task::block_on(fut)
+}
+
+#[cfg(feature = "runtime-tokio")]
+async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
+
+#[cfg(not(feature = "runtime-tokio"))]
+async fn sleep(duration: Duration) {
+    async_std::task::sleep(duration).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(parse_address("1ae5d3"), Some(0x1ae5d3));
+        assert_eq!(parse_address("0x1AE5D3"), Some(0x1ae5d3));
+        assert_eq!(parse_address("0x5"), Some(5));
+
+        // room and device names are no addresses
+        assert_eq!(parse_address("Kitchen"), None);
+        assert_eq!(parse_address("Office"), None);
+        assert_eq!(parse_address("1ae5d"), None);
+        assert_eq!(parse_address("1ae5d3a"), None);
+        assert_eq!(parse_address("0x"), None);
+        assert_eq!(parse_address("0x1ae5d3a"), None);
+        assert_eq!(parse_address("+1ae5d"), None);
+        assert_eq!(parse_address("0x+1ae5"), None);
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("06:30"), Some((6, 30)));
+        assert_eq!(parse_time("0:00"), Some((0, 0)));
+        assert_eq!(parse_time("24:00"), Some((24, 0)));
+
+        assert_eq!(parse_time("24:30"), None);
+        assert_eq!(parse_time("25:00"), None);
+        assert_eq!(parse_time("12:60"), None);
+        assert_eq!(parse_time("12"), None);
+        assert_eq!(parse_time("12:-5"), None);
+        assert_eq!(parse_time("ab:cd"), None);
+    }
+
+    #[test]
+    fn test_parse_date_time() {
+        let expected = DateTime {
+            year: 2024,
+            month: 8,
+            day: 29,
+            hour: 14,
+            minute: 0,
+        };
+        assert_eq!(parse_date_time("2024-08-29T14:00").unwrap(), expected);
+        assert_eq!(parse_date_time("2024-08-29 14:00").unwrap(), expected);
+
+        for s in [
+            "2024-08-29",
+            "2024-08-29T",
+            "2024-08-29T24:00",
+            "2024-13-29T14:00",
+            "2024-08-32T14:00",
+            "1999-08-29T14:00",
+            "2024-08T14:00",
+            "2024-08-29-01T14:00",
+            "2024-256-29T14:00",
+            "29.08.2024 14:00",
+        ] {
+            assert!(parse_date_time(s).is_err(), "{}", s);
+        }
+    }
+}
@@ -0,0 +1,913 @@
+//! Blocking client for applications without an async runtime.
+//! The API mirrors the async `MaxCube`, but is built on `std::net::TcpStream`.
+//! It is available with the feature `blocking`.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+use serde::Serialize;
+
+use crate::backup::Backup;
+use crate::error::{Error, Result};
+use crate::history::HistoryLogger;
+use crate::messages::{
+    device_by_name, devices_in_room, room_by_name, room_of, thermostat_by_name, CubeStatus,
+    DateTime, DayProgram, DeviceMode, DeviceSettings, Devices, HeaterThermostat, Room, Rooms,
+};
+use crate::plan::{DesiredState, Plan};
+use crate::session::{self, Config, Operation, Request, State, Step};
+use crate::trace::{Direction, Recorder};
+use crate::udp::{
+    build_request, set_network_config_request, CubeInfo, Discovery, NetworkConfig, NetworkRequest,
+    Query, SerialDiscovery, UDP_PORT,
+};
+use crate::TCP_PORT;
+
+/// discovers all cubes in the local network by a UDP broadcast.
+/// All answers received within the timeout are returned.
+pub fn discover(timeout: Duration) -> Result<Vec<CubeInfo>> {
+    discover_at(SocketAddr::from((Ipv4Addr::BROADCAST, UDP_PORT)), timeout)
+}
+
+/// sends the discovery to the given address, e.g. the broadcast address of another subnet
+/// or the address of a known cube.
+pub fn discover_at<A>(addr: A, timeout: Duration) -> Result<Vec<CubeInfo>>
+where
+    A: ToSocketAddrs,
+{
+    query(addr, timeout, Discovery::default())
+}
+
+/// discovers the cube with the serial number in the local network. It returns as soon as the cube has
+/// answered, `Error::CubeNotFound` is returned, if it did not answer within the timeout.
+pub fn discover_serial(serial: &str, timeout: Duration) -> Result<CubeInfo> {
+    discover_serial_at(
+        SocketAddr::from((Ipv4Addr::BROADCAST, UDP_PORT)),
+        serial,
+        timeout,
+    )
+}
+
+/// discovers the cube with the serial number, the request is sent to the given address
+pub fn discover_serial_at<A>(addr: A, serial: &str, timeout: Duration) -> Result<CubeInfo>
+where
+    A: ToSocketAddrs,
+{
+    query(addr, timeout, SerialDiscovery::new(serial))
+}
+
+/// reads the IP configuration of the cube with the serial number
+/// # Examples
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use eq3_max_cube_rs::blocking;
+/// let config = blocking::network_config("KEQ0523864", Duration::from_secs(2)).unwrap();
+/// println!("{:?}", config);
+/// ```
+pub fn network_config(serial: &str, timeout: Duration) -> Result<NetworkConfig> {
+    network_config_at(
+        SocketAddr::from((Ipv4Addr::BROADCAST, UDP_PORT)),
+        serial,
+        timeout,
+    )
+}
+
+/// reads the IP configuration of the cube with the serial number, the request is sent to the given address
+pub fn network_config_at<A>(addr: A, serial: &str, timeout: Duration) -> Result<NetworkConfig>
+where
+    A: ToSocketAddrs,
+{
+    query(addr, timeout, NetworkRequest::new(serial))
+}
+
+/// changes the IP configuration of the cube with the serial number.
+/// The cube does not answer this request, the new configuration is applied after a reboot.
+/// # Examples
+///
+/// ```no_run
+/// # use eq3_max_cube_rs::{blocking, udp::NetworkConfig};
+/// blocking::set_network_config("KEQ0523864", &NetworkConfig::dhcp()).unwrap();
+/// blocking::reboot("KEQ0523864").unwrap();
+/// ```
+pub fn set_network_config(serial: &str, config: &NetworkConfig) -> Result<()> {
+    set_network_config_at(
+        SocketAddr::from((Ipv4Addr::BROADCAST, UDP_PORT)),
+        serial,
+        config,
+    )
+}
+
+/// changes the IP configuration of the cube with the serial number, the request is sent to the given address
+pub fn set_network_config_at<A>(addr: A, serial: &str, config: &NetworkConfig) -> Result<()>
+where
+    A: ToSocketAddrs,
+{
+    send(addr, &set_network_config_request(serial, config))
+}
+
+/// reboots the cube with the serial number. The cube does not answer this request.
+pub fn reboot(serial: &str) -> Result<()> {
+    reboot_at(SocketAddr::from((Ipv4Addr::BROADCAST, UDP_PORT)), serial)
+}
+
+/// reboots the cube with the serial number, the request is sent to the given address
+pub fn reboot_at<A>(addr: A, serial: &str) -> Result<()>
+where
+    A: ToSocketAddrs,
+{
+    send(addr, &build_request(Some(serial), b'R'))
+}
+
+/// binds the socket for the communication with the cube.
+/// Some firmware versions answer to the UDP port of the cube instead of the sending port,
+/// therefore this port is preferred.
+fn bind() -> Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, UDP_PORT))
+        .or_else(|_| UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)))?;
+    socket.set_broadcast(true)?;
+    Ok(socket)
+}
+
+/// sends the request of the query and passes the answers to it, until it has all answers
+/// or the timeout is elapsed
+fn query<A, Q>(addr: A, timeout: Duration, mut query: Q) -> Result<Q::Output>
+where
+    A: ToSocketAddrs,
+    Q: Query,
+{
+    let socket = bind()?;
+    socket.send_to(&query.request(), addr)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 1024];
+
+    while let Some(remaining) = remaining(deadline) {
+        socket.set_read_timeout(Some(remaining))?;
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if is_timeout(&e) => break,
+            Err(e) => return Err(e.into()),
+        };
+        debug!("{}: {:?}", from, &buf[..len]);
+        if query.handle(&buf[..len], from) {
+            break;
+        }
+    }
+    query.finish(timeout)
+}
+
+/// sends a request, which the cube does not answer
+fn send<A: ToSocketAddrs>(addr: A, data: &[u8]) -> Result<()> {
+    let socket = bind()?;
+    socket.send_to(data, addr)?;
+    Ok(())
+}
+
+/// MaxCubeBuilder is used to configure the connection before connecting to the cube
+/// # Examples
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use eq3_max_cube_rs::blocking::MaxCubeBuilder;
+/// let cube = MaxCubeBuilder::new()
+///     .set_connect_timeout(Duration::from_secs(5))
+///     .set_command_timeout(Duration::from_secs(3))
+///     .connect("172.22.51.191:62910")
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MaxCubeBuilder {
+    config: Config,
+}
+
+impl MaxCubeBuilder {
+    /// returns a instant of MaxCubeBuilder with default values
+    pub fn new() -> Self {
+        Self {
+            ..Default::default()
+        }
+    }
+
+    session::builder_setters!();
+
+    /// connects to the cube and receives the meta and status data of the whole system.
+    /// If one of the steps takes longer than configured, `Error::Timeout` is returned.
+    pub fn connect<A>(self, addr: A) -> Result<MaxCube>
+    where
+        A: ToSocketAddrs,
+    {
+        let stream = self.connect_any(addr)?;
+
+        let mut cube = MaxCube {
+            reader: BufReader::new(stream.try_clone()?),
+            stream,
+            keep_alive: self.config.keep_alive,
+            command_timeout: self.config.command_timeout,
+            last_activity: Instant::now(),
+            connected: true,
+            recorder: self.config.recorder,
+            history: self.config.history,
+            status: CubeStatus::default(),
+            rooms: Rooms::new(),
+            devices: Devices::new(),
+        };
+
+        // the Max Cube will reply with meta data and status data immediately after connection
+        let start = Instant::now();
+        loop {
+            let received = cube.read_line(start, self.config.handshake_timeout, "Handshake")?;
+            if cube.state().handshake(&received)? {
+                return Ok(cube);
+            }
+        }
+    }
+
+    /// discovers the cube with the serial number in the local network and connects to it.
+    /// The discovery waits for the answer of the cube up to the discovery timeout.
+    pub fn connect_serial(self, serial: &str) -> Result<MaxCube> {
+        let cube = discover_serial(serial, self.config.discovery_timeout)?;
+
+        info!("Cube {} found at {}.", cube.serial, cube.ip);
+        self.connect((cube.ip, TCP_PORT))
+    }
+
+    /// tries all addresses, until a connection is established
+    fn connect_any<A: ToSocketAddrs>(&self, addr: A) -> Result<TcpStream> {
+        let mut last_error = None;
+        for addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.config.connect_timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) if is_timeout(&e) => {
+                    last_error = Some(Error::Timeout {
+                        operation: "Connection",
+                        duration: self.config.connect_timeout,
+                    })
+                }
+                Err(e) => last_error = Some(e.into()),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "No address to connect to.").into()
+        }))
+    }
+}
+
+/// MaxCube represtents a MAX! Cube Gateway, with a blocking connection.
+/// All operations to the devices shall be triggert from hier.
+#[derive(Debug, Serialize)]
+pub struct MaxCube {
+    /// Socket connection to Cube, kept alive while the application calls `keep_alive()`.
+    #[serde(skip_serializing)]
+    stream: TcpStream,
+
+    /// Buffered reader on the same socket, kept over the whole connection
+    #[serde(skip_serializing)]
+    reader: BufReader<TcpStream>,
+
+    /// Interval, after which an idle connection will be refreshed
+    #[serde(skip_serializing)]
+    keep_alive: Duration,
+
+    /// Maximum time to wait for the answer of a command
+    #[serde(skip_serializing)]
+    command_timeout: Duration,
+
+    /// Time of the last successful exchange with the cube
+    #[serde(skip_serializing)]
+    last_activity: Instant,
+
+    /// If the cube answered the last request
+    #[serde(skip_serializing)]
+    connected: bool,
+
+    /// Trace, which all sent and received lines are written to
+    #[serde(skip_serializing)]
+    recorder: Option<Recorder>,
+
+    /// History, which the heater thermostats are written to after every refresh
+    #[serde(skip_serializing)]
+    history: Option<HistoryLogger>,
+
+    /// State of the cube, received after connection. The duty cycle and the free memory slots
+    /// are updated by the answers to the commands.
+    pub status: CubeStatus,
+
+    /// A list to all rooms (groups)
+    pub rooms: Rooms,
+
+    /// A list to all devices
+    pub devices: Devices,
+}
+
+impl MaxCube {
+    /// starts a connection to MAX! Cube gateway.
+    /// After successful connection, the cube will send back the meta data and status data of the whole system
+    /// immediately. The data will be decoded and stored in this structure.
+    /// The default timeouts are used, use `MaxCubeBuilder` to configure them.
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use eq3_max_cube_rs::blocking::MaxCube;
+    /// let mut cube = MaxCube::new("172.22.51.191:62910").unwrap();
+    /// cube.set_temperature(1763839, 21.0).unwrap();
+    /// cube.close().unwrap();
+    /// ```
+    pub fn new<A>(addr: A) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        MaxCubeBuilder::new().connect(addr)
+    }
+
+    /// discovers the cube with the serial number in the local network and connects to it.
+    pub fn connect_serial(serial: &str) -> Result<Self> {
+        MaxCubeBuilder::new().connect_serial(serial)
+    }
+
+    /// sets the interval, after which an idle connection will be refreshed by `keep_alive()`.
+    /// Nothing is sent by itself, the application has to call `keep_alive()` periodically.
+    pub fn set_keep_alive(&mut self, interval: Duration) {
+        self.keep_alive = interval;
+    }
+
+    /// starts recording the session to the trace, or stops it with `None`.
+    /// Use `MaxCubeBuilder::set_recorder()` to record the data received after connection as well.
+    pub fn set_recorder(&mut self, recorder: Option<Recorder>) {
+        self.recorder = recorder;
+    }
+
+    /// starts writing the history of the heater thermostats after every refresh, or stops it with `None`
+    pub fn set_history(&mut self, history: Option<HistoryLogger>) {
+        self.history = history;
+    }
+
+    /// returns if the connection can be used. It is false, after the connection was closed or failed, and
+    /// after a request timed out, as a late answer could be taken for the answer of the next request.
+    /// All requests fail with `Error::NotConnected` then, a new connection is needed.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// returns the thermostat with the name.
+    /// `Error::NameNotFound` or `Error::AmbiguousName` is returned, if the name is missing or not unique.
+    pub fn device_by_name(&self, name: &str) -> Result<&HeaterThermostat> {
+        device_by_name(&self.devices, name)
+    }
+
+    /// returns the room with the name.
+    /// `Error::NameNotFound` or `Error::AmbiguousName` is returned, if the name is missing or not unique.
+    pub fn room_by_name(&self, name: &str) -> Result<&Room> {
+        room_by_name(&self.rooms, name)
+    }
+
+    /// returns the RF address of the thermostat with the name, or of the first thermostat in the room
+    /// with the name. If a device and a room have the same name, the device is used.
+    /// `Error::NameNotFound` or `Error::AmbiguousName` is returned, if the name is missing or not unique.
+    pub fn thermostat_by_name(&self, name: &str) -> Result<u32> {
+        thermostat_by_name(&self.rooms, &self.devices, name)
+    }
+
+    /// returns the thermostats in the room
+    pub fn devices_in_room(&self, room_id: u8) -> Vec<&HeaterThermostat> {
+        devices_in_room(&self.devices, room_id)
+    }
+
+    /// returns the room of the thermostat with the RF address
+    pub fn room_of(&self, rf_address: u32) -> Result<&Room> {
+        room_of(&self.rooms, &self.devices, rf_address)
+    }
+
+    /// requests the current status of all devices (`l:`) and updates `devices` and the state of `rooms`.
+    pub fn refresh(&mut self) -> Result<()> {
+        self.run(session::refresh())
+    }
+
+    /// keeps the connection alive. If there was no traffic for the keep alive interval,
+    /// the device status will be refreshed, otherwise nothing is done.
+    /// The cube closes connections without traffic after a few minutes. There is no background task,
+    /// so the application has to call this function periodically, more often than the interval,
+    /// e.g. from its main loop.
+    pub fn keep_alive(&mut self) -> Result<()> {
+        if self.last_activity.elapsed() >= self.keep_alive {
+            debug!("Connection idle, refreshing...");
+            self.refresh()?;
+        }
+        Ok(())
+    }
+
+    /// sets the thermostat with the rf_address to the manual mode and the given temperature.
+    pub fn set_temperature(&mut self, rf_address: u32, temperature: f64) -> Result<()> {
+        self.run(session::set_temperature(rf_address, temperature))
+    }
+
+    /// sets the thermostat with the rf_address to the mode, the vacation mode is set by `set_vacation()`.
+    pub fn set_mode(&mut self, rf_address: u32, mode: DeviceMode, temperature: f64) -> Result<()> {
+        self.run(session::set_mode(rf_address, mode, temperature, None))
+    }
+
+    /// sets the thermostat with the rf_address to the temperature until the end of the vacation.
+    pub fn set_vacation(
+        &mut self,
+        rf_address: u32,
+        temperature: f64,
+        until: DateTime,
+    ) -> Result<()> {
+        let mode = DeviceMode::Vacation;
+        self.run(session::set_mode(
+            rf_address,
+            mode,
+            temperature,
+            Some(until),
+        ))
+    }
+
+    /// requests the configuration of the device, including the time scheduling of the thermostats.
+    pub fn device_settings(&mut self, rf_address: u32) -> Result<DeviceSettings> {
+        self.run(session::device_settings(rf_address))
+    }
+
+    /// sets the time scheduling of the thermostat for one day, 0 = Saturday ... 6 = Friday.
+    pub fn set_program(&mut self, rf_address: u32, day: u8, program: &DayProgram) -> Result<()> {
+        self.run(session::set_program(rf_address, day, program))
+    }
+
+    /// renames the device and writes the meta data to the cube.
+    pub fn rename_device(&mut self, rf_address: u32, name: &str) -> Result<()> {
+        self.run(session::rename_device(rf_address, name))
+    }
+
+    /// renames the room and writes the meta data to the cube.
+    pub fn rename_room(&mut self, room_id: u8, name: &str) -> Result<()> {
+        self.run(session::rename_room(room_id, name))
+    }
+
+    /// deletes the device from the cube, the device has to be paired again to be used.
+    pub fn delete_device(&mut self, rf_address: u32) -> Result<()> {
+        self.run(session::delete_device(rf_address))
+    }
+
+    /// sets the thermostat or the room with the name to the manual mode and the given temperature.
+    /// If a device and a room have the same name, the device is used.
+    pub fn set_temperature_by_name(&mut self, name: &str, temperature: f64) -> Result<()> {
+        self.run(session::thermostat_named(name, |rf_address| {
+            session::set_temperature(rf_address, temperature)
+        }))
+    }
+
+    /// sets the thermostat or the room with the name to the mode, see `set_mode()`.
+    pub fn set_mode_by_name(
+        &mut self,
+        name: &str,
+        mode: DeviceMode,
+        temperature: f64,
+    ) -> Result<()> {
+        self.run(session::thermostat_named(name, |rf_address| {
+            session::set_mode(rf_address, mode, temperature, None)
+        }))
+    }
+
+    /// sets the thermostat or the room with the name to the temperature until the end of the vacation.
+    pub fn set_vacation_by_name(
+        &mut self,
+        name: &str,
+        temperature: f64,
+        until: DateTime,
+    ) -> Result<()> {
+        self.run(session::thermostat_named(name, |rf_address| {
+            session::set_mode(rf_address, DeviceMode::Vacation, temperature, Some(until))
+        }))
+    }
+
+    /// requests the configuration of the thermostat or of the first thermostat in the room with the name.
+    pub fn device_settings_by_name(&mut self, name: &str) -> Result<DeviceSettings> {
+        self.run(session::thermostat_named(name, session::device_settings))
+    }
+
+    /// sets the time scheduling of the thermostat or the room with the name for one day.
+    pub fn set_program_by_name(&mut self, name: &str, day: u8, program: &DayProgram) -> Result<()> {
+        self.run(session::thermostat_named(name, |rf_address| {
+            session::set_program(rf_address, day, program)
+        }))
+    }
+
+    /// renames the device with the name.
+    pub fn rename_device_by_name(&mut self, name: &str, new_name: &str) -> Result<()> {
+        self.run(session::device_named(name, |rf_address| {
+            session::rename_device(rf_address, new_name)
+        }))
+    }
+
+    /// renames the room with the name.
+    pub fn rename_room_by_name(&mut self, name: &str, new_name: &str) -> Result<()> {
+        self.run(session::room_named(name, |room_id| {
+            session::rename_room(room_id, new_name)
+        }))
+    }
+
+    /// deletes the device with the name from the cube.
+    pub fn delete_device_by_name(&mut self, name: &str) -> Result<()> {
+        self.run(session::device_named(name, session::delete_device))
+    }
+
+    /// returns the backup of the configuration: the rooms, the devices with their names and link partners,
+    /// and the settings and time scheduling of the heater thermostats, which are requested from the cube.
+    pub fn export_backup(&mut self) -> Result<Backup> {
+        self.run(session::export_backup())
+    }
+
+    /// re-creates the configuration of the backup, e.g. on a reset cube: the meta data with the rooms and
+    /// names, the groups and link partners of the devices, and the settings and time scheduling of the
+    /// heater thermostats. The devices shall be paired with the cube before, e.g. by `pair()`.
+    /// Stops with `Error::BackupIncomplete` and the devices left, when the duty cycle of the cube reaches
+    /// `DUTY_CYCLE_LIMIT` or the cube refuses a command for it. The import can be run again, after the
+    /// duty cycle has gone down, e.g. after an hour.
+    pub fn import_backup(&mut self, backup: &Backup) -> Result<()> {
+        self.run(session::import_backup(backup)?)
+    }
+
+    /// compares the desired state with the configuration of the cube, and returns the changes.
+    /// The settings and time scheduling of the heater thermostats are requested from the cube.
+    pub fn plan(&mut self, desired: &DesiredState) -> Result<Plan> {
+        self.run(session::plan(desired))
+    }
+
+    /// sends the commands of the plan: the meta data first, if rooms or devices are changed, then the
+    /// commands to the devices. Stops with `Error::DutyCycle` before a command, when the duty cycle of the
+    /// cube reaches `DUTY_CYCLE_LIMIT`, or at the first command, which is refused by the cube.
+    /// The remaining changes can be applied later by a new plan.
+    pub fn apply(&mut self, plan: &Plan) -> Result<()> {
+        self.run(session::apply(plan))
+    }
+
+    /// starts pairing of new devices and waits for the timeout, a new device is added to `devices`.
+    /// `Ok(())` is returned as well, if the cube stays silent, as no device was found.
+    pub fn pair(&mut self, timeout: Duration) -> Result<()> {
+        self.send(&session::pairing(timeout))?;
+
+        // response shall be only read after timeout / or aborted
+        sleep(timeout);
+
+        let received = match self.read_line(Instant::now(), self.command_timeout, "Pairing") {
+            Ok(received) => received,
+            Err(Error::Timeout { .. }) => {
+                // the cube may stay silent, if no device was found
+                info!("No new device found.");
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+
+        self.state().paired(&received);
+        Ok(())
+    }
+
+    /// closes the connection gracefully.
+    /// The cube is informed by a `q:` message and accepts the next client immediately.
+    pub fn close(mut self) -> Result<()> {
+        if self.connected {
+            self.send("q:\r\n")?;
+        }
+        self.connected = false;
+        self.stream.shutdown(Shutdown::Both)?;
+        Ok(())
+    }
+
+    /// runs the operation, its requests are sent one after another
+    fn run<O: Operation>(&mut self, mut operation: O) -> Result<O::Output> {
+        let mut answer = None;
+        loop {
+            match operation.step(&mut self.state(), answer.take())? {
+                Step::Request(request) => answer = Some(self.request(&request)?),
+                Step::Done(output) => return Ok(output),
+            }
+        }
+    }
+
+    /// returns the data of the cube, which is read and updated by the operations
+    fn state(&mut self) -> State<'_> {
+        State {
+            status: &mut self.status,
+            rooms: &mut self.rooms,
+            devices: &mut self.devices,
+            history: self.history.as_ref(),
+        }
+    }
+
+    /// sends the request to the cube and waits for the answer.
+    /// Other messages received in the meantime are ignored.
+    /// After a timeout, the connection is not used anymore, as the answer may still arrive.
+    fn request(&mut self, request: &Request) -> Result<String> {
+        let start = Instant::now();
+        self.send(&request.payload)?;
+        let result = loop {
+            match self.read_line(start, self.command_timeout, "Command") {
+                Ok(received) if !request.is_answer(&received, &mut self.status) => continue,
+                result => break result,
+            }
+        };
+
+        if let Err(Error::Timeout { .. }) = result {
+            self.connected = false;
+        }
+        result
+    }
+
+    /// sends the payload to the cube, `Error::NotConnected` is returned, if the connection is not usable
+    fn send(&mut self, payload: &str) -> Result<()> {
+        if !self.connected {
+            return Err(Error::NotConnected);
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Sent, payload);
+        }
+
+        let result = self
+            .stream
+            .write_all(payload.as_bytes())
+            .and_then(|_| self.stream.flush());
+
+        if result.is_err() {
+            self.connected = false;
+        }
+        Ok(result?)
+    }
+
+    /// reads one line from the cube, without the trailing line break.
+    /// `Error::Timeout` is returned, if the line is not complete within the timeout since the start.
+    fn read_line(
+        &mut self,
+        start: Instant,
+        timeout: Duration,
+        operation: &'static str,
+    ) -> Result<String> {
+        let remaining = remaining(start + timeout).ok_or(Error::Timeout {
+            operation,
+            duration: timeout,
+        })?;
+        self.reader.get_ref().set_read_timeout(Some(remaining))?;
+
+        let mut received = String::new();
+        match self.reader.read_line(&mut received) {
+            Ok(0) => {
+                self.connected = false;
+                Err(Error::ConnectionClosed)
+            }
+            Ok(_) => {
+                self.connected = true;
+                self.last_activity = Instant::now();
+                let received = received.replace("\r\n", "");
+                debug!("{:?}", received);
+                if let Some(recorder) = &self.recorder {
+                    recorder.record(Direction::Received, &received);
+                }
+                Ok(received)
+            }
+            Err(e) if is_timeout(&e) => Err(Error::Timeout {
+                operation,
+                duration: timeout,
+            }),
+            Err(e) => {
+                self.connected = false;
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// returns the remaining time until the deadline, `None` if it is passed
+fn remaining(deadline: Instant) -> Option<Duration> {
+    deadline
+        .checked_duration_since(Instant::now())
+        .filter(|e| !e.is_zero())
+}
+
+/// checks if the IO error is caused by a timeout. Depending on the platform, the kind differs.
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+    use crate::messages::test::{handshake, MESSAGE_L};
+    use crate::messages::Device;
+    use crate::trace::read_trace;
+    use crate::trace::test::Shared;
+
+    #[test]
+    fn test_blocking_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // scripted cube
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(handshake().as_bytes()).unwrap();
+
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "s:AARAAAAAGuXTAW4=\r\n");
+            stream.write_all(b"S:00,0,31\r\n").unwrap();
+
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "q:\r\n");
+        });
+
+        let mut cube = MaxCube::new(addr).unwrap();
+        assert_eq!(cube.rooms.len(), 5);
+        match &cube.devices[2] {
+            Device::HeaterThermostat(ts) => assert_eq!(ts.temperature_set, 19.0),
+            _ => panic!("Wrong device type!"),
+        }
+
+        cube.set_temperature(1762771, 23.0).unwrap();
+        assert!(matches!(
+            cube.set_temperature(1, 23.0),
+            Err(Error::DeviceNotFound(1))
+        ));
+        // nothing is sent for an unknown name
+        assert!(matches!(
+            cube.rename_room_by_name("Attic", "Loft"),
+            Err(Error::NameNotFound(_))
+        ));
+        cube.close().unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_blocking_recorder() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // scripted cube, which refuses the command by the duty cycle
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(handshake().as_bytes()).unwrap();
+
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            stream.write_all(b"S:64,1,0\r\n").unwrap();
+            reader.read_line(&mut line).unwrap();
+        });
+
+        let output = Shared::default();
+        let mut cube = MaxCubeBuilder::new()
+            .set_recorder(Recorder::new(output.clone()))
+            .connect(addr)
+            .unwrap();
+        assert!(matches!(
+            cube.set_temperature(1762771, 23.0),
+            Err(Error::DutyCycle {
+                duty_cycle: 100,
+                ..
+            })
+        ));
+        assert_eq!(cube.status.duty_cycle, 100);
+        cube.close().unwrap();
+        handle.join().unwrap();
+
+        let recorded = read_trace(output.0.lock().unwrap().as_slice()).unwrap();
+        let lines: Vec<_> = recorded
+            .iter()
+            .map(|e| (e.direction, e.line.as_str()))
+            .collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0].0, Direction::Received);
+        assert_eq!(
+            lines[3..],
+            [
+                (Direction::Sent, "s:AARAAAAAGuXTAW4="),
+                (Direction::Received, "S:64,1,0"),
+                (Direction::Sent, "q:"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_blocking_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // cube, which never finishes the handshake
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_millis(500));
+            drop(stream);
+        });
+
+        let result = MaxCubeBuilder::new()
+            .set_handshake_timeout(Duration::from_millis(100))
+            .connect(addr);
+        assert!(matches!(
+            result,
+            Err(Error::Timeout {
+                operation: "Handshake",
+                duration,
+            }) if duration == Duration::from_millis(100)
+        ));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_blocking_late_answer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // scripted cube, which answers after the command timeout
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(handshake().as_bytes()).unwrap();
+
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "l:\r\n");
+            thread::sleep(Duration::from_millis(300));
+            stream
+                .write_all(format!("{}\r\n", MESSAGE_L).as_bytes())
+                .unwrap();
+        });
+
+        let mut cube = MaxCubeBuilder::new()
+            .set_command_timeout(Duration::from_millis(100))
+            .connect(addr)
+            .unwrap();
+        assert!(matches!(
+            cube.refresh(),
+            Err(Error::Timeout {
+                operation: "Command",
+                ..
+            })
+        ));
+        assert!(!cube.is_connected());
+        handle.join().unwrap();
+
+        // the late answer is not taken for the answer of the next request
+        assert!(matches!(cube.refresh(), Err(Error::NotConnected)));
+        cube.close().unwrap();
+    }
+
+    #[test]
+    fn test_blocking_discover_serial() {
+        let responder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = responder.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            let (len, from) = responder.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"eQ3Max*\0**********I");
+            responder
+                .send_to(b"eQ3MaxApKEQ0523864>I\x00\x09\x3a\x41\x01\x13", from)
+                .unwrap();
+        });
+
+        // the answer is returned without waiting for the timeout
+        let start = Instant::now();
+        let cube = discover_serial_at(addr, "KEQ0523864", Duration::from_secs(10)).unwrap();
+        handle.join().unwrap();
+        assert_eq!(cube.rf_address, 0x093a41);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_blocking_network_config() {
+        let responder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = responder.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            let (len, from) = responder.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"eQ3Max*\0KEQ0523864N");
+            let mut response = b"eQ3MaxApKEQ0523864>N".to_vec();
+            response.extend([192, 168, 0, 222, 192, 168, 0, 1, 255, 255, 255, 0]);
+            response.extend([192, 168, 0, 1, 0, 0, 0, 0]);
+            responder.send_to(&response, from).unwrap();
+
+            let (len, _) = responder.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..19], b"eQ3Max*\0KEQ0523864c");
+            assert_eq!(&buf[19..len], [0; 20]);
+            let (len, _) = responder.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"eQ3Max*\0KEQ0523864R");
+        });
+
+        let config = network_config_at(addr, "KEQ0523864", Duration::from_millis(500)).unwrap();
+        assert!(!config.dhcp);
+        assert_eq!(config.ip, Ipv4Addr::new(192, 168, 0, 222));
+        assert_eq!(config.netmask, Ipv4Addr::new(255, 255, 255, 0));
+
+        set_network_config_at(addr, "KEQ0523864", &NetworkConfig::dhcp()).unwrap();
+        reboot_at(addr, "KEQ0523864").unwrap();
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,992 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use futures::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use futures::lock::Mutex;
+use log::{debug, info};
+use serde::Serialize;
+
+use crate::backup::Backup;
+use crate::error::{Error, Result};
+use crate::history::HistoryLogger;
+use crate::messages::{
+    device_by_name, devices_in_room, room_by_name, room_of, thermostat_by_name, CubeStatus,
+    DateTime, DayProgram, DeviceMode, DeviceSettings, Devices, HeaterThermostat, Room, Rooms,
+};
+use crate::plan::{DesiredState, Plan};
+use crate::rt::{self, sleep, TcpStream, ToSocketAddrs};
+use crate::session::{self, Config, Operation, Request, State, Step};
+use crate::trace::{Direction, Recorder};
+use crate::transport::Transport;
+use crate::udp::discover_serial;
+use crate::TCP_PORT;
+
+/// MaxCubeBuilder is used to configure the connection before connecting to the cube
+/// # Examples
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use eq3_max_cube_rs::MaxCubeBuilder;
+/// # futures::executor::block_on(async {
+/// let cube = MaxCubeBuilder::new()
+///     .set_connect_timeout(Duration::from_secs(5))
+///     .set_command_timeout(Duration::from_secs(3))
+///     .connect("172.22.51.191:62910")
+///     .await
+///     .unwrap();
+/// # });
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MaxCubeBuilder {
+    config: Config,
+}
+
+impl MaxCubeBuilder {
+    /// returns a instant of MaxCubeBuilder with default values
+    pub fn new() -> Self {
+        Self {
+            ..Default::default()
+        }
+    }
+
+    session::builder_setters!();
+
+    /// connects to the cube and receives the meta and status data of the whole system.
+    /// If one of the steps takes longer than configured, `Error::Timeout` is returned.
+    pub async fn connect<A>(self, addr: A) -> Result<MaxCube>
+    where
+        A: ToSocketAddrs,
+    {
+        let stream = with_timeout(self.config.connect_timeout, "Connection", async {
+            Ok(rt::connect(addr).await?)
+        })
+        .await?;
+        self.with_transport(stream).await
+    }
+
+    /// uses the already connected transport, and receives the meta and status data of the whole system.
+    pub async fn with_transport<T: Transport>(self, transport: T) -> Result<MaxCube<T>> {
+        let (reader, writer) = transport.split();
+
+        let mut cube = MaxCube {
+            reader: BufReader::new(reader),
+            writer,
+            keep_alive: self.config.keep_alive,
+            command_timeout: self.config.command_timeout,
+            last_activity: Instant::now(),
+            connected: true,
+            recorder: self.config.recorder,
+            history: self.config.history,
+            status: CubeStatus::default(),
+            rooms: Rooms::new(),
+            devices: Devices::new(),
+        };
+
+        // Sequence:
+        // Receive: H-Message, M-Message (multiple), C-Message (multiple), F-Message, L-Message
+        // Only H-, M- and L-Message will be proceed.
+        // All the content after L-Message will be ignored.
+
+        // the Max Cube will reply with meta data and status data immediately after connection
+        with_timeout(self.config.handshake_timeout, "Handshake", async {
+            loop {
+                let received = cube.read_line().await?;
+                if cube.state().handshake(&received)? {
+                    return Ok(());
+                }
+            }
+        })
+        .await?;
+
+        Ok(cube)
+    }
+
+    /// discovers the cube with the serial number in the local network and connects to it.
+    /// The discovery waits for the answer of the cube up to the discovery timeout.
+    pub async fn connect_serial(self, serial: &str) -> Result<MaxCube> {
+        let cube = discover_serial(serial, self.config.discovery_timeout).await?;
+
+        info!("Cube {} found at {}.", cube.serial, cube.ip);
+        self.connect((cube.ip, TCP_PORT)).await
+    }
+}
+
+/// runs the future, but stops waiting after the duration with `Error::Timeout`
+async fn with_timeout<T, F>(duration: Duration, operation: &'static str, fut: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    rt::timeout(duration, fut).await.ok_or(Error::Timeout {
+        operation,
+        duration,
+    })?
+}
+
+/// MaxCube represtents a MAX! Cube Gateway.
+/// All operations to the devices shall be triggert from hier.
+/// The connection is a TCP connection by default, but any `Transport` can be used.
+#[derive(Debug, Serialize)]
+pub struct MaxCube<T: Transport = TcpStream> {
+    /// Socket connection to Cube, kept alive while the application calls `keep_alive()`.
+    #[serde(skip_serializing)]
+    writer: WriteHalf<T>,
+
+    /// Buffered reader on the same socket, kept over the whole connection
+    #[serde(skip_serializing)]
+    reader: BufReader<ReadHalf<T>>,
+
+    /// Interval, after which an idle connection will be refreshed
+    #[serde(skip_serializing)]
+    keep_alive: Duration,
+
+    /// Maximum time to wait for the answer of a command
+    #[serde(skip_serializing)]
+    command_timeout: Duration,
+
+    /// Time of the last successful exchange with the cube
+    #[serde(skip_serializing)]
+    last_activity: Instant,
+
+    /// If the cube answered the last request
+    #[serde(skip_serializing)]
+    connected: bool,
+
+    /// Trace, which all sent and received lines are written to
+    #[serde(skip_serializing)]
+    recorder: Option<Recorder>,
+
+    /// History, which the heater thermostats are written to after every refresh
+    #[serde(skip_serializing)]
+    history: Option<HistoryLogger>,
+
+    /// State of the cube, received after connection. The duty cycle and the free memory slots
+    /// are updated by the answers to the commands.
+    pub status: CubeStatus,
+
+    /// A list to all rooms (groups)
+    pub rooms: Rooms,
+
+    /// A list to all devices
+    pub devices: Devices,
+}
+
+impl MaxCube {
+    /// starts a connection to MAX! Cube gateway.
+    /// The connection is kept alive, as long as `keep_alive()` is called periodically.
+    /// After successful connection, the cube will send back the meta data and status data of the whole system
+    /// immediately. The data will be decoded and stored in this structure.
+    /// The default timeouts are used, use `MaxCubeBuilder` to configure them.
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::net::SocketAddr;
+    /// # use eq3_max_cube_rs::MaxCube;
+    /// # futures::executor::block_on(async {
+    ///
+    /// let cube = MaxCube::new(&SocketAddr::from(([172, 22, 51, 191], 62910))).await.unwrap();
+    /// println!("{:?}", cube);
+    /// # });
+    /// ```
+    pub async fn new<A>(addr: A) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        MaxCubeBuilder::new().connect(addr).await
+    }
+
+    /// discovers the cube with the serial number in the local network and connects to it.
+    /// The current IP address is used, so that changes of the address by DHCP do not matter.
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use eq3_max_cube_rs::MaxCube;
+    /// # futures::executor::block_on(async {
+    /// let cube = MaxCube::connect_serial("KEQ0523864").await.unwrap();
+    /// # });
+    /// ```
+    pub async fn connect_serial(serial: &str) -> Result<Self> {
+        MaxCubeBuilder::new().connect_serial(serial).await
+    }
+}
+
+impl<T: Transport> MaxCube<T> {
+    /// starts the communication over an already connected transport, e.g. a TLS tunnel or an in-memory stream.
+    /// The default timeouts are used, use `MaxCubeBuilder::with_transport()` to configure them.
+    pub async fn from_transport(transport: T) -> Result<Self> {
+        MaxCubeBuilder::new().with_transport(transport).await
+    }
+
+    /// sets the interval, after which an idle connection will be refreshed by `keep_alive()`.
+    /// Nothing is sent by itself, the application has to call `keep_alive()` periodically
+    /// or run `run_keep_alive()`.
+    /// The cube closes connections without traffic after a few minutes.
+    pub fn set_keep_alive(&mut self, interval: Duration) {
+        self.keep_alive = interval;
+    }
+
+    /// starts recording the session to the trace, or stops it with `None`.
+    /// Use `MaxCubeBuilder::set_recorder()` to record the data received after connection as well.
+    pub fn set_recorder(&mut self, recorder: Option<Recorder>) {
+        self.recorder = recorder;
+    }
+
+    /// starts writing the history of the heater thermostats after every refresh, or stops it with `None`
+    pub fn set_history(&mut self, history: Option<HistoryLogger>) {
+        self.history = history;
+    }
+
+    /// returns if the connection can be used. It is false, after the connection was closed or failed, and
+    /// after a request timed out, as a late answer could be taken for the answer of the next request.
+    /// All requests fail with `Error::NotConnected` then, a new connection is needed.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// returns the thermostat with the name.
+    /// `Error::NameNotFound` or `Error::AmbiguousName` is returned, if the name is missing or not unique.
+    pub fn device_by_name(&self, name: &str) -> Result<&HeaterThermostat> {
+        device_by_name(&self.devices, name)
+    }
+
+    /// returns the room with the name.
+    /// `Error::NameNotFound` or `Error::AmbiguousName` is returned, if the name is missing or not unique.
+    pub fn room_by_name(&self, name: &str) -> Result<&Room> {
+        room_by_name(&self.rooms, name)
+    }
+
+    /// returns the RF address of the thermostat with the name, or of the first thermostat in the room
+    /// with the name. If a device and a room have the same name, the device is used.
+    /// `Error::NameNotFound` or `Error::AmbiguousName` is returned, if the name is missing or not unique.
+    pub fn thermostat_by_name(&self, name: &str) -> Result<u32> {
+        thermostat_by_name(&self.rooms, &self.devices, name)
+    }
+
+    /// returns the thermostats in the room
+    pub fn devices_in_room(&self, room_id: u8) -> Vec<&HeaterThermostat> {
+        devices_in_room(&self.devices, room_id)
+    }
+
+    /// returns the room of the thermostat with the RF address
+    pub fn room_of(&self, rf_address: u32) -> Result<&Room> {
+        room_of(&self.rooms, &self.devices, rf_address)
+    }
+
+    /// requests the current status of all devices (`l:`) and updates `devices` and the state of `rooms`.
+    pub async fn refresh(&mut self) -> Result<()> {
+        self.run(session::refresh()).await
+    }
+
+    /// keeps the connection alive. If there was no traffic for the keep alive interval,
+    /// the device status will be refreshed, otherwise nothing is done.
+    /// The cube closes connections without traffic after a few minutes. The application has to call this
+    /// function periodically, more often than the interval, e.g. from its main loop, or run
+    /// `run_keep_alive()` beside its other tasks. `http::serve()` and the MQTT bridge refresh the
+    /// connection by themselves.
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use std::{net::SocketAddr, time::Duration};
+    /// # async fn sleep(_: Duration) {}
+    /// # use eq3_max_cube_rs::MaxCube;
+    /// # futures::executor::block_on(async {
+    /// let mut cube = MaxCube::new(&SocketAddr::from(([172, 22, 51, 191], 62910))).await.unwrap();
+    /// loop {
+    ///     cube.keep_alive().await.unwrap();
+    ///     sleep(Duration::from_secs(10)).await;
+    /// }
+    /// # });
+    /// ```
+    pub async fn keep_alive(&mut self) -> Result<()> {
+        if self.last_activity.elapsed() >= self.keep_alive {
+            debug!("Connection idle, refreshing...");
+            self.refresh().await?;
+        }
+        Ok(())
+    }
+
+    /// keeps the connection of the shared cube alive: the device status is refreshed after every keep alive
+    /// interval without traffic, and the answer is checked. It runs until a refresh fails and returns the
+    /// error, `is_connected()` tells then, if a new connection is needed. The cube is locked during the
+    /// refresh only, so other tasks can use it meanwhile.
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use futures::{future, lock::Mutex};
+    /// # use eq3_max_cube_rs::MaxCube;
+    /// # futures::executor::block_on(async {
+    /// let cube = Mutex::new(MaxCube::new("172.22.51.191:62910").await.unwrap());
+    /// let keep_alive = Box::pin(MaxCube::run_keep_alive(&cube));
+    /// let work = Box::pin(async {
+    ///     cube.lock().await.set_temperature(1763839, 21.0).await.unwrap();
+    /// });
+    /// future::select(keep_alive, work).await;
+    /// # });
+    /// ```
+    pub async fn run_keep_alive(cube: &Mutex<Self>) -> Result<()> {
+        loop {
+            let wait = {
+                let mut cube = cube.lock().await;
+                cube.keep_alive().await?;
+                cube.keep_alive.saturating_sub(cube.last_activity.elapsed())
+            };
+            sleep(wait).await;
+        }
+    }
+
+    /// sets the thermostat with the rf_address to the manual mode and the given temperature.
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use std::net::SocketAddr;
+    /// # use eq3_max_cube_rs::MaxCube;
+    /// # futures::executor::block_on(async {
+    /// let mut cube = MaxCube::new(&SocketAddr::from(([172, 22, 51, 191], 62910))).await.unwrap();
+    /// cube.set_temperature(1763839, 21.0).await.unwrap();
+    /// # });
+    /// ```
+    pub async fn set_temperature(&mut self, rf_address: u32, temperature: f64) -> Result<()> {
+        self.run(session::set_temperature(rf_address, temperature))
+            .await
+    }
+
+    /// sets the thermostat with the rf_address to the mode, e.g. `DeviceMode::Auto` to follow the
+    /// time scheduling again. In the auto mode, the temperature 0 keeps the temperature of the time scheduling.
+    /// The vacation mode is set by `set_vacation()`.
+    pub async fn set_mode(
+        &mut self,
+        rf_address: u32,
+        mode: DeviceMode,
+        temperature: f64,
+    ) -> Result<()> {
+        self.run(session::set_mode(rf_address, mode, temperature, None))
+            .await
+    }
+
+    /// sets the thermostat with the rf_address to the temperature until the end of the vacation.
+    pub async fn set_vacation(
+        &mut self,
+        rf_address: u32,
+        temperature: f64,
+        until: DateTime,
+    ) -> Result<()> {
+        let mode = DeviceMode::Vacation;
+        self.run(session::set_mode(
+            rf_address,
+            mode,
+            temperature,
+            Some(until),
+        ))
+        .await
+    }
+
+    /// requests the configuration of the device, including the time scheduling of the thermostats.
+    pub async fn device_settings(&mut self, rf_address: u32) -> Result<DeviceSettings> {
+        self.run(session::device_settings(rf_address)).await
+    }
+
+    /// sets the time scheduling of the thermostat for one day, 0 = Saturday ... 6 = Friday.
+    /// The last point of the day shall last until 24:00.
+    pub async fn set_program(
+        &mut self,
+        rf_address: u32,
+        day: u8,
+        program: &DayProgram,
+    ) -> Result<()> {
+        self.run(session::set_program(rf_address, day, program))
+            .await
+    }
+
+    /// renames the device and writes the meta data to the cube.
+    pub async fn rename_device(&mut self, rf_address: u32, name: &str) -> Result<()> {
+        self.run(session::rename_device(rf_address, name)).await
+    }
+
+    /// renames the room and writes the meta data to the cube.
+    pub async fn rename_room(&mut self, room_id: u8, name: &str) -> Result<()> {
+        self.run(session::rename_room(room_id, name)).await
+    }
+
+    /// deletes the device from the cube, the device has to be paired again to be used.
+    pub async fn delete_device(&mut self, rf_address: u32) -> Result<()> {
+        self.run(session::delete_device(rf_address)).await
+    }
+
+    /// sets the thermostat or the room with the name to the manual mode and the given temperature.
+    /// If a device and a room have the same name, the device is used.
+    pub async fn set_temperature_by_name(&mut self, name: &str, temperature: f64) -> Result<()> {
+        self.run(session::thermostat_named(name, |rf_address| {
+            session::set_temperature(rf_address, temperature)
+        }))
+        .await
+    }
+
+    /// sets the thermostat or the room with the name to the mode, see `set_mode()`.
+    pub async fn set_mode_by_name(
+        &mut self,
+        name: &str,
+        mode: DeviceMode,
+        temperature: f64,
+    ) -> Result<()> {
+        self.run(session::thermostat_named(name, |rf_address| {
+            session::set_mode(rf_address, mode, temperature, None)
+        }))
+        .await
+    }
+
+    /// sets the thermostat or the room with the name to the temperature until the end of the vacation.
+    pub async fn set_vacation_by_name(
+        &mut self,
+        name: &str,
+        temperature: f64,
+        until: DateTime,
+    ) -> Result<()> {
+        self.run(session::thermostat_named(name, |rf_address| {
+            session::set_mode(rf_address, DeviceMode::Vacation, temperature, Some(until))
+        }))
+        .await
+    }
+
+    /// requests the configuration of the thermostat or of the first thermostat in the room with the name.
+    pub async fn device_settings_by_name(&mut self, name: &str) -> Result<DeviceSettings> {
+        self.run(session::thermostat_named(name, session::device_settings))
+            .await
+    }
+
+    /// sets the time scheduling of the thermostat or the room with the name for one day.
+    pub async fn set_program_by_name(
+        &mut self,
+        name: &str,
+        day: u8,
+        program: &DayProgram,
+    ) -> Result<()> {
+        self.run(session::thermostat_named(name, |rf_address| {
+            session::set_program(rf_address, day, program)
+        }))
+        .await
+    }
+
+    /// renames the device with the name.
+    pub async fn rename_device_by_name(&mut self, name: &str, new_name: &str) -> Result<()> {
+        self.run(session::device_named(name, |rf_address| {
+            session::rename_device(rf_address, new_name)
+        }))
+        .await
+    }
+
+    /// renames the room with the name.
+    pub async fn rename_room_by_name(&mut self, name: &str, new_name: &str) -> Result<()> {
+        self.run(session::room_named(name, |room_id| {
+            session::rename_room(room_id, new_name)
+        }))
+        .await
+    }
+
+    /// deletes the device with the name from the cube.
+    pub async fn delete_device_by_name(&mut self, name: &str) -> Result<()> {
+        self.run(session::device_named(name, session::delete_device))
+            .await
+    }
+
+    /// returns the backup of the configuration: the rooms, the devices with their names and link partners,
+    /// and the settings and time scheduling of the heater thermostats, which are requested from the cube.
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use eq3_max_cube_rs::MaxCube;
+    /// # futures::executor::block_on(async {
+    /// let mut cube = MaxCube::new("172.22.51.191:62910").await.unwrap();
+    /// let backup = cube.export_backup().await.unwrap();
+    /// println!("{:?}", backup);
+    /// # });
+    /// ```
+    pub async fn export_backup(&mut self) -> Result<Backup> {
+        self.run(session::export_backup()).await
+    }
+
+    /// re-creates the configuration of the backup, e.g. on a reset cube: the meta data with the rooms and
+    /// names, the groups and link partners of the devices, and the settings and time scheduling of the
+    /// heater thermostats. The devices shall be paired with the cube before, e.g. by `pair()`.
+    /// Stops with `Error::BackupIncomplete` and the devices left, when the duty cycle of the cube reaches
+    /// `DUTY_CYCLE_LIMIT` or the cube refuses a command for it. The import can be run again, after the
+    /// duty cycle has gone down, e.g. after an hour.
+    pub async fn import_backup(&mut self, backup: &Backup) -> Result<()> {
+        self.run(session::import_backup(backup)?).await
+    }
+
+    /// compares the desired state with the configuration of the cube, and returns the changes.
+    /// The settings and time scheduling of the heater thermostats are requested from the cube.
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use eq3_max_cube_rs::{plan::{DesiredRoom, DesiredState}, MaxCube};
+    /// # futures::executor::block_on(async {
+    /// let mut cube = MaxCube::new("172.22.51.191:62910").await.unwrap();
+    /// let desired = DesiredState {
+    ///     rooms: vec![DesiredRoom {
+    ///         id: 1,
+    ///         name: "Bedroom".to_string(),
+    ///         comfort_temperature: Some(21.0),
+    ///         ..Default::default()
+    ///     }],
+    /// };
+    /// let plan = cube.plan(&desired).await.unwrap();
+    /// print!("{}", plan);
+    /// cube.apply(&plan).await.unwrap();
+    /// # });
+    /// ```
+    pub async fn plan(&mut self, desired: &DesiredState) -> Result<Plan> {
+        self.run(session::plan(desired)).await
+    }
+
+    /// sends the commands of the plan: the meta data first, if rooms or devices are changed, then the
+    /// commands to the devices. Stops with `Error::DutyCycle` before a command, when the duty cycle of the
+    /// cube reaches `DUTY_CYCLE_LIMIT`, or at the first command, which is refused by the cube.
+    /// The remaining changes can be applied later by a new plan.
+    pub async fn apply(&mut self, plan: &Plan) -> Result<()> {
+        self.run(session::apply(plan)).await
+    }
+
+    /// starts pairing of new devices and waits for the timeout, a new device is added to `devices`.
+    /// `Ok(())` is returned as well, if the cube stays silent, as no device was found.
+    pub async fn pair(&mut self, timeout: Duration) -> Result<()> {
+        self.send(&session::pairing(timeout)).await?;
+
+        // response shall be only read after timeout / or aborted
+        sleep(timeout).await;
+
+        let command_timeout = self.command_timeout;
+        let received = match with_timeout(command_timeout, "Pairing", self.read_line()).await {
+            Ok(received) => received,
+            Err(Error::Timeout { .. }) => {
+                // the cube may stay silent, if no device was found
+                info!("No new device found.");
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+
+        self.state().paired(&received);
+        Ok(())
+    }
+
+    /// closes the connection gracefully.
+    /// The cube is informed by a `q:` message and accepts the next client immediately,
+    /// instead of waiting for the timeout of the dropped connection.
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use eq3_max_cube_rs::MaxCube;
+    /// # futures::executor::block_on(async {
+    /// let cube = MaxCube::new("172.22.51.191:62910").await.unwrap();
+    /// cube.close().await.unwrap();
+    /// # });
+    /// ```
+    pub async fn close(mut self) -> Result<()> {
+        if self.connected {
+            self.send("q:\r\n").await?;
+        }
+        self.connected = false;
+        self.writer.close().await?;
+        Ok(())
+    }
+
+    /// runs the operation, its requests are sent one after another
+    async fn run<O: Operation>(&mut self, mut operation: O) -> Result<O::Output> {
+        let mut answer = None;
+        loop {
+            match operation.step(&mut self.state(), answer.take())? {
+                Step::Request(request) => answer = Some(self.request(&request).await?),
+                Step::Done(output) => return Ok(output),
+            }
+        }
+    }
+
+    /// returns the data of the cube, which is read and updated by the operations
+    fn state(&mut self) -> State<'_> {
+        State {
+            status: &mut self.status,
+            rooms: &mut self.rooms,
+            devices: &mut self.devices,
+            history: self.history.as_ref(),
+        }
+    }
+
+    /// sends the request to the cube and waits for the answer.
+    /// Other messages received in the meantime are ignored.
+    /// After a timeout, the connection is not used anymore, as the answer may still arrive.
+    async fn request(&mut self, request: &Request) -> Result<String> {
+        let command_timeout = self.command_timeout;
+        let result = with_timeout(command_timeout, "Command", async {
+            self.send(&request.payload).await?;
+            loop {
+                let received = self.read_line().await?;
+                if request.is_answer(&received, &mut self.status) {
+                    return Ok(received);
+                }
+            }
+        })
+        .await;
+
+        if let Err(Error::Timeout { .. }) = result {
+            self.connected = false;
+        }
+        result
+    }
+
+    /// sends the payload to the cube, `Error::NotConnected` is returned, if the connection is not usable
+    async fn send(&mut self, payload: &str) -> Result<()> {
+        if !self.connected {
+            return Err(Error::NotConnected);
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Sent, payload);
+        }
+
+        let result = async {
+            self.writer.write_all(payload.as_bytes()).await?;
+            self.writer.flush().await
+        }
+        .await;
+
+        if result.is_err() {
+            self.connected = false;
+        }
+        Ok(result?)
+    }
+
+    /// reads one line from the cube, without the trailing line break
+    async fn read_line(&mut self) -> Result<String> {
+        let mut received = String::new();
+        let result = self.reader.read_line(&mut received).await;
+
+        match result {
+            Ok(0) => {
+                self.connected = false;
+                Err(Error::ConnectionClosed)
+            }
+            Ok(_) => {
+                self.connected = true;
+                self.last_activity = Instant::now();
+                let received = received.replace("\r\n", "");
+                debug!("{:?}", received);
+                if let Some(recorder) = &self.recorder {
+                    recorder.record(Direction::Received, &received);
+                }
+                Ok(received)
+            }
+            Err(e) => {
+                self.connected = false;
+                Err(e.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::history::HistoryFormat;
+    use crate::messages::test::{handshake, trace, MESSAGE_H, MESSAGE_L, MESSAGE_M};
+    use crate::messages::{to_message_c, Device, ThermostatSettings};
+    use crate::plan::DesiredRoom;
+    use crate::trace::{read_trace, Replay};
+    use crate::transport::test::Script;
+
+    #[test]
+    fn test_handshake() {
+        let (script, _) = Script::new(handshake());
+        let cube = rt::block_on(MaxCube::from_transport(script)).unwrap();
+
+        assert_eq!(cube.rooms.len(), 5);
+        assert_eq!(cube.devices.len(), 5);
+        assert!(cube.is_connected());
+        assert_eq!(cube.status.serial, "KEQ0523864");
+        assert_eq!(cube.status.free_memory_slots, 50);
+        match &cube.devices[2] {
+            Device::HeaterThermostat(ts) => {
+                assert_eq!(ts.name, "Kitchen");
+                assert_eq!(ts.temperature_set, 19.0);
+            }
+            _ => panic!("Wrong device type!"),
+        }
+
+        // the connection is closed before the L-Message
+        let (script, _) = Script::new(format!("{}\r\n{}\r\n", MESSAGE_H, MESSAGE_M));
+        assert!(matches!(
+            rt::block_on(MaxCube::from_transport(script)),
+            Err(Error::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn test_timeout() {
+        // the answer arrives after the command timeout
+        let session = trace(&format!("0.000 > l:\n0.300 < {}\n", MESSAGE_L));
+        let replay = Replay::new(read_trace(session.as_bytes()).unwrap()).set_realtime(true);
+        rt::block_on(async {
+            let mut cube = MaxCubeBuilder::new()
+                .set_command_timeout(Duration::from_millis(100))
+                .with_transport(replay)
+                .await
+                .unwrap();
+            assert!(matches!(
+                cube.refresh().await,
+                Err(Error::Timeout {
+                    operation: "Command",
+                    ..
+                })
+            ));
+            assert!(!cube.is_connected());
+
+            // the late answer is not taken for the answer of the next request
+            rt::sleep(Duration::from_millis(300)).await;
+            assert!(matches!(cube.refresh().await, Err(Error::NotConnected)));
+            cube.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_run_keep_alive() {
+        // the cube answers the first refresh only
+        let session = trace(&format!("0.050 > l:\n0.060 < {}\n", MESSAGE_L));
+        let replay = Replay::new(read_trace(session.as_bytes()).unwrap()).set_realtime(true);
+        rt::block_on(async {
+            let cube = MaxCubeBuilder::new()
+                .set_keep_alive(Duration::from_millis(50))
+                .with_transport(replay)
+                .await
+                .unwrap();
+            let cube = Mutex::new(cube);
+            let start = Instant::now();
+            let result = MaxCube::run_keep_alive(&cube).await;
+            assert!(matches!(result, Err(Error::Io(_))), "{:?}", result);
+            assert!(start.elapsed() >= Duration::from_millis(100));
+            assert!(!cube.lock().await.is_connected());
+        });
+    }
+
+    #[test]
+    fn test_set_temperature() {
+        let (script, output) = Script::new(format!("{}S:00,0,31\r\nS:64,1,0\r\n", handshake()));
+        rt::block_on(async {
+            let mut cube = MaxCube::from_transport(script).await.unwrap();
+            cube.set_temperature(1762771, 23.0).await.unwrap();
+            assert_eq!(cube.status.free_memory_slots, 49);
+            assert!(matches!(
+                cube.set_temperature(1762771, 23.0).await,
+                Err(Error::DutyCycle {
+                    rf_address: 1762771,
+                    duty_cycle: 100
+                })
+            ));
+            assert_eq!(
+                (cube.status.duty_cycle, cube.status.free_memory_slots),
+                (100, 0)
+            );
+            assert!(matches!(
+                cube.set_temperature(1, 23.0).await,
+                Err(Error::DeviceNotFound(1))
+            ));
+            cube.close().await.unwrap();
+        });
+
+        assert_eq!(
+            String::from_utf8(output.lock().unwrap().clone()).unwrap(),
+            "s:AARAAAAAGuXTAW4=\r\ns:AARAAAAAGuXTAW4=\r\nq:\r\n"
+        );
+    }
+
+    #[test]
+    fn test_pair() {
+        let (script, output) = Script::new(format!("{}N:ARrl009FUTIxMjE2NDQA\r\n", handshake()));
+        rt::block_on(async {
+            let mut cube = MaxCube::from_transport(script).await.unwrap();
+            cube.pair(Duration::from_millis(10)).await.unwrap();
+            assert_eq!(cube.devices.len(), 6);
+        });
+
+        assert_eq!(
+            String::from_utf8(output.lock().unwrap().clone()).unwrap(),
+            "n:0000\r\n"
+        );
+    }
+
+    #[test]
+    fn test_meta_data() {
+        let (script, output) = Script::new(format!("{}A:\r\nA:\r\n", handshake()));
+        rt::block_on(async {
+            let mut cube = MaxCube::from_transport(script).await.unwrap();
+            cube.rename_room(3, "Küche").await.unwrap();
+            assert_eq!(cube.rooms[2].name, "Küche");
+            assert!(matches!(
+                cube.rename_room(9, "Attic").await,
+                Err(Error::RoomNotFound(9))
+            ));
+
+            cube.delete_device(1763118).await.unwrap();
+            assert_eq!(cube.devices.len(), 4);
+            assert!(matches!(
+                cube.delete_device(1763118).await,
+                Err(Error::DeviceNotFound(1763118))
+            ));
+        });
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("m:00,"));
+        assert!(output.ends_with("\r\nt:01,1,Gucu\r\n"));
+    }
+
+    #[test]
+    fn test_history() {
+        let path = std::env::temp_dir().join(format!("maxcube-history-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let history = HistoryLogger::append(HistoryFormat::Csv, &path).unwrap();
+
+        let (script, _) = Script::new(format!("{}{}\r\n", handshake(), MESSAGE_L));
+        rt::block_on(async {
+            let mut cube = MaxCubeBuilder::new()
+                .set_history(history)
+                .with_transport(script)
+                .await
+                .unwrap();
+            cube.refresh().await.unwrap();
+        });
+
+        // the header and 5 thermostats after the handshake and the refresh
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(text.lines().count(), 11);
+        assert!(text.starts_with("timestamp,rf_address,"));
+    }
+
+    #[test]
+    fn test_backup() {
+        let addresses = [0x1ae5d3, 0x1aea01, 0x1ae72e, 0x1ae9ff, 0x1ae518];
+        let settings = addresses
+            .iter()
+            .map(|rf_address| {
+                to_message_c(&DeviceSettings {
+                    rf_address: *rf_address,
+                    device_type: 1,
+                    thermostat: Some(ThermostatSettings::default()),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        // group, 2 settings and 7 days for every thermostat, the second import reaches the duty cycle limit
+        // after the first command
+        let (script, output) = Script::new(format!(
+            "{}{}\r\nA:\r\n{}{}\r\nA:\r\nS:5a,0,31\r\n",
+            handshake(),
+            settings.join("\r\n"),
+            "S:00,0,31\r\n".repeat(50),
+            MESSAGE_L
+        ));
+        rt::block_on(async {
+            let mut cube = MaxCube::from_transport(script).await.unwrap();
+            let backup = cube.export_backup().await.unwrap();
+            assert_eq!(backup.serial, "KEQ0523864");
+            assert_eq!(backup.devices.len(), 5);
+            assert!(backup.devices.iter().all(|e| e.thermostat.is_some()));
+
+            cube.import_backup(&backup).await.unwrap();
+            assert_eq!(cube.rooms.len(), 5);
+            assert_eq!(cube.rooms[0].temperature_set, 20.0);
+
+            let mut old = backup.clone();
+            old.version = 0;
+            assert!(matches!(
+                cube.import_backup(&old).await,
+                Err(Error::BackupVersion(0))
+            ));
+
+            match cube.import_backup(&backup).await {
+                Err(Error::BackupIncomplete {
+                    duty_cycle,
+                    remaining,
+                }) => {
+                    assert_eq!(duty_cycle, 90);
+                    assert_eq!(remaining, addresses);
+                }
+                result => panic!("Unexpected result {:?}", result),
+            }
+        });
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("c:1ae5d3\r\nc:1aea01\r\n"));
+        assert!(output.contains("\r\nm:00,"));
+        assert_eq!(output.matches("s:").count(), 51);
+        assert!(output.contains("\r\nl:\r\n"));
+    }
+
+    #[test]
+    fn test_plan() {
+        let settings = [0x1ae5d3, 0x1aea01, 0x1ae72e, 0x1ae9ff, 0x1ae518]
+            .iter()
+            .map(|rf_address| {
+                to_message_c(&DeviceSettings {
+                    rf_address: *rf_address,
+                    device_type: 1,
+                    thermostat: Some(ThermostatSettings::default()),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        // the duty cycle reaches the limit after the first command to a device
+        let (script, output) = Script::new(format!(
+            "{}{}\r\nA:\r\nS:5a,0,31\r\n",
+            handshake(),
+            settings.join("\r\n"),
+        ));
+        rt::block_on(async {
+            let mut cube = MaxCube::from_transport(script).await.unwrap();
+            let desired = DesiredState {
+                rooms: vec![DesiredRoom {
+                    id: 1,
+                    name: "Sleeping room".to_string(),
+                    comfort_temperature: Some(22.0),
+                    schedule: [(
+                        "monday".to_string(),
+                        vec!["06:00=17".to_string(), "24:00=21".to_string()],
+                    )]
+                    .into(),
+                    ..Default::default()
+                }],
+            };
+            let plan = cube.plan(&desired).await.unwrap();
+            assert_eq!(plan.changes.len(), 3);
+
+            assert!(matches!(
+                cube.apply(&plan).await,
+                Err(Error::DutyCycle {
+                    rf_address: 0x1ae5d3,
+                    duty_cycle: 90
+                })
+            ));
+            assert_eq!(cube.rooms[0].name, "Sleeping room");
+        });
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("\r\nm:00,"));
+        assert_eq!(output.matches("s:").count(), 1);
+    }
+
+    #[test]
+    fn test_by_name() {
+        let (script, output) = Script::new(format!("{}S:00,0,31\r\n", handshake()));
+        rt::block_on(async {
+            let mut cube = MaxCube::from_transport(script).await.unwrap();
+            assert_eq!(cube.room_of(1762771).unwrap().name, "Bedroom");
+            cube.set_temperature_by_name("Bedroom", 23.0).await.unwrap();
+            assert!(matches!(
+                cube.set_temperature_by_name("Attic", 23.0).await,
+                Err(Error::NameNotFound(_))
+            ));
+        });
+
+        assert_eq!(
+            String::from_utf8(output.lock().unwrap().clone()).unwrap(),
+            "s:AARAAAAAGuXTAW4=\r\n"
+        );
+    }
+}
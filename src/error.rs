@@ -0,0 +1,113 @@
+use std::{io, time::Duration};
+
+use thiserror::Error;
+
+/// Errors returned by all operations of this crate
+#[derive(Debug, Error)]
+pub enum Error {
+    /// the connection to the cube failed
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    /// the cube closed the connection
+    #[error("Connection closed by cube.")]
+    ConnectionClosed,
+
+    /// the connection is closed, lost or out of sync after a timeout, a new connection is needed
+    #[error("Not connected to the cube.")]
+    NotConnected,
+
+    /// the cube did not answer in time
+    #[error("{operation} timed out after {duration:?}.")]
+    Timeout {
+        /// the operation, which timed out
+        operation: &'static str,
+        /// the configured timeout
+        duration: Duration,
+    },
+
+    /// a message from the cube could not be decoded
+    #[error("{reason} Message: `{message}`")]
+    Protocol {
+        /// the description of the failure
+        reason: String,
+        /// the offending message
+        message: String,
+    },
+
+    /// no cube with the serial number answered the discovery
+    #[error("Cube with serial number {0} not found.")]
+    CubeNotFound(String),
+
+    /// no device with the RF address is known
+    #[error("Device with RF address {0} not found.")]
+    DeviceNotFound(u32),
+
+    /// no room with the room id is known
+    #[error("Room with id {0} not found.")]
+    RoomNotFound(u8),
+
+    /// no device or room with the name is known
+    #[error("No device or room named {0:?} found.")]
+    NameNotFound(String),
+
+    /// more than one device or room has the name
+    #[error("The name {0:?} is not unique.")]
+    AmbiguousName(String),
+
+    /// no device with the serial number is known
+    #[error("No device with serial number {0:?} found.")]
+    DeviceSerialNotFound(String),
+
+    /// the date or time is out of range
+    #[error("Invalid date {0}.")]
+    InvalidDate(String),
+
+    /// the time scheduling could not be parsed
+    #[error("{0}")]
+    InvalidProgram(String),
+
+    /// the device does not support the operation
+    #[error("Device with RF address {0} not supported.")]
+    DeviceNotSupported(u32),
+
+    /// the MQTT broker refused the connection
+    #[cfg(feature = "mqtt")]
+    #[error("MQTT broker refused the connection, return code {0}.")]
+    BrokerRefused(u8),
+
+    /// the backup was written by another version
+    #[error("Backup version {0} is not supported.")]
+    BackupVersion(u32),
+
+    /// the import of the backup stopped, as the duty cycle limit is reached
+    #[error("Backup import stopped at duty cycle {duty_cycle}%, devices left: {remaining:06x?}.")]
+    BackupIncomplete {
+        /// the duty cycle reported by the cube, in percent
+        duty_cycle: u8,
+        /// RF addresses of the devices, whose settings are not imported
+        remaining: Vec<u32>,
+    },
+
+    /// the cube refused the command, as the duty cycle limit is reached
+    #[error("Command for device with RF address {rf_address} refused, duty cycle: {duty_cycle}%.")]
+    DutyCycle {
+        /// RF address of the device, the command was sent to
+        rf_address: u32,
+        /// the duty cycle reported by the cube, in percent
+        duty_cycle: u8,
+    },
+}
+
+impl Error {
+    /// returns a protocol error for the offending message
+    pub(crate) fn protocol(reason: impl Into<String>, message: &str) -> Self {
+        Self::Protocol {
+            reason: reason.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Result type of this crate
+pub type Result<T> = std::result::Result<T, Error>;
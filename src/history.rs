@@ -0,0 +1,300 @@
+//! History of the heater thermostats, e.g. to analyse the heating behaviour over a winter.
+//! A `HistoryLogger` writes a snapshot of every heater thermostat after each refresh of the device
+//! status (L-Message), as InfluxDB line protocol or as CSV.
+//!
+//! ```text
+//! maxcube_thermostat,rf_address=1ae5d3,serial=OEQ2121644,name=Bedroom,room=Bedroom mode="Manual",temperature_set=20,temperature_measured=20.5,valve_position=0i,battery_low=false,error=false 1700000000000000000
+//! ```
+//!
+//! ```text
+//! timestamp,rf_address,serial,name,room,mode,temperature_set,temperature_measured,valve_position,battery_low,error
+//! 2023-11-14T22:13:20.000Z,1ae5d3,OEQ2121644,Bedroom,Bedroom,Manual,20,20.5,0,false,false
+//! ```
+//!
+//! The timestamps are UTC, nanoseconds since the Unix epoch in the line protocol and RFC 3339 in CSV.
+//! The measured temperature is left out (empty in CSV), as long as the thermostat has not reported one.
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::warn;
+
+use crate::error::Result;
+use crate::messages::{Device, Devices, Rooms};
+
+/// columns of the CSV format
+const CSV_HEADER: &str = "timestamp,rf_address,serial,name,room,mode,temperature_set,temperature_measured,valve_position,battery_low,error";
+
+/// output format of the history
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryFormat {
+    /// InfluxDB line protocol, measurement `maxcube_thermostat`
+    #[default]
+    Influx,
+    /// comma separated values with a header line
+    Csv,
+}
+
+/// the writer with the state of the CSV header
+struct Output {
+    writer: Box<dyn Write + Send>,
+    header: bool,
+}
+
+/// HistoryLogger writes the snapshots of the heater thermostats.
+/// It can be cloned, all clones write to the same output.
+/// The cube writes to it after every refresh, when it is set by `MaxCubeBuilder::set_history()`.
+/// # Examples
+///
+/// ```no_run
+/// # use eq3_max_cube_rs::history::{HistoryFormat, HistoryLogger};
+/// let csv = HistoryLogger::append(HistoryFormat::Csv, "heating.csv").unwrap();
+/// let influx = HistoryLogger::new(HistoryFormat::Influx, std::io::stdout());
+/// ```
+#[derive(Clone)]
+pub struct HistoryLogger {
+    format: HistoryFormat,
+    output: Arc<Mutex<Output>>,
+}
+
+impl fmt::Debug for HistoryLogger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HistoryLogger")
+            .field("format", &self.format)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HistoryLogger {
+    /// returns a logger, which writes the history to the writer, e.g. `std::io::stdout()`
+    pub fn new<W: Write + Send + 'static>(format: HistoryFormat, writer: W) -> Self {
+        Self {
+            format,
+            output: Arc::new(Mutex::new(Output {
+                writer: Box::new(writer),
+                header: false,
+            })),
+        }
+    }
+
+    /// returns a logger, which appends the history to the file. The file is created if it does not exist,
+    /// the CSV header is only written to an empty file.
+    pub fn append<P: AsRef<Path>>(format: HistoryFormat, path: P) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let header = file.metadata()?.len() > 0;
+        let logger = Self::new(format, file);
+        if let Ok(mut output) = logger.output.lock() {
+            output.header = header;
+        }
+        Ok(logger)
+    }
+
+    /// returns the format of the history
+    pub fn format(&self) -> HistoryFormat {
+        self.format
+    }
+
+    /// writes a snapshot of all heater thermostats with the current time. Failures are logged only,
+    /// the session shall not be interrupted by the history.
+    pub fn log(&self, rooms: &Rooms, devices: &Devices) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        if let Err(e) = self.write(timestamp, rooms, devices) {
+            warn!("Writing the history failed: {}", e);
+        }
+    }
+
+    /// writes a snapshot of all heater thermostats with the time since the Unix epoch
+    fn write(&self, timestamp: Duration, rooms: &Rooms, devices: &Devices) -> io::Result<()> {
+        let mut output = match self.output.lock() {
+            Ok(output) => output,
+            Err(e) => e.into_inner(),
+        };
+        if self.format == HistoryFormat::Csv && !output.header {
+            writeln!(output.writer, "{}", CSV_HEADER)?;
+            output.header = true;
+        }
+
+        for dev in devices {
+            let Device::HeaterThermostat(ts) = dev else {
+                continue;
+            };
+            let room = rooms
+                .iter()
+                .find(|e| e.room_id == ts.room_id)
+                .map(|e| e.name.as_str())
+                .unwrap_or_default();
+            let measured = Some(ts.temperature_measured).filter(|e| *e > 0.0);
+
+            match self.format {
+                HistoryFormat::Influx => {
+                    let mut line = format!(
+                        "maxcube_thermostat,rf_address={:06x},serial={},name={},room={} mode=\"{:?}\",temperature_set={}",
+                        ts.rf_address,
+                        escape_tag(&ts.serial),
+                        escape_tag(&ts.name),
+                        escape_tag(room),
+                        ts.mode,
+                        ts.temperature_set
+                    );
+                    if let Some(measured) = measured {
+                        line.push_str(&format!(",temperature_measured={}", measured));
+                    }
+                    writeln!(
+                        output.writer,
+                        "{},valve_position={}i,battery_low={},error={} {}",
+                        line,
+                        ts.valve_position,
+                        ts.battery_low,
+                        ts.error,
+                        timestamp.as_nanos()
+                    )?;
+                }
+                HistoryFormat::Csv => {
+                    writeln!(
+                        output.writer,
+                        "{},{:06x},{},{},{},{:?},{},{},{},{},{}",
+                        rfc3339(timestamp),
+                        ts.rf_address,
+                        escape_csv(&ts.serial),
+                        escape_csv(&ts.name),
+                        escape_csv(room),
+                        ts.mode,
+                        ts.temperature_set,
+                        measured.map(|e| e.to_string()).unwrap_or_default(),
+                        ts.valve_position,
+                        ts.battery_low,
+                        ts.error
+                    )?;
+                }
+            }
+        }
+        output.writer.flush()
+    }
+}
+
+/// escapes the tag value of the line protocol, an empty value is not allowed
+fn escape_tag(value: &str) -> String {
+    if value.is_empty() {
+        return "-".to_string();
+    }
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+        .replace('\n', "\\n")
+}
+
+/// quotes the CSV field, if it contains a separator, a quote or a line break
+fn escape_csv(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// formats the time since the Unix epoch as RFC 3339 in UTC, with milliseconds
+fn rfc3339(timestamp: Duration) -> String {
+    let secs = timestamp.as_secs();
+    // civil date from the days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        timestamp.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::test::house;
+
+    /// writer, which can be read by the test after the logger took it
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_influx() {
+        let (rooms, devices) = house();
+        let shared = Shared::default();
+        let logger = HistoryLogger::new(HistoryFormat::Influx, shared.clone());
+        logger
+            .write(Duration::from_secs(1700000000), &rooms, &devices)
+            .unwrap();
+
+        let text = shared.text();
+        assert_eq!(text.lines().count(), 5);
+        assert!(text.contains("maxcube_thermostat,rf_address=1ae72e,serial=OEQ2121476,name=Kitchen,room=Kitchen mode=\"Manual\",temperature_set=19,temperature_measured=20.5,valve_position=0i,battery_low=false,error=false 1700000000000000000\n"));
+        assert!(text.contains(",name=Living\\ room,room=Living\\ room "));
+    }
+
+    #[test]
+    fn test_csv() {
+        let (rooms, devices) = house();
+        let shared = Shared::default();
+        let logger = HistoryLogger::new(HistoryFormat::Csv, shared.clone());
+        let timestamp = Duration::from_millis(1700000000123);
+        logger.write(timestamp, &rooms, &devices).unwrap();
+        logger.write(timestamp, &rooms, &devices).unwrap();
+
+        let text = shared.text();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines.contains(
+            &"2023-11-14T22:13:20.123Z,1ae72e,OEQ2121476,Kitchen,Kitchen,Manual,19,20.5,0,false,false"
+        ));
+        assert_eq!(escape_csv("a, \"b\""), "\"a, \"\"b\"\"\"");
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(Duration::ZERO), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            rfc3339(Duration::from_secs(951782400)),
+            "2000-02-29T00:00:00.000Z"
+        );
+        assert_eq!(
+            rfc3339(Duration::from_secs(4102444799)),
+            "2099-12-31T23:59:59.000Z"
+        );
+    }
+}
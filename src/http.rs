@@ -0,0 +1,498 @@
+//! Small REST/JSON API, which shares one connection to the cube with several clients.
+//! The cube accepts only one client at a time, so the services shall use this API instead of
+//! connecting to the cube themselves. It is available with the feature `http`.
+//!
+//! | Method | Path                         | Body                                          |
+//! |--------|------------------------------|-----------------------------------------------|
+//! | GET    | `/rooms`                     |                                               |
+//! | GET    | `/devices`                   |                                               |
+//! | GET    | `/devices/{addr}`            |                                               |
+//! | PUT    | `/devices/{addr}/temperature`| `{"temperature": 21.5}`                       |
+//! | PUT    | `/rooms/{id}/mode`           | `{"mode": "Manual", "temperature": 21.5}`     |
+//! | POST   | `/pair`                      | `{"timeout": 60}` (optional)                  |
+//! | GET    | `/metrics`                   | with the feature `metrics`                    |
+//!
+//! The RF address `addr` is decimal, as in the JSON of the devices, or hex with the prefix `0x`.
+//! The mode is one of `Auto`, `Manual`, `Boost` or `Vacation`, the vacation requires `until`, e.g.
+//! `{"year": 2024, "month": 1, "day": 6, "hour": 18, "minute": 0}`. Without `temperature`, the
+//! current set point of the room is kept.
+//! The requests are answered one after another, `POST /pair` blocks the API until the timeout.
+
+use std::io;
+use std::time::Duration;
+
+use futures::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::cube::MaxCube;
+use crate::error::{Error, Result};
+use crate::messages::{DateTime, DeviceMode};
+use crate::rt::{self, ToSocketAddrs};
+use crate::transport::Transport;
+use crate::{DEFAULT_COMMAND_TIMEOUT, DEFAULT_KEEP_ALIVE};
+
+/// maximum size of the request body
+const MAX_BODY_SIZE: usize = 64 * 1024;
+/// default time to wait for new devices by `POST /pair`, in seconds
+const DEFAULT_PAIR_TIMEOUT: u64 = 60;
+
+/// body of `PUT /devices/{addr}/temperature`
+#[derive(Debug, Deserialize)]
+struct TemperatureRequest {
+    temperature: f64,
+}
+
+/// body of `PUT /rooms/{id}/mode`
+#[derive(Debug, Deserialize)]
+struct ModeRequest {
+    mode: DeviceMode,
+    temperature: Option<f64>,
+    until: Option<DateTime>,
+}
+
+/// body of `POST /pair`
+#[derive(Debug, Default, Deserialize)]
+struct PairRequest {
+    timeout: Option<u64>,
+}
+
+/// HTTP request, only the parts used by the API
+#[derive(Debug, PartialEq)]
+struct Request {
+    method: String,
+    path: String,
+    body: String,
+}
+
+/// HTTP response, the body is JSON except for the metrics
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json<S: Serialize + ?Sized>(value: &S) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Self {
+                status: 200,
+                content_type: "application/json",
+                body,
+            },
+            Err(e) => Self::error(500, &e.to_string()),
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    fn text(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body,
+        }
+    }
+
+    fn no_content() -> Self {
+        Self {
+            status: 204,
+            content_type: "application/json",
+            body: String::new(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::json!({ "error": message }).to_string(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            204 => "No Content",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+impl From<Error> for Response {
+    fn from(e: Error) -> Self {
+        let status = match &e {
+            Error::DeviceNotFound(_) | Error::RoomNotFound(_) => 404,
+            Error::DeviceNotSupported(_) => 400,
+            Error::DutyCycle { .. } => 503,
+            Error::Timeout { .. } => 504,
+            Error::Io(_)
+            | Error::ConnectionClosed
+            | Error::NotConnected
+            | Error::Protocol { .. } => 502,
+            _ => 500,
+        };
+        Self::error(status, &e.to_string())
+    }
+}
+
+/// listens on the address and answers the requests one after another with the cube.
+/// While no request arrives, the connection to the cube is kept alive by `keep_alive()`.
+/// Returns, if the connection to the cube is lost, so the caller can reconnect.
+/// Failed clients are logged, they do not stop the server.
+/// # Examples
+///
+/// ```no_run
+/// # use eq3_max_cube_rs::{http, MaxCube};
+/// # futures::executor::block_on(async {
+/// let mut cube = MaxCube::new("172.22.51.191:62910").await.unwrap();
+/// http::serve(&mut cube, "0.0.0.0:8080").await.unwrap();
+/// # });
+/// ```
+pub async fn serve<T: Transport, A: ToSocketAddrs>(cube: &mut MaxCube<T>, addr: A) -> Result<()> {
+    let listener = rt::listen(addr).await?;
+    while cube.is_connected() {
+        match rt::timeout(DEFAULT_KEEP_ALIVE, rt::accept(&listener)).await {
+            Some(Ok((stream, addr))) => {
+                debug!("Client {} connected.", addr);
+                if let Err(e) = handle(cube, stream).await {
+                    info!("Client {} failed: {}", addr, e);
+                }
+            }
+            // e.g. a client, which aborted the connection before it was accepted
+            Some(Err(e)) => warn!("Accepting a client failed: {}", e),
+            None => cube.keep_alive().await?,
+        }
+    }
+    Err(Error::ConnectionClosed)
+}
+
+/// answers one request of the client and closes the connection
+pub async fn handle<T: Transport, S: Transport>(cube: &mut MaxCube<T>, stream: S) -> Result<()> {
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader);
+
+    let response = match rt::timeout(DEFAULT_COMMAND_TIMEOUT, read_request(&mut reader)).await {
+        Some(Ok(request)) => {
+            debug!("{} {}", request.method, request.path);
+            respond(cube, &request).await
+        }
+        Some(Err(e)) => Response::error(400, &e.to_string()),
+        None => {
+            return Err(Error::Timeout {
+                operation: "HTTP request",
+                duration: DEFAULT_COMMAND_TIMEOUT,
+            })
+        }
+    };
+    write_response(&mut writer, &response).await
+}
+
+/// reads the request line, the headers and the body with the announced length
+async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Request> {
+    let invalid = |reason: &str| Error::Io(io::Error::new(io::ErrorKind::InvalidData, reason));
+
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(invalid("Malformed request line.")),
+    };
+
+    let mut length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(invalid("Unexpected end of request."));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("Invalid Content-Length."))?;
+            }
+        }
+    }
+    if length > MAX_BODY_SIZE {
+        return Err(invalid("Request body too large."));
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    let body = String::from_utf8(body).map_err(|_| invalid("Request body is not UTF-8."))?;
+
+    Ok(Request { method, path, body })
+}
+
+/// executes the request with the cube
+async fn respond<T: Transport>(cube: &mut MaxCube<T>, request: &Request) -> Response {
+    route(cube, request).await.unwrap_or_else(|e| e)
+}
+
+/// executes the request, failures are returned as error response
+async fn route<T: Transport>(
+    cube: &mut MaxCube<T>,
+    request: &Request,
+) -> std::result::Result<Response, Response> {
+    let path = request.path.split('?').next().unwrap_or_default();
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["rooms"]) => {
+            cube.refresh().await?;
+            Ok(Response::json(&cube.rooms))
+        }
+        ("GET", ["devices"]) => {
+            cube.refresh().await?;
+            Ok(Response::json(&cube.devices))
+        }
+        ("GET", ["devices", addr]) => {
+            let rf_address = parse_address(addr)?;
+            cube.refresh().await?;
+            let device = cube
+                .devices
+                .iter()
+                .find(|e| e.rf_address() == Some(rf_address))
+                .ok_or(Error::DeviceNotFound(rf_address))?;
+            Ok(Response::json(device))
+        }
+        ("PUT", ["devices", addr, "temperature"]) => {
+            let rf_address = parse_address(addr)?;
+            let body: TemperatureRequest = parse_body(&request.body)?;
+            cube.set_temperature(rf_address, body.temperature).await?;
+            Ok(Response::no_content())
+        }
+        ("PUT", ["rooms", id, "mode"]) => {
+            let room_id = id
+                .parse()
+                .map_err(|_| Response::error(400, "Invalid room id."))?;
+            let body: ModeRequest = parse_body(&request.body)?;
+            let room = cube
+                .rooms
+                .iter()
+                .find(|e| e.room_id == room_id)
+                .ok_or(Error::RoomNotFound(room_id))?;
+            // in auto mode, the temperature 0 keeps the time scheduling
+            let temperature = body.temperature.unwrap_or(match body.mode {
+                DeviceMode::Auto => 0.0,
+                _ => room.temperature_set,
+            });
+            // the commands to a thermostat are applied to the whole room
+            let rf_address = cube
+                .devices_in_room(room_id)
+                .first()
+                .map(|e| e.rf_address)
+                .ok_or_else(|| Response::error(404, "The room has no thermostat."))?;
+
+            match (body.mode, body.until) {
+                (DeviceMode::Vacation, Some(until)) => {
+                    cube.set_vacation(rf_address, temperature, until).await?
+                }
+                (DeviceMode::Vacation, None) => {
+                    return Err(Response::error(400, "The vacation mode requires `until`."))
+                }
+                (mode, _) => cube.set_mode(rf_address, mode, temperature).await?,
+            }
+            Ok(Response::no_content())
+        }
+        ("POST", ["pair"]) => {
+            let body: PairRequest = if request.body.trim().is_empty() {
+                PairRequest::default()
+            } else {
+                parse_body(&request.body)?
+            };
+            let count = cube.devices.len();
+            let timeout = body.timeout.unwrap_or(DEFAULT_PAIR_TIMEOUT);
+            cube.pair(Duration::from_secs(timeout)).await?;
+            Ok(Response::json(&cube.devices[count..]))
+        }
+        #[cfg(feature = "metrics")]
+        ("GET", ["metrics"]) => {
+            cube.refresh().await?;
+            Ok(Response::text(crate::metrics::encode(cube)))
+        }
+        #[cfg(feature = "metrics")]
+        (_, ["metrics"]) => Err(Response::error(405, "Method not allowed.")),
+        (
+            _,
+            ["rooms"]
+            | ["devices"]
+            | ["devices", _]
+            | ["devices", _, "temperature"]
+            | ["rooms", _, "mode"]
+            | ["pair"],
+        ) => Err(Response::error(405, "Method not allowed.")),
+        _ => Err(Response::error(404, "Not found.")),
+    }
+}
+
+/// parses the RF address, decimal or hex with the prefix `0x`
+fn parse_address(addr: &str) -> std::result::Result<u32, Response> {
+    match addr.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => addr.parse(),
+    }
+    .map_err(|_| Response::error(400, "Invalid RF address."))
+}
+
+/// parses the JSON body of the request
+fn parse_body<'a, D: Deserialize<'a>>(body: &'a str) -> std::result::Result<D, Response> {
+    serde_json::from_str(body).map_err(|e| Response::error(400, &e.to_string()))
+}
+
+/// writes the response and closes the connection
+async fn write_response<W: AsyncWrite + Unpin>(writer: &mut W, response: &Response) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len()
+    );
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(response.body.as_bytes()).await?;
+    writer.flush().await?;
+    writer.close().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use futures::io::Cursor;
+
+    use super::*;
+    use crate::messages::test::{trace, MESSAGE_L};
+    use crate::trace::{read_trace, Replay};
+
+    /// returns the session with three refreshes and two commands after the handshake
+    fn session() -> String {
+        let refresh = format!("0.000 > l:\n0.000 < {}\n", MESSAGE_L);
+        trace(&format!(
+            "{}0.000 > s:AARAAAAAGuXTAW4=
+0.000 < S:00,0,31
+0.000 > s:AARAAAAAGuXTAW4=
+0.000 < S:64,1,0
+",
+            refresh.repeat(3)
+        ))
+    }
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    async fn call(cube: &mut MaxCube<Replay>, method: &str, path: &str, body: &str) -> Response {
+        respond(cube, &request(method, path, body)).await
+    }
+
+    #[test]
+    fn test_read_request() {
+        let mut data = Cursor::new(
+            "PUT /devices/1762771/temperature HTTP/1.1\r\nHost: localhost\r\ncontent-length: 20\r\n\r\n{\"temperature\":23.0}"
+                .as_bytes(),
+        );
+        assert_eq!(
+            rt::block_on(read_request(&mut data)).unwrap(),
+            request(
+                "PUT",
+                "/devices/1762771/temperature",
+                "{\"temperature\":23.0}"
+            )
+        );
+
+        let mut data = Cursor::new("GET /rooms HTTP/1.1\r\nHost: localhost\r\n".as_bytes());
+        assert!(rt::block_on(read_request(&mut data)).is_err());
+    }
+
+    #[test]
+    fn test_api() {
+        let replay = Replay::new(read_trace(session().as_bytes()).unwrap());
+        rt::block_on(async {
+            let mut cube = MaxCube::from_transport(replay).await.unwrap();
+            let rooms = call(&mut cube, "GET", "/rooms", "").await;
+            assert_eq!(rooms.status, 200);
+            assert!(rooms.body.contains("\"name\":\"Kitchen\""));
+
+            let device = call(&mut cube, "GET", "/devices/0x1ae5d3", "").await;
+            assert_eq!(device.status, 200);
+            assert!(device
+                .body
+                .starts_with("{\"HeaterThermostat\":{\"rf_address\":1762771,"));
+
+            assert_eq!(call(&mut cube, "GET", "/devices/1", "").await.status, 404);
+            assert_eq!(
+                call(
+                    &mut cube,
+                    "PUT",
+                    "/devices/1762771/temperature",
+                    "{\"temperature\":23.0}"
+                )
+                .await
+                .status,
+                204
+            );
+            assert_eq!(
+                call(
+                    &mut cube,
+                    "PUT",
+                    "/devices/1762771/temperature",
+                    "{\"temperature\":23.0}"
+                )
+                .await
+                .status,
+                503
+            );
+
+            // rejected before sending anything to the cube
+            assert_eq!(
+                call(&mut cube, "PUT", "/devices/1762771/temperature", "{}")
+                    .await
+                    .status,
+                400
+            );
+            assert_eq!(
+                call(
+                    &mut cube,
+                    "PUT",
+                    "/devices/abc/temperature",
+                    "{\"temperature\":23.0}"
+                )
+                .await
+                .status,
+                400
+            );
+            assert_eq!(
+                call(&mut cube, "PUT", "/rooms/1/mode", "{\"mode\":\"Vacation\"}")
+                    .await
+                    .status,
+                400
+            );
+            assert_eq!(
+                call(&mut cube, "PUT", "/rooms/9/mode", "{\"mode\":\"Auto\"}")
+                    .await
+                    .status,
+                404
+            );
+            assert_eq!(call(&mut cube, "DELETE", "/rooms", "").await.status, 405);
+            assert_eq!(call(&mut cube, "GET", "/heating", "").await.status, 404);
+        });
+    }
+}
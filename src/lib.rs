@@ -2,167 +2,74 @@
 
 use std::time::Duration;
 
-use anyhow::{anyhow, Result};
-use async_std::io::BufReader;
-use async_std::net::{TcpStream, ToSocketAddrs};
-use async_std::task::sleep;
-use async_std::prelude::*;
-use log::{debug, info};
+#[cfg(not(any(feature = "runtime-async-std", feature = "runtime-tokio", feature = "blocking")))]
+compile_error!("One of the features `runtime-async-std`, `runtime-tokio` or `blocking` must be enabled.");
 
-pub mod messages;
-
-use messages::{from_message_m, from_message_n, Device, DeviceConfig, DeviceMode, Devices, PairingConfig, Rooms};
-use serde::Serialize;
+#[cfg(all(feature = "sim", not(any(feature = "runtime-async-std", feature = "runtime-tokio"))))]
+compile_error!("The feature `sim` requires `runtime-async-std` or `runtime-tokio`.");
 
-use crate::messages::from_message_l;
+#[cfg(all(feature = "cli", not(any(feature = "runtime-async-std", feature = "runtime-tokio"))))]
+compile_error!("The feature `cli` requires `runtime-async-std` or `runtime-tokio`.");
 
-/// MaxCube represtents a MAX! Cube Gateway.
-/// All operations to the devices shall be triggert from hier.
-#[derive(Debug, Serialize)]
-pub struct MaxCube {
-    /// Socket connection to Cube. The connection will be kept alive.
-    #[serde(skip_serializing)]
-    stream: TcpStream,
+#[cfg(all(feature = "http", not(any(feature = "runtime-async-std", feature = "runtime-tokio"))))]
+compile_error!("The feature `http` requires `runtime-async-std` or `runtime-tokio`.");
 
-    /// A list to all rooms (groups)
-    pub rooms: Rooms,
+#[cfg(all(feature = "mqtt", not(any(feature = "runtime-async-std", feature = "runtime-tokio"))))]
+compile_error!("The feature `mqtt` requires `runtime-async-std` or `runtime-tokio`.");
 
-    /// A list to all devices
-    pub devices: Devices,
+/// declares items, which are only available with an async runtime
+macro_rules! cfg_async {
+    ($($item:item)*) => {
+        $(
+            #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+            $item
+        )*
+    };
 }
 
-impl MaxCube {
-    /// starts a connection to MAX! Cube gateway.
-    /// The connection will be kept alive.
-    /// After successful connection, the cube will send back the meta data and status data of the whole system
-    /// immediately. The data will be decoded and stored in this structure.
-    /// # Examples
-    ///
-    /// ```
-    /// use std::net::SocketAddr;
-    ///
-    /// let cube = MaxCube::new(&SocketAddr::from(([172, 22, 51, 191], 62910))).await.unwrap();
-    /// println!("{:?}", cube);
-    /// ```
-    pub async fn new<A>(addr: A) -> Result<Self>
-    where
-        A: ToSocketAddrs,
-    {
-        let stream = TcpStream::connect(addr).await?;
-
-        let mut cube = MaxCube {
-            stream,
-            rooms: Rooms::new(),
-            devices: Devices::new(),
-        };
-
-        // Sequence:
-        // Receive: H-Message, M-Message (multiple), C-Message (multiple), F-Message, L-Message
-        // Only M- and L-Message will be proceed.
-        // All the content after L-Message will be ignored.
-
-        // the Max Cube will reply with meta data and status data immediately after connection
-        let mut reader = BufReader::new(&cube.stream);
-
-        loop {
-            let mut received = String::new();
-            reader.read_line(&mut received).await?;
-            let received = received.replace("\r\n", "");
-            debug!("{:?}", received);
-
-            if received.starts_with('L') {
-                from_message_l(&received, &mut cube.devices)?;
-                break;
-            } else if received.starts_with('M') {
-                (cube.rooms, cube.devices) = from_message_m(&received)?;
-            }
-        }
-
-        Ok(cube)
-    }
-
-    /// sets the thermostat with the rf_address to the manual mode and the given temperature.
-    /// # Examples
-    ///
-    /// ```
-    /// let mut cube = MaxCube::new(&SocketAddr::from(([172, 22, 51, 191], 62910))).await.unwrap();
-    /// cube.set_temperature(1763839, 21.0).await.unwrap();
-    /// ```
-    pub async fn set_temperature(&mut self, rf_address: u32, temperature: f64) -> Result<()> {
-        // the room id must be set, if the room id = 0, all thermostats will be set
-        // to the temperature.
-
-        let mut dev_conf = DeviceConfig::new();
-
-        let mut dev_it = self.devices.iter().filter(|e| {
-            if let Device::HeaterThermostat(ts) = e {
-                ts.rf_address == rf_address
-            } else {
-                false
-            }
-        });
-
-        if let Some(dev) = dev_it.next() {
-            if let Device::HeaterThermostat(ts) = dev {
-                dev_conf = dev_conf.set_room_id(ts.room_id);
-            } else {
-                return Err(anyhow!("Device type not supported."));
-            }
-        } else {
-            return Err(anyhow!("Device with RF address {} not found.", rf_address));
-        }
-
-        let cmd = dev_conf
-            .set_address(rf_address)
-            .set_mode(DeviceMode::Manual)
-            .set_temperature(temperature)
-            .build();
-
-        self.stream.write_all(cmd.as_bytes()).await?;
-        self.stream.flush().await?;
-
-        let mut resp = "".to_string();
-        let mut reader = BufReader::new(&self.stream);
-        reader.read_line(&mut resp).await?;
-
-        let resp_code = resp
-            .split(',')
-            .into_iter()
-            .collect::<Vec<_>>()
-            .get(1)
-            .ok_or(anyhow!("Response not well-formatted."))?
-            .parse::<u8>()?;
-
-        if resp_code == 0 {
-            Ok(())
-        } else {
-            Err(anyhow!("Device configuration failed."))
-        }
-    }
-
-    pub async fn pair(&mut self, timeout: Duration) -> Result<()> {
-        let pair_cfg = PairingConfig::new(timeout);
-
-        let payload = pair_cfg.build();
-        self.stream.write_all(payload.as_bytes()).await?;
-        self.stream.flush().await?;
-
-        // response shall be only read after timeout / or aborted
-        sleep(timeout).await;
-
-        let mut reader = BufReader::new(&self.stream);
-
-        let mut received = String::new();
-        reader.read_line(&mut received).await?;
-        let received = received.replace("\r\n", "");
-        debug!("{:?}", received);
-
-        if let Ok(dev) = from_message_n(&received) {
-            info!("New device found: {:?}", dev);
-            self.devices.push(dev);
-        }
-
-        Ok(())
-
-    }
+pub mod backup;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod error;
+pub mod history;
+pub mod messages;
+pub mod plan;
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio", feature = "blocking"))]
+mod session;
+pub mod trace;
+pub mod udp;
+
+pub use error::{Error, Result};
+pub use udp::CubeInfo;
+
+cfg_async! {
+    mod cube;
+    #[cfg(feature = "http")]
+    pub mod http;
+    #[cfg(feature = "metrics")]
+    pub mod metrics;
+    #[cfg(feature = "mqtt")]
+    pub mod mqtt;
+    mod rt;
+    #[cfg(feature = "sim")]
+    pub mod sim;
+    mod transport;
+
+    pub use cube::{MaxCube, MaxCubeBuilder};
+    pub use rt::ToSocketAddrs;
+    pub use transport::Transport;
+    pub use udp::{discover, discover_serial};
 }
+
+/// TCP port of the cube
+pub const TCP_PORT: u16 = 62910;
+/// Default time to wait for answers to the discovery, when connecting by serial number
+pub const DEFAULT_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Default interval, after which an idle connection will be refreshed by `keep_alive()`
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// Default timeout for establishing the TCP connection
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default timeout for receiving the initial meta and status data after connection
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Default timeout for the answer of a single command
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
@@ -1,19 +1,85 @@
-use anyhow::{anyhow, bail, Result};
 use base64::{engine::general_purpose, Engine as _};
 use serde::{Deserialize, Serialize};
 use std::{collections::VecDeque, time::Duration};
 
+use crate::error::{Error, Result};
+
 /// represents a heating system device, e.g. thermostat, shutter contact...
-/// Only thermostat is supported by now.
-#[derive(Debug, Default, Deserialize, Serialize)]
+/// Heater thermostats, wall thermostats and shutter contacts are supported by now.
+/// Devices of other types, e.g. the eco button, keep their meta data only.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
 pub enum Device {
     #[default]
     NotSupported,
     HeaterThermostat(HeaterThermostat),
+    WallThermostat(WallThermostat),
+    ShutterContact(ShutterContact),
+    Other(OtherDevice),
+}
+
+impl Device {
+    /// returns the RF address, if the meta data of the device is known
+    pub fn rf_address(&self) -> Option<u32> {
+        self.meta().map(|(_, rf_address, _, _, _)| rf_address)
+    }
+
+    /// returns the name, if the meta data of the device is known
+    pub fn name(&self) -> Option<&str> {
+        self.meta().map(|(_, _, _, name, _)| name)
+    }
+
+    /// returns the serial number, if the meta data of the device is known
+    pub fn serial(&self) -> Option<&str> {
+        self.meta().map(|(_, _, serial, _, _)| serial)
+    }
+
+    /// returns the room id, if the meta data of the device is known
+    pub fn room_id(&self) -> Option<u8> {
+        self.meta().map(|(_, _, _, _, room_id)| room_id)
+    }
+
+    /// returns the device type, 1 = heater thermostat, 3 = wall thermostat, 4 = shutter contact,
+    /// other types as received from the cube
+    pub fn device_type(&self) -> Option<u8> {
+        self.meta().map(|(device_type, _, _, _, _)| device_type)
+    }
+
+    /// returns if the battery level of the device is low
+    pub fn battery_low(&self) -> bool {
+        match self {
+            Device::HeaterThermostat(ts) => ts.battery_low,
+            Device::WallThermostat(wt) => wt.battery_low,
+            Device::ShutterContact(sc) => sc.battery_low,
+            Device::Other(_) | Device::NotSupported => false,
+        }
+    }
+
+    /// returns the meta data: device type, RF address, serial number, name and room id
+    fn meta(&self) -> Option<(u8, u32, &str, &str, u8)> {
+        match self {
+            Device::HeaterThermostat(ts) => {
+                Some((1, ts.rf_address, &ts.serial, &ts.name, ts.room_id))
+            }
+            Device::WallThermostat(wt) => {
+                Some((3, wt.rf_address, &wt.serial, &wt.name, wt.room_id))
+            }
+            Device::ShutterContact(sc) => {
+                Some((4, sc.rf_address, &sc.serial, &sc.name, sc.room_id))
+            }
+            Device::Other(od) => Some((
+                od.device_type,
+                od.rf_address,
+                &od.serial,
+                &od.name,
+                od.room_id,
+            )),
+            Device::NotSupported => None,
+        }
+    }
 }
 
 /// represents a thermostat of heater
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct HeaterThermostat {
     /// RF address of the thermostat
     pub rf_address: u32,
@@ -30,6 +96,33 @@ pub struct HeaterThermostat {
     /// current temperature, which is measured by the thermostat
     /// this value is not always available
     pub temperature_measured: f64,
+    /// current mode of the thermostat
+    pub mode: DeviceMode,
+    /// if the battery level is low
+    pub battery_low: bool,
+    /// if the thermostat in error state
+    pub error: bool,
+    /// if the values from thermostat are valid
+    pub valid: bool,
+}
+
+/// represents a wall mounted thermostat, which measures the temperature of the room
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WallThermostat {
+    /// RF address of the thermostat
+    pub rf_address: u32,
+    /// Serial number of the thermostat
+    pub serial: String,
+    /// Name of the thermostat
+    pub name: String,
+    /// room id (group id), which the thermostat belongs to
+    pub room_id: u8,
+    /// current temperature set point of the room
+    pub temperature_set: f64,
+    /// current temperature of the room
+    pub temperature_measured: f64,
+    /// current mode of the thermostat
+    pub mode: DeviceMode,
     /// if the battery level is low
     pub battery_low: bool,
     /// if the thermostat in error state
@@ -38,8 +131,46 @@ pub struct HeaterThermostat {
     pub valid: bool,
 }
 
-/// represents a room/group, which is set up by MAX! software
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// represents a shutter contact of a window or a door
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShutterContact {
+    /// RF address of the contact
+    pub rf_address: u32,
+    /// Serial number of the contact
+    pub serial: String,
+    /// Name of the contact
+    pub name: String,
+    /// room id (group id), which the contact belongs to
+    pub room_id: u8,
+    /// if the window or door is open
+    pub open: bool,
+    /// if the battery level is low
+    pub battery_low: bool,
+    /// if the contact in error state
+    pub error: bool,
+    /// if the values from contact are valid
+    pub valid: bool,
+}
+
+/// represents a device of a type, which is not supported, e.g. the eco button.
+/// Only the meta data is kept, it is written back unchanged with the meta data of the cube.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OtherDevice {
+    /// device type, as received from the cube, e.g. 2 = heater thermostat plus, 5 = eco button
+    pub device_type: u8,
+    /// RF address of the device
+    pub rf_address: u32,
+    /// Serial number of the device
+    pub serial: String,
+    /// Name of the device
+    pub name: String,
+    /// room id (group id), which the device belongs to
+    pub room_id: u8,
+}
+
+/// represents a room/group, which is set up by MAX! software.
+/// The state of the room is computed from its devices by `update_rooms()`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Room {
     /// room id (group id)
     pub room_id: u8,
@@ -47,6 +178,28 @@ pub struct Room {
     pub name: String,
     /// RF address of the room
     pub rf_address: u32,
+    /// RF addresses of the devices in the room
+    #[serde(default)]
+    pub devices: Vec<u32>,
+    /// temperature set point of the room, the wall thermostat is preferred
+    #[serde(default)]
+    pub temperature_set: f64,
+    /// measured temperature of the room, the wall thermostat is preferred,
+    /// otherwise the average of the heater thermostats
+    #[serde(default)]
+    pub temperature_measured: f64,
+    /// average valve position of the heater thermostats, in percent
+    #[serde(default)]
+    pub valve_position: u8,
+    /// if any window or door of the room is open
+    #[serde(default)]
+    pub window_open: bool,
+    /// if the battery level of any device is low
+    #[serde(default)]
+    pub battery_low: bool,
+    /// current mode of the room, the wall thermostat is preferred
+    #[serde(default)]
+    pub mode: DeviceMode,
 }
 
 /// List of devices
@@ -54,68 +207,194 @@ pub type Devices = Vec<Device>;
 /// List of rooms
 pub type Rooms = Vec<Room>;
 
-/// the function shall not be called directly
-pub(super) fn from_message_m(recv: &str) -> Result<(Rooms, Devices)> {
+/// represents the state of the cube, which is sent as H-Message after connection
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CubeStatus {
+    /// Serial number of the cube
+    pub serial: String,
+    /// RF address of the cube
+    pub rf_address: u32,
+    /// firmware version, e.g. 0x0113 for 1.1.3
+    pub firmware: u16,
+    /// id of the HTTP connection
+    pub http_connection_id: u32,
+    /// used duty cycle, in percent. The cube may only send 1% of the time.
+    pub duty_cycle: u8,
+    /// free memory slots for queued commands
+    pub free_memory_slots: u8,
+    /// date of the cube, the year is between 2000 and 2255
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    /// time of the cube
+    pub hour: u8,
+    pub minute: u8,
+    pub state_time: u8,
+    pub ntp_counter: u16,
+}
+
+/// represents the configuration of a device, which is sent as C-Message
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceSettings {
+    /// RF address of the device
+    pub rf_address: u32,
+    /// device type, 0 = cube, 1 = heater thermostat, 2 = heater thermostat plus,
+    /// 3 = wall thermostat, 4 = shutter contact, 5 = push button
+    pub device_type: u8,
+    /// room id (group id), which the device belongs to
+    pub room_id: u8,
+    /// firmware version
+    pub firmware: u8,
+    pub test_result: u8,
+    /// Serial number of the device
+    pub serial: String,
+    /// the settings of the heater thermostats (device type 1 and 2)
+    pub thermostat: Option<ThermostatSettings>,
+    /// the remaining data, which is not decoded, e.g. the configuration of the cube
+    pub data: Vec<u8>,
+}
+
+/// represents the settings of a heater thermostat
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThermostatSettings {
+    pub comfort_temperature: f64,
+    pub eco_temperature: f64,
+    pub max_temperature: f64,
+    pub min_temperature: f64,
+    /// offset of the measured temperature, from -3.5 to 3.5
+    pub temperature_offset: f64,
+    /// temperature set point, if an open window is detected
+    pub window_open_temperature: f64,
+    /// in minutes, step of 5 minutes
+    pub window_open_duration: u16,
+    /// in minutes, 0 to 30 with step of 5 minutes, or 60
+    pub boost_duration: u8,
+    /// valve position while boosting, in percent with step of 5%
+    pub boost_valve: u8,
+    /// day of the week for decalcification, 0 = Saturday ... 6 = Friday
+    pub decalcification_day: u8,
+    pub decalcification_hour: u8,
+    /// maximum valve position, in percent
+    pub max_valve: u8,
+    /// in percent
+    pub valve_offset: u8,
+    /// time scheduling for every day of the week, starting with Saturday
+    pub weekly_program: [DayProgram; 7],
+}
+
+impl Default for ThermostatSettings {
+    /// returns the factory settings of the thermostat
+    fn default() -> Self {
+        let day = vec![
+            ProgramPoint {
+                temperature: 17.0,
+                until: 6 * 60,
+            },
+            ProgramPoint {
+                temperature: 21.0,
+                until: 22 * 60,
+            },
+            ProgramPoint {
+                temperature: 17.0,
+                until: 24 * 60,
+            },
+        ];
+        Self {
+            comfort_temperature: 21.0,
+            eco_temperature: 17.0,
+            max_temperature: 30.5,
+            min_temperature: 4.5,
+            temperature_offset: 0.0,
+            window_open_temperature: 12.0,
+            window_open_duration: 15,
+            boost_duration: 5,
+            boost_valve: 80,
+            decalcification_day: 0,
+            decalcification_hour: 12,
+            max_valve: 100,
+            valve_offset: 0,
+            weekly_program: std::array::from_fn(|_| day.clone()),
+        }
+    }
+}
+
+/// one switch point of the time scheduling: the temperature is kept until the time
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProgramPoint {
+    /// temperature set point, with step of 0.5°C
+    pub temperature: f64,
+    /// minutes since midnight, with step of 5 minutes. The last point of the day lasts until 1440 (24:00).
+    pub until: u16,
+}
+
+/// time scheduling of one day, up to 13 points
+pub type DayProgram = Vec<ProgramPoint>;
+
+/// names of the days of the week in the order of the cube, 0 = Saturday ... 6 = Friday
+pub const DAYS: [&str; 7] = [
+    "saturday",
+    "sunday",
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+];
+
+/// decodes the M-Message with the meta data: rooms and devices
+pub fn from_message_m(recv: &str) -> Result<(Rooms, Devices)> {
+    let short = || Error::protocol("Unexpected data length.", recv);
+
     // assertions
     if !recv.starts_with("M:") {
-        return Err(anyhow!(
-            "Message `M` expected, but `{}` received.",
-            recv.chars().next().unwrap()
-        ));
+        return Err(Error::protocol("Message `M` expected.", recv));
     }
 
-    for (index, part) in recv.split(",").into_iter().enumerate() {
-        if index == 0 && part != "M:00" {
-            return Err(anyhow!("Chunked M-Message not supported."));
-        } else if index == 1 && part != "01" {
-            return Err(anyhow!("Chunked M-Message not supported."));
+    for (index, part) in recv.split(',').enumerate() {
+        if (index == 0 && part != "M:00") || (index == 1 && part != "01") {
+            return Err(Error::protocol("Chunked M-Message not supported.", recv));
         } else if index == 2 {
-            let mut b = VecDeque::from(general_purpose::STANDARD.decode(part)?);
-            b.pop_front().ok_or(anyhow!("Unexpected data length."))?;
-            b.pop_front().ok_or(anyhow!("Unexpected data length."))?;
+            let mut b = VecDeque::from(decode_base64(part, recv)?);
+            b.pop_front().ok_or_else(short)?;
+            b.pop_front().ok_or_else(short)?;
 
             // decode all rooms
-            let room_count = b.pop_front().ok_or(anyhow!("Unexpected data length."))? as usize;
+            let room_count = b.pop_front().ok_or_else(short)? as usize;
             let mut rooms = Rooms::new();
             for _ in vec![0; room_count] {
-                let room_id = b.pop_front().ok_or(anyhow!("Unexpected data length."))?;
-                let length = b.pop_front().ok_or(anyhow!("Unexpected data length."))? as usize;
-                let name =
-                    String::from_utf8_lossy(&b.drain(..length).into_iter().collect::<Vec<_>>())
-                        .to_string();
+                let room_id = b.pop_front().ok_or_else(short)?;
+                let length = b.pop_front().ok_or_else(short)? as usize;
+                let name = String::from_utf8_lossy(&take(&mut b, length, recv)?).to_string();
                 let rf_address = u32::from_be_bytes([
                     0,
-                    b.pop_front().ok_or(anyhow!("Unexpected data length."))?,
-                    b.pop_front().ok_or(anyhow!("Unexpected data length."))?,
-                    b.pop_front().ok_or(anyhow!("Unexpected data length."))?,
+                    b.pop_front().ok_or_else(short)?,
+                    b.pop_front().ok_or_else(short)?,
+                    b.pop_front().ok_or_else(short)?,
                 ]);
                 let room = Room {
                     room_id,
                     name,
                     rf_address,
+                    ..Default::default()
                 };
                 rooms.push(room);
             }
 
             // decode all devices
-            let dev_count = b.pop_front().ok_or(anyhow!("Unexpected data length."))? as usize;
+            let dev_count = b.pop_front().ok_or_else(short)? as usize;
             let mut devices = Devices::new();
             for _ in vec![0; dev_count] {
-                let dev_type = b.pop_front().ok_or(anyhow!("Unexpected data length."))?;
+                let dev_type = b.pop_front().ok_or_else(short)?;
                 let rf_address = u32::from_be_bytes([
                     0,
-                    b.pop_front().ok_or(anyhow!("Unexpected data length."))?,
-                    b.pop_front().ok_or(anyhow!("Unexpected data length."))?,
-                    b.pop_front().ok_or(anyhow!("Unexpected data length."))?,
+                    b.pop_front().ok_or_else(short)?,
+                    b.pop_front().ok_or_else(short)?,
+                    b.pop_front().ok_or_else(short)?,
                 ]);
-                let serial =
-                    String::from_utf8_lossy(&b.drain(..10).into_iter().collect::<Vec<_>>())
-                        .to_string();
-                let length = b.pop_front().ok_or(anyhow!("Unexpected data length."))? as usize;
-                let name =
-                    String::from_utf8_lossy(&b.drain(..length).into_iter().collect::<Vec<_>>())
-                        .to_string();
-                let room_id = b.pop_front().ok_or(anyhow!("Unexpected data length."))?;
+                let serial = String::from_utf8_lossy(&take(&mut b, 10, recv)?).to_string();
+                let length = b.pop_front().ok_or_else(short)? as usize;
+                let name = String::from_utf8_lossy(&take(&mut b, length, recv)?).to_string();
+                let room_id = b.pop_front().ok_or_else(short)?;
                 let device = match dev_type {
                     1 => Device::HeaterThermostat(HeaterThermostat {
                         rf_address,
@@ -124,7 +403,27 @@ pub(super) fn from_message_m(recv: &str) -> Result<(Rooms, Devices)> {
                         name,
                         ..Default::default()
                     }),
-                    _ => Device::NotSupported,
+                    3 => Device::WallThermostat(WallThermostat {
+                        rf_address,
+                        serial,
+                        room_id,
+                        name,
+                        ..Default::default()
+                    }),
+                    4 => Device::ShutterContact(ShutterContact {
+                        rf_address,
+                        serial,
+                        room_id,
+                        name,
+                        ..Default::default()
+                    }),
+                    _ => Device::Other(OtherDevice {
+                        device_type: dev_type,
+                        rf_address,
+                        serial,
+                        name,
+                        room_id,
+                    }),
                 };
                 devices.push(device);
             }
@@ -132,113 +431,840 @@ pub(super) fn from_message_m(recv: &str) -> Result<(Rooms, Devices)> {
         }
     }
 
-    Err(anyhow!("Message M not well-formatted."))
+    Err(Error::protocol("Message M not well-formatted.", recv))
 }
 
-pub(super) fn from_message_l(recv: &str, devices: &mut Devices) -> Result<()> {
+/// decodes the L-Message and updates the status of the devices
+pub fn from_message_l(recv: &str, devices: &mut Devices) -> Result<()> {
+    let short = || Error::protocol("Unexpected data length.", recv);
+
     // assertions
     if !recv.starts_with("L:") {
-        return Err(anyhow!(
-            "Message `L` expected, but `{}` received.",
-            recv.chars().next().unwrap()
-        ));
+        return Err(Error::protocol("Message `L` expected.", recv));
     }
 
-    let mut b = VecDeque::from(
-        general_purpose::STANDARD.decode(
-            recv.split(":")
-                .last()
-                .ok_or(anyhow!("Message L not well-formatted."))?,
-        )?,
-    );
+    let mut b = VecDeque::from(decode_base64(
+        recv.split(':')
+            .next_back()
+            .ok_or_else(|| Error::protocol("Message L not well-formatted.", recv))?,
+        recv,
+    )?);
 
-    while b.len() > 0 {
-        let length = b.pop_front().ok_or(anyhow!("Unexpected data length."))? as usize;
-        let mut sub = b.drain(..length).into_iter().collect::<VecDeque<_>>();
+    while !b.is_empty() {
+        let length = b.pop_front().ok_or_else(short)? as usize;
+        let mut sub = VecDeque::from(take(&mut b, length, recv)?);
         let rf_address = u32::from_be_bytes([
             0,
-            sub.pop_front().ok_or(anyhow!("Unexpected data length."))?,
-            sub.pop_front().ok_or(anyhow!("Unexpected data length."))?,
-            sub.pop_front().ok_or(anyhow!("Unexpected data length."))?,
+            sub.pop_front().ok_or_else(short)?,
+            sub.pop_front().ok_or_else(short)?,
+            sub.pop_front().ok_or_else(short)?,
         ]);
-        sub.pop_front().ok_or(anyhow!("Unexpected data length."))?; // unknown field
+        sub.pop_front().ok_or_else(short)?; // unknown field
         let flags = u16::from_be_bytes([
-            sub.pop_front().ok_or(anyhow!("Unexpected data length."))?,
-            sub.pop_front().ok_or(anyhow!("Unexpected data length."))?,
+            sub.pop_front().ok_or_else(short)?,
+            sub.pop_front().ok_or_else(short)?,
         ]);
 
+        // the thermostat specific part is only available, if the length is greater than 6
+        let thermostat = if length > 6 {
+            Some(take(&mut sub, 4, recv)?)
+        } else {
+            None
+        };
+        // the wall thermostat sends the lower byte of the measured temperature at the end
+        let wall_measured = sub.get(1).copied();
+
+        let battery_low = (flags & 0x80) > 0;
+        let error = (flags & 0x800) > 0;
+        let valid = (flags & 0x1000) > 0;
+        let mode = DeviceMode::from_bits(flags as u8);
+
         // get mutable reference from devices
-        devices.iter_mut().for_each(|e| {
-            if let Device::HeaterThermostat(ts) = e {
-                if ts.rf_address == rf_address {
-                    ts.battery_low = (flags & 0x80) > 0;
-                    ts.error = (flags & 0x800) > 0;
-                    ts.valid = (flags & 0x1000) > 0;
-
-                    if length > 6 {
-                        ts.valve_position = sub.pop_front().unwrap();
-                        ts.temperature_set = sub.pop_front().unwrap() as f64 / 2.0;
-                        ts.temperature_measured = u16::from_be_bytes([
-                            sub.pop_front()
-                                .ok_or(anyhow!("Unexpected data length."))
-                                .unwrap(),
-                            sub.pop_front()
-                                .ok_or(anyhow!("Unexpected data length."))
-                                .unwrap(),
-                        ]) as f64
-                            / 10.0;
+        for dev in devices
+            .iter_mut()
+            .filter(|e| e.rf_address() == Some(rf_address))
+        {
+            match dev {
+                Device::HeaterThermostat(ts) => {
+                    (ts.battery_low, ts.error, ts.valid, ts.mode) =
+                        (battery_low, error, valid, mode);
+                    if let Some(b) = &thermostat {
+                        ts.valve_position = b[0];
+                        ts.temperature_set = b[1] as f64 / 2.0;
+                        ts.temperature_measured = u16::from_be_bytes([b[2], b[3]]) as f64 / 10.0;
+                    }
+                }
+                Device::WallThermostat(wt) => {
+                    (wt.battery_low, wt.error, wt.valid, wt.mode) =
+                        (battery_low, error, valid, mode);
+                    if let (Some(b), Some(measured)) = (&thermostat, wall_measured) {
+                        // the highest bit of the set point is the 9th bit of the measured temperature
+                        wt.temperature_set = (b[1] & 0x7f) as f64 / 2.0;
+                        wt.temperature_measured =
+                            ((((b[1] & 0x80) as u16) << 1) | measured as u16) as f64 / 10.0;
                     }
                 }
+                Device::ShutterContact(sc) => {
+                    (sc.battery_low, sc.error, sc.valid) = (battery_low, error, valid);
+                    sc.open = flags & 0x03 == 0x02;
+                }
+                Device::Other(_) | Device::NotSupported => (),
             }
-        });
+        }
     }
 
     Ok(())
 }
 
-pub(super) fn from_message_n(recv: &str) -> Result<Device> {
-        // assertions
-        if !recv.starts_with("N:") {
-            bail!(
-                "Message `N` expected, but `{}` received.",
-                recv.chars().next().unwrap()
-            );
+/// decodes the N-Message with the newly paired device
+pub fn from_message_n(recv: &str) -> Result<Device> {
+    // assertions
+    if !recv.starts_with("N:") {
+        return Err(Error::protocol("Message `N` expected.", recv));
+    }
+
+    let b = decode_base64(
+        recv.split(':')
+            .next_back()
+            .ok_or_else(|| Error::protocol("Message N not well-formatted.", recv))?,
+        recv,
+    )?;
+
+    if b.len() < 15 {
+        return Err(Error::protocol(
+            format!(
+                "Message `N` shall have a length of 15 bytes. {} received.",
+                b.len()
+            ),
+            recv,
+        ));
+    }
+
+    let rf_address = u32::from_be_bytes([0, b[1], b[2], b[3]]);
+    let serial = String::from_utf8_lossy(&b[4..14]).to_string();
+    match b[0] {
+        1 => Ok(Device::HeaterThermostat(HeaterThermostat {
+            rf_address,
+            serial,
+            ..Default::default()
+        })),
+        3 => Ok(Device::WallThermostat(WallThermostat {
+            rf_address,
+            serial,
+            ..Default::default()
+        })),
+        4 => Ok(Device::ShutterContact(ShutterContact {
+            rf_address,
+            serial,
+            ..Default::default()
+        })),
+        _ => Err(Error::DeviceNotSupported(rf_address)),
+    }
+}
+
+/// decodes the H-Message with the state of the cube
+pub fn from_message_h(recv: &str) -> Result<CubeStatus> {
+    let fields = recv
+        .strip_prefix("H:")
+        .ok_or_else(|| Error::protocol("Message `H` expected.", recv))?
+        .split(',')
+        .collect::<Vec<_>>();
+
+    // all fields except the serial number are hex values with fixed width
+    let hex = |index: usize, width: usize| {
+        fields
+            .get(index)
+            .filter(|e| e.len() == width && e.chars().all(|c| c.is_ascii_hexdigit()))
+            .and_then(|e| u32::from_str_radix(e, 16).ok())
+            .ok_or_else(|| Error::protocol("Message H not well-formatted.", recv))
+    };
+
+    let date = hex(7, 6)?;
+    let time = hex(8, 4)?;
+    Ok(CubeStatus {
+        serial: fields.first().unwrap_or(&"").to_string(),
+        rf_address: hex(1, 6)?,
+        firmware: hex(2, 4)? as u16,
+        http_connection_id: hex(4, 8)?,
+        duty_cycle: hex(5, 2)? as u8,
+        free_memory_slots: hex(6, 2)? as u8,
+        year: 2000 + (date >> 16) as u16,
+        month: (date >> 8) as u8,
+        day: date as u8,
+        hour: (time >> 8) as u8,
+        minute: time as u8,
+        state_time: hex(9, 2)? as u8,
+        ntp_counter: hex(10, 4)? as u16,
+    })
+}
+
+/// decodes the C-Message with the configuration of a device
+pub fn from_message_c(recv: &str) -> Result<DeviceSettings> {
+    let short = || Error::protocol("Unexpected data length.", recv);
+
+    // assertions
+    if !recv.starts_with("C:") {
+        return Err(Error::protocol("Message `C` expected.", recv));
+    }
+
+    let (address, data) = recv[2..]
+        .split_once(',')
+        .ok_or_else(|| Error::protocol("Message C not well-formatted.", recv))?;
+    // the configuration is empty, if the device is unknown to the cube
+    if data.is_empty() {
+        return Err(u32::from_str_radix(address, 16)
+            .map(Error::DeviceNotFound)
+            .unwrap_or_else(|_| Error::protocol("Message C not well-formatted.", recv)));
+    }
+
+    let mut b = VecDeque::from(decode_base64(data, recv)?);
+
+    let length = b.pop_front().ok_or_else(short)? as usize;
+    let mut b = VecDeque::from(take(&mut b, length, recv)?);
+    let header = take(&mut b, 17, recv)?;
+
+    let mut settings = DeviceSettings {
+        rf_address: u32::from_be_bytes([0, header[0], header[1], header[2]]),
+        device_type: header[3],
+        room_id: header[4],
+        firmware: header[5],
+        test_result: header[6],
+        serial: String::from_utf8_lossy(&header[7..17]).to_string(),
+        ..Default::default()
+    };
+
+    // the heater thermostats have 11 bytes of settings, followed by the program of 7 days
+    if (settings.device_type == 1 || settings.device_type == 2) && b.len() >= 11 + 7 * 26 {
+        let b = take(&mut b, 11 + 7 * 26, recv)?;
+        let boost = b[7];
+        let decalcification = b[8];
+        let mut program = b[11..].chunks_exact(26).map(|day| {
+            let mut points = DayProgram::new();
+            for point in day.chunks_exact(2) {
+                let point = u16::from_be_bytes([point[0], point[1]]);
+                points.push(ProgramPoint {
+                    temperature: (point >> 9) as f64 / 2.0,
+                    until: (point & 0x1ff) * 5,
+                });
+                if (point & 0x1ff) * 5 >= 24 * 60 {
+                    break;
+                }
+            }
+            points
+        });
+
+        settings.thermostat = Some(ThermostatSettings {
+            comfort_temperature: b[0] as f64 / 2.0,
+            eco_temperature: b[1] as f64 / 2.0,
+            max_temperature: b[2] as f64 / 2.0,
+            min_temperature: b[3] as f64 / 2.0,
+            temperature_offset: b[4] as f64 / 2.0 - 3.5,
+            window_open_temperature: b[5] as f64 / 2.0,
+            window_open_duration: b[6] as u16 * 5,
+            boost_duration: match boost >> 5 {
+                7 => 60,
+                d => d * 5,
+            },
+            boost_valve: (boost & 0x1f) * 5,
+            decalcification_day: decalcification >> 5,
+            decalcification_hour: decalcification & 0x1f,
+            max_valve: (b[9] as f64 * 100.0 / 255.0).round() as u8,
+            valve_offset: (b[10] as f64 * 100.0 / 255.0).round() as u8,
+            weekly_program: std::array::from_fn(|_| program.next().unwrap_or_default()),
+        });
+    }
+    settings.data = b.into();
+
+    Ok(settings)
+}
+
+/// builds the command, which sets the thermostat to the manual mode and the given temperature
+pub(crate) fn set_temperature_command(
+    devices: &Devices,
+    rf_address: u32,
+    temperature: f64,
+) -> Result<String> {
+    mode_command(devices, rf_address, DeviceMode::Manual, temperature, None)
+}
+
+/// builds the command, which sets the thermostat to the mode and the given temperature
+pub(crate) fn mode_command(
+    devices: &Devices,
+    rf_address: u32,
+    mode: DeviceMode,
+    temperature: f64,
+    until: Option<DateTime>,
+) -> Result<String> {
+    // the room id must be set, if the room id = 0, all thermostats will be set
+    // to the temperature.
+    let mut dev_conf = DeviceConfig::new()
+        .set_room_id(room_id_of(devices, rf_address)?)
+        .set_address(rf_address)
+        .set_mode(mode)
+        .set_temperature(temperature);
+    if let Some(until) = until {
+        dev_conf = dev_conf.set_until(until);
+    }
+
+    dev_conf.try_build()
+}
+
+/// builds the command, which sets the time scheduling of the thermostat for one day,
+/// 0 = Saturday ... 6 = Friday
+pub(crate) fn program_command(
+    devices: &Devices,
+    rf_address: u32,
+    day: u8,
+    program: &DayProgram,
+) -> Result<String> {
+    let room_id = room_id_of(devices, rf_address)?;
+
+    let mut data = vec![0x00u8, 0x04, 0x10, 0x00, 0x00, 0x00];
+    data.extend_from_slice(&rf_address.to_be_bytes()[1..]);
+    data.push(room_id);
+    data.push(day % 7);
+    data.extend(encode_day_program(program));
+
+    Ok(format!("s:{}\r\n", general_purpose::STANDARD.encode(data)))
+}
+
+/// builds the `s:` command of the type to the single device, followed by the payload
+fn device_command(command: u8, rf_address: u32, room_id: u8, payload: &[u8]) -> String {
+    let mut data = vec![0x00u8, 0x00, command, 0x00, 0x00, 0x00];
+    data.extend_from_slice(&rf_address.to_be_bytes()[1..]);
+    data.push(room_id);
+    data.extend_from_slice(payload);
+
+    format!("s:{}\r\n", general_purpose::STANDARD.encode(data))
+}
+
+/// builds the command, which sets the comfort, eco, maximum, minimum and window open temperatures,
+/// the temperature offset and the window open duration of the heater thermostat
+pub(crate) fn config_temperatures_command(
+    rf_address: u32,
+    room_id: u8,
+    settings: &ThermostatSettings,
+) -> String {
+    let b = encode_thermostat_settings(settings);
+    device_command(0x11, rf_address, room_id, &b[..7])
+}
+
+/// builds the command, which sets the boost, decalcification and valve settings of the heater thermostat
+pub(crate) fn config_valve_command(
+    rf_address: u32,
+    room_id: u8,
+    settings: &ThermostatSettings,
+) -> String {
+    let b = encode_thermostat_settings(settings);
+    device_command(0x12, rf_address, room_id, &b[7..])
+}
+
+/// builds the command, which adds the partner to the devices, which the device talks to directly
+pub(crate) fn link_partner_command(
+    rf_address: u32,
+    room_id: u8,
+    partner: u32,
+    partner_type: u8,
+) -> String {
+    let mut payload = partner.to_be_bytes()[1..].to_vec();
+    payload.push(partner_type);
+    device_command(0x20, rf_address, room_id, &payload)
+}
+
+/// builds the command, which removes the partner from the devices, which the device talks to directly
+pub(crate) fn unlink_partner_command(
+    rf_address: u32,
+    room_id: u8,
+    partner: u32,
+    partner_type: u8,
+) -> String {
+    let mut payload = partner.to_be_bytes()[1..].to_vec();
+    payload.push(partner_type);
+    device_command(0x21, rf_address, room_id, &payload)
+}
+
+/// builds the command, which assigns the device to the room (group)
+pub(crate) fn group_command(rf_address: u32, room_id: u8) -> String {
+    device_command(0x22, rf_address, 0, &[room_id])
+}
+
+/// builds the command, which writes the meta data (rooms, names of the devices) to the cube
+pub(crate) fn meta_data_command(rooms: &Rooms, devices: &Devices) -> String {
+    format!(
+        "m:00,{}\r\n",
+        general_purpose::STANDARD.encode(encode_meta_data(rooms, devices))
+    )
+}
+
+/// builds the command, which deletes the devices from the cube
+pub(crate) fn delete_command(rf_addresses: &[u32]) -> String {
+    let data = rf_addresses
+        .iter()
+        .flat_map(|e| e.to_be_bytes()[1..].to_vec())
+        .collect::<Vec<_>>();
+    format!(
+        "t:{:02x},1,{}\r\n",
+        rf_addresses.len(),
+        general_purpose::STANDARD.encode(data)
+    )
+}
+
+/// returns the room id of the thermostat, which is needed by the commands
+fn room_id_of(devices: &Devices, rf_address: u32) -> Result<u8> {
+    let mut dev_it = devices.iter().filter(|e| {
+        if let Device::HeaterThermostat(ts) = e {
+            ts.rf_address == rf_address
+        } else {
+            false
         }
+    });
 
-        let b = Vec::from(
-            general_purpose::STANDARD.decode(
-                recv.split(":")
-                    .last()
-                    .ok_or(anyhow!("Message N not well-formatted."))?,
-            )?,
-        );
+    match dev_it.next() {
+        Some(Device::HeaterThermostat(ts)) => Ok(ts.room_id),
+        Some(_) => Err(Error::DeviceNotSupported(rf_address)),
+        None => Err(Error::DeviceNotFound(rf_address)),
+    }
+}
+
+/// returns the thermostat with the name
+pub(crate) fn device_by_name<'a>(devices: &'a Devices, name: &str) -> Result<&'a HeaterThermostat> {
+    unique(
+        thermostats(devices).filter(|e| e.name == name),
+        name,
+        || Error::NameNotFound(name.to_string()),
+    )
+}
+
+/// returns the room with the name
+pub(crate) fn room_by_name<'a>(rooms: &'a Rooms, name: &str) -> Result<&'a Room> {
+    unique(rooms.iter().filter(|e| e.name == name), name, || {
+        Error::NameNotFound(name.to_string())
+    })
+}
 
-        if b.len() < 15 {
-            bail!("Message `N` shall have a length of 15 bytes. {} received.", b.len())
+/// returns the thermostats in the room
+pub(crate) fn devices_in_room(devices: &Devices, room_id: u8) -> Vec<&HeaterThermostat> {
+    thermostats(devices)
+        .filter(|e| e.room_id == room_id)
+        .collect()
+}
+
+/// returns the room of the thermostat with the RF address
+pub(crate) fn room_of<'a>(
+    rooms: &'a Rooms,
+    devices: &Devices,
+    rf_address: u32,
+) -> Result<&'a Room> {
+    let room_id = room_id_of(devices, rf_address)?;
+    rooms
+        .iter()
+        .find(|e| e.room_id == room_id)
+        .ok_or(Error::RoomNotFound(room_id))
+}
+
+/// returns the RF address of the thermostat with the name, or of the first thermostat in the room
+/// with the name. The commands to a thermostat are applied to the whole room by the cube.
+/// If a device and a room have the same name, the device is used.
+pub(crate) fn thermostat_by_name(rooms: &Rooms, devices: &Devices, name: &str) -> Result<u32> {
+    match device_by_name(devices, name) {
+        Ok(ts) => Ok(ts.rf_address),
+        Err(Error::NameNotFound(_)) => {
+            let room = room_by_name(rooms, name)?;
+            devices_in_room(devices, room.room_id)
+                .first()
+                .map(|e| e.rf_address)
+                .ok_or_else(|| Error::NameNotFound(name.to_string()))
         }
+        Err(e) => Err(e),
+    }
+}
+
+/// returns all thermostats of the devices
+fn thermostats(devices: &Devices) -> impl Iterator<Item = &HeaterThermostat> {
+    devices.iter().filter_map(|e| match e {
+        Device::HeaterThermostat(ts) => Some(ts),
+        _ => None,
+    })
+}
+
+/// computes the state of the rooms from their devices.
+/// The wall thermostat of a room is preferred for the set point, the measured temperature and the mode,
+/// otherwise the heater thermostats are used.
+pub fn update_rooms(rooms: &mut Rooms, devices: &Devices) {
+    for room in rooms.iter_mut() {
+        let members = devices
+            .iter()
+            .filter(|e| e.room_id() == Some(room.room_id))
+            .collect::<Vec<_>>();
+        let heaters = devices_in_room(devices, room.room_id);
+        let wall = members.iter().find_map(|e| match e {
+            Device::WallThermostat(wt) => Some(wt),
+            _ => None,
+        });
 
-        if b[0] != 1 {
-            bail!("Device type not supported. Only heater thermostat supported.");
+        room.devices = members.iter().filter_map(|e| e.rf_address()).collect();
+        room.window_open = members
+            .iter()
+            .any(|e| matches!(e, Device::ShutterContact(sc) if sc.open));
+        room.battery_low = members.iter().any(|e| e.battery_low());
+        room.valve_position =
+            average(heaters.iter().map(|e| e.valve_position as f64)).round() as u8;
+
+        match wall {
+            Some(wt) => {
+                room.temperature_set = wt.temperature_set;
+                room.temperature_measured = wt.temperature_measured;
+                room.mode = wt.mode;
+            }
+            None => {
+                // the heater thermostats report 0, if the measured temperature is not available
+                room.temperature_set = heaters
+                    .first()
+                    .map(|e| e.temperature_set)
+                    .unwrap_or_default();
+                room.temperature_measured = average(
+                    heaters
+                        .iter()
+                        .map(|e| e.temperature_measured)
+                        .filter(|e| *e > 0.0),
+                );
+                room.mode = heaters.first().map(|e| e.mode).unwrap_or_default();
+            }
         }
+    }
+}
 
-        Ok(Device::HeaterThermostat(
-            HeaterThermostat {
-                rf_address: u32::from_be_bytes([0, b[1], b[2], b[3]]),
-                serial: String::from_utf8_lossy(&b[4..14]).to_string(),
-                ..Default::default()
+/// returns the average of the values, or 0 if there is no value
+fn average(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), e| (sum + e, count + 1));
+    if count == 0 {
+        0.0
+    } else {
+        sum / count as f64
+    }
+}
+
+/// returns the only item, or an error if there is none or more than one
+fn unique<T>(
+    mut it: impl Iterator<Item = T>,
+    name: &str,
+    not_found: impl FnOnce() -> Error,
+) -> Result<T> {
+    match (it.next(), it.next()) {
+        (Some(e), None) => Ok(e),
+        (None, _) => Err(not_found()),
+        (Some(_), Some(_)) => Err(Error::AmbiguousName(name.to_string())),
+    }
+}
+
+/// decodes the S-Message, the answer of the cube to a command sent to the device with the RF address.
+/// `Error::DutyCycle` is returned, if the cube refused the command.
+pub fn from_message_s(recv: &str, rf_address: u32) -> Result<()> {
+    if !recv.starts_with("S:") {
+        return Err(Error::protocol("Message `S` expected.", recv));
+    }
+
+    let fields = recv
+        .trim_start_matches("S:")
+        .split(',')
+        .map(|e| u8::from_str_radix(e, 16))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| Error::protocol("Response not well-formatted.", recv))?;
+
+    match fields.as_slice() {
+        [_, 0, ..] => Ok(()),
+        [duty_cycle, _, ..] => Err(Error::DutyCycle {
+            rf_address,
+            duty_cycle: *duty_cycle,
+        }),
+        _ => Err(Error::protocol("Response not well-formatted.", recv)),
+    }
+}
+
+/// updates the duty cycle and the free memory slots of the cube status from the S-Message,
+/// which the cube sends as answer to every command. Malformed messages are ignored.
+pub(crate) fn update_status(recv: &str, status: &mut CubeStatus) {
+    let mut fields = recv
+        .trim_start_matches("S:")
+        .split(',')
+        .map(|e| u8::from_str_radix(e, 16));
+    if let (Some(Ok(duty_cycle)), Some(_), Some(Ok(free_memory_slots))) =
+        (fields.next(), fields.next(), fields.next())
+    {
+        status.duty_cycle = duty_cycle;
+        status.free_memory_slots = free_memory_slots;
+    }
+}
+
+/// encodes the rooms and devices as M-Message, as sent by the cube after connection.
+/// Devices of other types are written with their meta data as received.
+pub fn to_message_m(rooms: &Rooms, devices: &Devices) -> String {
+    format!(
+        "M:00,01,{}",
+        general_purpose::STANDARD.encode(encode_meta_data(rooms, devices))
+    )
+}
+
+/// encodes the meta data, which is used by the M-Message and the `m:` command
+fn encode_meta_data(rooms: &Rooms, devices: &Devices) -> Vec<u8> {
+    let mut b = vec![0x56u8, 0x02];
+
+    b.push(rooms.len() as u8);
+    for room in rooms {
+        b.push(room.room_id);
+        push_name(&mut b, &room.name);
+        b.extend_from_slice(&room.rf_address.to_be_bytes()[1..]);
+    }
+
+    let known = devices.iter().filter_map(Device::meta).collect::<Vec<_>>();
+    b.push(known.len() as u8);
+    for (device_type, rf_address, serial, name, room_id) in known {
+        b.push(device_type);
+        b.extend_from_slice(&rf_address.to_be_bytes()[1..]);
+        b.extend(format!("{:<10.10}", serial).as_bytes());
+        push_name(&mut b, name);
+        b.push(room_id);
+    }
+    b.push(0x01);
+    b
+}
+
+/// encodes the status of all devices as L-Message
+pub fn to_message_l(devices: &Devices) -> String {
+    let mut b = Vec::new();
+
+    let flags = |bits: u16, battery_low: bool, error: bool, valid: bool| {
+        (0x0218u16 | bits)
+            | if battery_low { 0x80 } else { 0 }
+            | if error { 0x800 } else { 0 }
+            | if valid { 0x1000 } else { 0 }
+    };
+
+    for dev in devices {
+        match dev {
+            Device::HeaterThermostat(ts) => {
+                b.push(11);
+                b.extend_from_slice(&ts.rf_address.to_be_bytes()[1..]);
+                b.push(0x09);
+                b.extend_from_slice(
+                    &flags(ts.mode as u16, ts.battery_low, ts.error, ts.valid).to_be_bytes(),
+                );
+                b.push(ts.valve_position);
+                b.push((ts.temperature_set * 2.0) as u8);
+                b.extend_from_slice(
+                    &((ts.temperature_measured * 10.0).round() as u16).to_be_bytes(),
+                );
+                b.push(0x00);
+            }
+            Device::WallThermostat(wt) => {
+                let measured = (wt.temperature_measured * 10.0).round() as u16;
+                b.push(12);
+                b.extend_from_slice(&wt.rf_address.to_be_bytes()[1..]);
+                b.push(0x09);
+                b.extend_from_slice(
+                    &flags(wt.mode as u16, wt.battery_low, wt.error, wt.valid).to_be_bytes(),
+                );
+                b.push(0x00);
+                b.push(((wt.temperature_set * 2.0) as u8 & 0x7f) | ((measured >> 1) & 0x80) as u8);
+                b.extend_from_slice(&[0x00, 0x00, 0x00]);
+                b.push(measured as u8);
+            }
+            Device::ShutterContact(sc) => {
+                b.push(6);
+                b.extend_from_slice(&sc.rf_address.to_be_bytes()[1..]);
+                b.push(0x09);
+                b.extend_from_slice(
+                    &flags(
+                        if sc.open { 0x02 } else { 0 },
+                        sc.battery_low,
+                        sc.error,
+                        sc.valid,
+                    )
+                    .to_be_bytes(),
+                );
             }
-        ))
+            Device::Other(_) | Device::NotSupported => (),
+        }
+    }
+
+    format!("L:{}", general_purpose::STANDARD.encode(b))
+}
+
+/// encodes the device as N-Message, as sent by the cube after pairing a new device
+pub fn to_message_n(device: &Device) -> Result<String> {
+    let (device_type, rf_address, serial, _, _) =
+        device.meta().ok_or(Error::DeviceNotSupported(0))?;
+    let mut b = vec![device_type];
+    b.extend_from_slice(&rf_address.to_be_bytes()[1..]);
+    b.extend(format!("{:<10.10}", serial).as_bytes());
+    b.push(0x00);
+    Ok(format!("N:{}", general_purpose::STANDARD.encode(b)))
+}
+
+/// encodes the state of the cube as H-Message
+pub fn to_message_h(status: &CubeStatus) -> String {
+    format!(
+        "H:{},{:06x},{:04x},00000000,{:08x},{:02x},{:02x},{:02x}{:02x}{:02x},{:02x}{:02x},{:02x},{:04x}",
+        status.serial,
+        status.rf_address & 0xffffff,
+        status.firmware,
+        status.http_connection_id,
+        status.duty_cycle,
+        status.free_memory_slots,
+        status.year.saturating_sub(2000).min(0xff),
+        status.month,
+        status.day,
+        status.hour,
+        status.minute,
+        status.state_time,
+        status.ntp_counter
+    )
+}
+
+/// encodes the configuration of the device as C-Message
+pub fn to_message_c(settings: &DeviceSettings) -> String {
+    let mut b = vec![0u8];
+    b.extend_from_slice(&settings.rf_address.to_be_bytes()[1..]);
+    b.extend([
+        settings.device_type,
+        settings.room_id,
+        settings.firmware,
+        settings.test_result,
+    ]);
+    b.extend(format!("{:<10.10}", settings.serial).as_bytes());
+
+    if let Some(ts) = &settings.thermostat {
+        b.extend(encode_thermostat_settings(ts));
+
+        for day in &ts.weekly_program {
+            b.extend(encode_day_program(day));
+        }
+    }
+    b.extend_from_slice(&settings.data);
+    b[0] = (b.len() - 1) as u8;
+
+    format!(
+        "C:{:06x},{}",
+        settings.rf_address & 0xffffff,
+        general_purpose::STANDARD.encode(b)
+    )
+}
+
+/// encodes the settings of the heater thermostat without the time scheduling, as in the C-Message:
+/// 7 bytes of temperatures, followed by 4 bytes of valve settings
+fn encode_thermostat_settings(ts: &ThermostatSettings) -> [u8; 11] {
+    let boost_duration = if ts.boost_duration >= 60 {
+        7
+    } else {
+        (ts.boost_duration / 5).min(6)
+    };
+    [
+        (ts.comfort_temperature * 2.0) as u8,
+        (ts.eco_temperature * 2.0) as u8,
+        (ts.max_temperature * 2.0) as u8,
+        (ts.min_temperature * 2.0) as u8,
+        ((ts.temperature_offset + 3.5) * 2.0).round() as u8,
+        (ts.window_open_temperature * 2.0) as u8,
+        (ts.window_open_duration / 5).min(0xff) as u8,
+        (boost_duration << 5) | (ts.boost_valve / 5).min(0x1f),
+        (ts.decalcification_day.min(7) << 5) | ts.decalcification_hour.min(0x1f),
+        (ts.max_valve.min(100) as f64 * 255.0 / 100.0).round() as u8,
+        (ts.valve_offset.min(100) as f64 * 255.0 / 100.0).round() as u8,
+    ]
+}
+
+/// encodes the time scheduling of one day. Every day has 13 points,
+/// the unused points repeat the end of the day.
+pub(crate) fn encode_day_program(day: &DayProgram) -> Vec<u8> {
+    let last = ProgramPoint {
+        temperature: day.last().map(|e| e.temperature).unwrap_or_default(),
+        until: 24 * 60,
+    };
+    day.iter()
+        .chain(std::iter::repeat(&last))
+        .take(13)
+        .flat_map(|point| {
+            ((((point.temperature * 2.0) as u16) << 9) | (point.until / 5).min(0x1ff)).to_be_bytes()
+        })
+        .collect()
+}
+
+/// appends the name with its length, names are limited to 255 bytes
+fn push_name(b: &mut Vec<u8>, name: &str) {
+    let name = &name.as_bytes()[..name.len().min(255)];
+    b.push(name.len() as u8);
+    b.extend_from_slice(name);
+}
 
+/// takes the next `length` bytes from the buffer, without panicking on short data
+fn take(b: &mut VecDeque<u8>, length: usize, recv: &str) -> Result<Vec<u8>> {
+    if length > b.len() {
+        return Err(Error::protocol("Unexpected data length.", recv));
+    }
+    Ok(b.drain(..length).collect())
+}
+
+/// decodes the base64 payload of the message
+fn decode_base64(payload: &str, recv: &str) -> Result<Vec<u8>> {
+    general_purpose::STANDARD
+        .decode(payload)
+        .map_err(|e| Error::protocol(format!("Invalid base64 payload: {}.", e), recv))
 }
 
-/// Device mode, can be Manual, Auto (other mode, such as Vaccation etc is not supported by now)
-#[derive(Debug, Default, Copy, Clone)]
+/// Device mode, can be Manual, Auto, Vacation or Boost
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DeviceMode {
     /// temperature set point is manually set, won't change automatically
     Manual = 1,
     /// temperature set point will be changed automatically according the time scheduling
     #[default]
     Auto = 0,
+    /// temperature set point is kept until the end of the vacation, then the mode returns to Auto
+    Vacation = 2,
+    /// the valve is opened for the boost duration, then the previous mode is restored
+    Boost = 3,
+}
+
+impl DeviceMode {
+    /// decodes the mode from the lowest 2 bits of the status flags
+    pub(crate) fn from_bits(bits: u8) -> Self {
+        match bits & 0x03 {
+            1 => DeviceMode::Manual,
+            2 => DeviceMode::Vacation,
+            3 => DeviceMode::Boost,
+            _ => DeviceMode::Auto,
+        }
+    }
+}
+
+/// date and time, e.g. the end of the vacation
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DateTime {
+    /// year, between 2000 and 2127
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    /// minute, the cube uses a resolution of 30 minutes
+    pub minute: u8,
+}
+
+impl DateTime {
+    /// returns an error, if a field is out of the range, which the cube supports
+    pub fn validate(&self) -> Result<()> {
+        if !(2000..=2127).contains(&self.year)
+            || !(1..=12).contains(&self.month)
+            || !(1..=31).contains(&self.day)
+            || self.hour >= 24
+            || self.minute >= 60
+        {
+            return Err(Error::InvalidDate(format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}",
+                self.year, self.month, self.day, self.hour, self.minute
+            )));
+        }
+        Ok(())
+    }
 }
 
 /// DeviceConfig is used to change the device configuration, like temperature set point
@@ -248,6 +1274,7 @@ pub struct DeviceConfig {
     temperature: f64,
     rf_address: u32,
     room_id: u8,
+    until: Option<DateTime>,
 }
 
 impl DeviceConfig {
@@ -283,6 +1310,12 @@ impl DeviceConfig {
         self
     }
 
+    /// set the end of the vacation mode
+    pub fn set_until(mut self, until: DateTime) -> Self {
+        self.until = Some(until);
+        self
+    }
+
     /// build the command payload
     pub fn build(&self) -> String {
         let mut data = vec![0x00u8, 0x04, 0x40, 0x00, 0x00, 0x00];
@@ -292,13 +1325,27 @@ impl DeviceConfig {
         data.push(self.room_id);
 
         data.push(((self.mode as u8) << 6) | (((self.temperature * 2.0) as u8) & 0x3f));
+        if let (DeviceMode::Vacation, Some(until)) = (self.mode, self.until) {
+            // the month is split into the upper 3 bits of the first and the upper bit of the second byte
+            data.push(((until.month & 0x0e) << 4) | (until.day & 0x1f));
+            data.push(((until.month & 0x01) << 7) | (until.year.saturating_sub(2000) as u8 & 0x7f));
+            data.push(until.hour.wrapping_mul(2).wrapping_add(until.minute / 30));
+        }
         let mut cmd = "s:".to_string();
         cmd.push_str(&general_purpose::STANDARD.encode(data));
         cmd.push_str("\r\n");
         cmd
     }
-}
 
+    /// build the command payload like `build()`, but checks the end of the vacation before.
+    /// `Error::InvalidDate` is returned, if the end of the vacation is out of range.
+    pub fn try_build(&self) -> Result<String> {
+        if let (DeviceMode::Vacation, Some(until)) = (self.mode, self.until) {
+            until.validate()?;
+        }
+        Ok(self.build())
+    }
+}
 
 /// PairingConfig includes the configuration for pairing a new device
 #[derive(Debug)]
@@ -314,21 +1361,56 @@ impl PairingConfig {
 
     pub fn build(&self) -> String {
         // build n-message
-        format!("n:{:04x}\r\n", u16::try_from(self.timeout.as_secs()).unwrap_or(0xffff))
+        format!(
+            "n:{:04x}\r\n",
+            u16::try_from(self.timeout.as_secs()).unwrap_or(0xffff)
+        )
     }
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
 
+    /// H-Message of the test house, sent by the cube on connection
+    pub(crate) const MESSAGE_H: &str =
+        "H:KEQ0523864,0b5e9b,0113,00000000,477719c0,00,32,0d0c09,1404,03,0000";
+
+    /// M-Message of the test house with five rooms, one heater thermostat per room
+    pub(crate) const MESSAGE_M: &str = "M:00,01,VgIFAQdCZWRyb29tGuXTAgtMaXZpbmcgcm9vbRrqAQMHS2l0Y2hlbhrnLgQGT2ZmaWNlGun/BQhCYXRocm9vbRrlGAUBGuXTT0VRMjEyMTY0NAdCZWRyb29tAQEa6gFPRVEyMTIyMzU2C0xpdmluZyByb29tAgEa5y5PRVEyMTIxNDc2B0tpdGNoZW4DARrp/09FUTIxMjIzNTMGT2ZmaWNlBAEa5RhPRVEyMTIxNzc0CEJhdGhyb29tBQE=";
+
+    /// L-Message of the test house, the bedroom at 20°C with low battery
+    pub(crate) const MESSAGE_L: &str =
+        "L:CxrnLgkSGQAmAM0ACxrlGAkSGQAKAAAACxrqAQkSGQApAOMACxrp/wkSGRYnAMoACxrl0wkSmQAoAOAA";
+
+    /// returns the rooms and the devices with the status of the test house
+    pub(crate) fn house() -> (Rooms, Devices) {
+        let (rooms, mut devices) = from_message_m(MESSAGE_M).unwrap();
+        from_message_l(MESSAGE_L, &mut devices).unwrap();
+        (rooms, devices)
+    }
+
+    /// returns the H-, M- and L-Message of the test house, as sent by the cube on connection
+    pub(crate) fn handshake() -> String {
+        format!("{}\r\n{}\r\n{}\r\n", MESSAGE_H, MESSAGE_M, MESSAGE_L)
+    }
+
+    /// returns the trace of the handshake of the test house, followed by the lines
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    pub(crate) fn trace(lines: &str) -> String {
+        format!(
+            "0.000 < {}\n0.000 < {}\n0.000 < {}\n{}",
+            MESSAGE_H, MESSAGE_M, MESSAGE_L, lines
+        )
+    }
+
     #[test]
     fn test_message_m_0() {
         // Test data from: https://github.com/Bouni/max-cube-protocol/blob/master/M-Message.md
 
         let data =  "M:00,01,VgIEAQNCYWQK7WkCBEJ1cm8K8wADCldvaG56aW1tZXIK8wwEDFNjaGxhZnppbW1lcgr1QAUCCu1pS0VRMDM3ODA0MAZIVCBCYWQBAgrzAEtFUTAzNzk1NDQHSFQgQnVybwICCvMMS0VRMDM3OTU1NhlIVCBXb2huemltbWVyIEJhbGtvbnNlaXRlAwIK83lLRVEwMzc5NjY1GkhUIFdvaG56aW1tZXIgRmVuc3RlcnNlaXRlAwIK9UBLRVEwMzgwMTIwD0hUIFNjaGxhZnppbW1lcgQB";
 
-        let (rooms, _) = from_message_m(&data).unwrap();
+        let (rooms, _) = from_message_m(data).unwrap();
 
         // println!("{:?}, {:?}", rooms, devices);
         assert_eq!(rooms.len(), 4);
@@ -336,11 +1418,40 @@ mod test {
         assert_eq!(rooms[0].rf_address, 716137);
         assert_eq!(rooms[3].name, "Schlafzimmer");
         assert_eq!(rooms[3].rf_address, 718144);
+
+        // the heater thermostats plus (type 2) are kept and written back unchanged
+        let (rooms, devices) = from_message_m(data).unwrap();
+        assert_eq!(devices.len(), 5);
+        assert_eq!(devices[0].device_type(), Some(2));
+        assert_eq!(to_message_m(&rooms, &devices), data);
+    }
+
+    #[test]
+    fn test_message_m_other() {
+        // a heater thermostat and an eco button (type 5)
+        let data = "M:00,01,VgIBAQdLaXRjaGVuGucuAgEa5y5LRVEwMDAwMDAxB0tpdGNoZW4BBQq83ktFUTAwMDAwMDIDRWNvAQE=";
+
+        let (rooms, devices) = from_message_m(data).unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(
+            devices[1],
+            Device::Other(OtherDevice {
+                device_type: 5,
+                rf_address: 0x0abcde,
+                serial: "KEQ0000002".to_string(),
+                name: "Eco".to_string(),
+                room_id: 1,
+            })
+        );
+        assert_eq!(to_message_m(&rooms, &devices), data);
+        assert_eq!(
+            meta_data_command(&rooms, &devices),
+            format!("m:00,{}\r\n", &data[8..])
+        );
     }
 
     fn extract_message_m_1() -> (Rooms, Devices) {
-        let data = "M:00,01,VgIFAQdCZWRyb29tGuXTAgtMaXZpbmcgcm9vbRrqAQMHS2l0Y2hlbhrnLgQGT2ZmaWNlGun/BQhCYXRocm9vbRrlGAUBGuXTT0VRMjEyMTY0NAdCZWRyb29tAQEa6gFPRVEyMTIyMzU2C0xpdmluZyByb29tAgEa5y5PRVEyMTIxNDc2B0tpdGNoZW4DARrp/09FUTIxMjIzNTMGT2ZmaWNlBAEa5RhPRVEyMTIxNzc0CEJhdGhyb29tBQE=";
-        from_message_m(&data).unwrap()
+        from_message_m(MESSAGE_M).unwrap()
     }
 
     #[test]
@@ -364,10 +1475,8 @@ mod test {
 
     #[test]
     fn test_message_l_1() {
-        let data =
-            "L:CxrnLgkSGQAmAM0ACxrlGAkSGQAKAAAACxrqAQkSGQApAOMACxrp/wkSGRYnAMoACxrl0wkSmQAoAOAA";
         let (_, mut devices) = extract_message_m_1();
-        from_message_l(data, &mut devices).unwrap();
+        from_message_l(MESSAGE_L, &mut devices).unwrap();
         // println!("{:?}", devices);
 
         match devices.get(2).unwrap() {
@@ -380,6 +1489,670 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_message_l_2() {
+        let mut devices = vec![
+            Device::WallThermostat(WallThermostat {
+                rf_address: 0x123456,
+                ..Default::default()
+            }),
+            Device::ShutterContact(ShutterContact {
+                rf_address: 0x0a0b0c,
+                ..Default::default()
+            }),
+        ];
+        let data = general_purpose::STANDARD.encode([
+            0x0c, 0x12, 0x34, 0x56, 0x00, 0x12, 0x19, 0x00, 0xaa, 0x00, 0x00, 0x00, 0x0b, 0x06,
+            0x0a, 0x0b, 0x0c, 0x00, 0x12, 0x92,
+        ]);
+        from_message_l(&format!("L:{}", data), &mut devices).unwrap();
+
+        match &devices[0] {
+            Device::WallThermostat(wt) => {
+                assert_eq!(wt.temperature_set, 21.0);
+                assert_eq!(wt.temperature_measured, 26.7);
+                assert_eq!(wt.mode, DeviceMode::Manual);
+                assert!(wt.valid);
+            }
+            _ => panic!("Wrong device type!"),
+        }
+        match &devices[1] {
+            Device::ShutterContact(sc) => {
+                assert!(sc.open);
+                assert!(sc.battery_low);
+            }
+            _ => panic!("Wrong device type!"),
+        }
+    }
+
+    #[test]
+    fn test_update_rooms() {
+        let (mut rooms, mut devices) = house();
+        devices.push(Device::ShutterContact(ShutterContact {
+            rf_address: 0x0a0b0c,
+            room_id: 3,
+            open: true,
+            ..Default::default()
+        }));
+        update_rooms(&mut rooms, &devices);
+
+        let kitchen = room_by_name(&rooms, "Kitchen").unwrap();
+        assert_eq!(kitchen.devices, vec![1763118, 0x0a0b0c]);
+        assert_eq!(kitchen.temperature_set, 19.0);
+        assert_eq!(kitchen.temperature_measured, 20.5);
+        assert_eq!(kitchen.mode, DeviceMode::Manual);
+        assert!(kitchen.window_open);
+        assert!(!kitchen.battery_low);
+
+        // the wall thermostat is preferred
+        devices.push(Device::WallThermostat(WallThermostat {
+            rf_address: 0x123456,
+            room_id: 3,
+            temperature_set: 21.5,
+            temperature_measured: 20.9,
+            mode: DeviceMode::Auto,
+            battery_low: true,
+            ..Default::default()
+        }));
+        update_rooms(&mut rooms, &devices);
+
+        let kitchen = room_by_name(&rooms, "Kitchen").unwrap();
+        assert_eq!(kitchen.devices.len(), 3);
+        assert_eq!(kitchen.temperature_set, 21.5);
+        assert_eq!(kitchen.temperature_measured, 20.9);
+        assert_eq!(kitchen.mode, DeviceMode::Auto);
+        assert!(kitchen.battery_low);
+    }
+
+    #[test]
+    fn test_message_wrong_type() {
+        let data = "L:CxrnLgkSGQAmAM0A";
+        match from_message_m(data) {
+            Err(Error::Protocol { message, .. }) => assert_eq!(message, data),
+            r => panic!("Protocol error expected, {:?} received.", r),
+        }
+    }
+
+    #[test]
+    fn test_truncated_messages() {
+        // all decoders shall return an error instead of panicking on corrupted data
+        let data_m = &MESSAGE_M[8..];
+        let data_l = &MESSAGE_L[2..];
+        let bytes_m = general_purpose::STANDARD.decode(data_m).unwrap();
+        let bytes_l = general_purpose::STANDARD.decode(data_l).unwrap();
+
+        for n in 0..bytes_m.len() {
+            let part = general_purpose::STANDARD.encode(&bytes_m[..n]);
+            let _ = from_message_m(&format!("M:00,01,{}", part));
+            let _ = from_message_n(&format!("N:{}", part));
+        }
+
+        for n in 0..bytes_l.len() {
+            let mut corrupted = bytes_l.clone();
+            corrupted[n] = 0xff;
+            let (_, mut devices) = extract_message_m_1();
+            let _ = from_message_l(
+                &format!("L:{}", general_purpose::STANDARD.encode(&bytes_l[..n])),
+                &mut devices,
+            );
+            let _ = from_message_l(
+                &format!("L:{}", general_purpose::STANDARD.encode(&corrupted)),
+                &mut devices,
+            );
+        }
+
+        assert!(from_message_m("").is_err());
+        assert!(from_message_n("").is_err());
+    }
+
+    #[test]
+    fn test_message_s() {
+        assert!(from_message_s("S:00,0,31", 1762771).is_ok());
+        match from_message_s("S:64,1,0", 1762771) {
+            Err(Error::DutyCycle {
+                rf_address,
+                duty_cycle,
+            }) => {
+                assert_eq!(rf_address, 1762771);
+                assert_eq!(duty_cycle, 100);
+            }
+            r => panic!("Duty cycle error expected, {:?} received.", r),
+        }
+        assert!(from_message_s("S:", 1762771).is_err());
+    }
+
+    #[test]
+    fn test_encode_messages() {
+        let (rooms, devices) = house();
+
+        let (rooms_decoded, mut devices_decoded) =
+            from_message_m(&to_message_m(&rooms, &devices)).unwrap();
+        from_message_l(&to_message_l(&devices), &mut devices_decoded).unwrap();
+        assert_eq!(rooms_decoded, rooms);
+        assert_eq!(devices_decoded, devices);
+
+        let n = to_message_n(&devices[0]).unwrap();
+        match from_message_n(&n).unwrap() {
+            Device::HeaterThermostat(ts) => assert_eq!(ts.serial, "OEQ2121644"),
+            _ => panic!("Wrong device type!"),
+        }
+    }
+
+    #[test]
+    fn test_message_h() {
+        let data = "H:KEQ0523864,097f2c,0113,00000000,477719c0,00,32,0d0c09,1404,03,0000";
+        let status = from_message_h(data).unwrap();
+        assert_eq!(status.serial, "KEQ0523864");
+        assert_eq!(status.rf_address, 0x097f2c);
+        assert_eq!(status.free_memory_slots, 50);
+        assert_eq!((status.year, status.month, status.day), (2013, 12, 9));
+        assert_eq!((status.hour, status.minute), (20, 4));
+        assert_eq!(to_message_h(&status), data);
+
+        assert!(from_message_h("H:KEQ0523864,097f2c").is_err());
+    }
+
+    #[test]
+    fn test_message_c() {
+        let settings = DeviceSettings {
+            rf_address: 0x1ae5d3,
+            device_type: 1,
+            room_id: 1,
+            serial: "OEQ2121644".to_string(),
+            thermostat: Some(ThermostatSettings::default()),
+            ..Default::default()
+        };
+        let data = to_message_c(&settings);
+        assert!(data.starts_with("C:1ae5d3,0hrl0wEBAABPRVEyMTIxNjQ0"));
+        assert_eq!(from_message_c(&data).unwrap(), settings);
+
+        // truncated messages
+        let b = general_purpose::STANDARD
+            .decode(data.split(',').nth(1).unwrap())
+            .unwrap();
+        for n in 0..b.len() {
+            let part = general_purpose::STANDARD.encode(&b[..n]);
+            assert!(from_message_c(&format!("C:1ae5d3,{}", part)).is_err());
+        }
+    }
+
+    mod roundtrip {
+        use super::super::*;
+        use proptest::collection::{btree_set, vec};
+        use proptest::prelude::*;
+
+        fn temperature() -> impl Strategy<Value = f64> {
+            (0u8..64).prop_map(|e| e as f64 / 2.0)
+        }
+
+        fn mode() -> impl Strategy<Value = DeviceMode> {
+            prop_oneof![
+                Just(DeviceMode::Auto),
+                Just(DeviceMode::Manual),
+                Just(DeviceMode::Vacation),
+                Just(DeviceMode::Boost),
+            ]
+        }
+
+        fn thermostat() -> impl Strategy<Value = HeaterThermostat> {
+            (
+                "[A-Z0-9]{10}",
+                "[a-zA-Z0-9 äöü]{0,20}",
+                any::<u8>(),
+                any::<u8>(),
+                any::<u8>().prop_map(|e| e as f64 / 2.0),
+                any::<u16>().prop_map(|e| e as f64 / 10.0),
+                mode(),
+                any::<(bool, bool, bool)>(),
+            )
+                .prop_map(
+                    |(
+                        serial,
+                        name,
+                        room_id,
+                        valve_position,
+                        temperature_set,
+                        temperature_measured,
+                        mode,
+                        (battery_low, error, valid),
+                    )| {
+                        HeaterThermostat {
+                            rf_address: 0,
+                            serial,
+                            name,
+                            room_id,
+                            valve_position,
+                            temperature_set,
+                            temperature_measured,
+                            mode,
+                            battery_low,
+                            error,
+                            valid,
+                        }
+                    },
+                )
+        }
+
+        fn wall_thermostat() -> impl Strategy<Value = WallThermostat> {
+            (
+                "[A-Z0-9]{10}",
+                "[a-zA-Z0-9 äöü]{0,20}",
+                any::<u8>(),
+                (0u8..128).prop_map(|e| e as f64 / 2.0),
+                (0u16..512).prop_map(|e| e as f64 / 10.0),
+                mode(),
+                any::<(bool, bool, bool)>(),
+            )
+                .prop_map(
+                    |(
+                        serial,
+                        name,
+                        room_id,
+                        temperature_set,
+                        temperature_measured,
+                        mode,
+                        (battery_low, error, valid),
+                    )| {
+                        WallThermostat {
+                            rf_address: 0,
+                            serial,
+                            name,
+                            room_id,
+                            temperature_set,
+                            temperature_measured,
+                            mode,
+                            battery_low,
+                            error,
+                            valid,
+                        }
+                    },
+                )
+        }
+
+        fn shutter_contact() -> impl Strategy<Value = ShutterContact> {
+            (
+                "[A-Z0-9]{10}",
+                "[a-zA-Z0-9 äöü]{0,20}",
+                any::<u8>(),
+                any::<(bool, bool, bool, bool)>(),
+            )
+                .prop_map(
+                    |(serial, name, room_id, (open, battery_low, error, valid))| ShutterContact {
+                        rf_address: 0,
+                        serial,
+                        name,
+                        room_id,
+                        open,
+                        battery_low,
+                        error,
+                        valid,
+                    },
+                )
+        }
+
+        fn other_device() -> impl Strategy<Value = OtherDevice> {
+            (
+                prop::sample::select(vec![0u8, 2, 5, 6]),
+                "[A-Z0-9]{10}",
+                "[a-zA-Z0-9 äöü]{0,20}",
+                any::<u8>(),
+            )
+                .prop_map(|(device_type, serial, name, room_id)| OtherDevice {
+                    device_type,
+                    rf_address: 0,
+                    serial,
+                    name,
+                    room_id,
+                })
+        }
+
+        /// devices of all types with unique RF addresses
+        fn devices() -> impl Strategy<Value = Devices> {
+            let device = prop_oneof![
+                thermostat().prop_map(Device::HeaterThermostat),
+                wall_thermostat().prop_map(Device::WallThermostat),
+                shutter_contact().prop_map(Device::ShutterContact),
+                other_device().prop_map(Device::Other),
+            ];
+            vec(device, 0..10).prop_map(|e| {
+                e.into_iter()
+                    .enumerate()
+                    .map(|(i, dev)| {
+                        let rf_address = 0x1ae5d3 + i as u32;
+                        match dev {
+                            Device::HeaterThermostat(ts) => {
+                                Device::HeaterThermostat(HeaterThermostat { rf_address, ..ts })
+                            }
+                            Device::WallThermostat(wt) => {
+                                Device::WallThermostat(WallThermostat { rf_address, ..wt })
+                            }
+                            Device::ShutterContact(sc) => {
+                                Device::ShutterContact(ShutterContact { rf_address, ..sc })
+                            }
+                            Device::Other(od) => Device::Other(OtherDevice { rf_address, ..od }),
+                            Device::NotSupported => Device::NotSupported,
+                        }
+                    })
+                    .collect()
+            })
+        }
+
+        fn rooms() -> impl Strategy<Value = Rooms> {
+            vec(
+                (any::<u8>(), "[a-zA-Z0-9 äöü]{0,20}", 0u32..0x1000000).prop_map(
+                    |(room_id, name, rf_address)| Room {
+                        room_id,
+                        name,
+                        rf_address,
+                        ..Default::default()
+                    },
+                ),
+                0..10,
+            )
+        }
+
+        fn day_program() -> impl Strategy<Value = DayProgram> {
+            (btree_set(1u16..288, 0..13), vec(temperature(), 13)).prop_map(
+                |(until, temperatures)| {
+                    until
+                        .into_iter()
+                        .take(12)
+                        .chain([288])
+                        .zip(temperatures)
+                        .map(|(until, temperature)| ProgramPoint {
+                            temperature,
+                            until: until * 5,
+                        })
+                        .collect()
+                },
+            )
+        }
+
+        fn thermostat_settings() -> impl Strategy<Value = ThermostatSettings> {
+            (
+                (
+                    temperature(),
+                    temperature(),
+                    temperature(),
+                    temperature(),
+                    temperature(),
+                ),
+                (0u8..=14).prop_map(|e| e as f64 / 2.0 - 3.5),
+                (0u16..=255).prop_map(|e| e * 5),
+                prop::sample::select(vec![0u8, 5, 10, 15, 20, 25, 30, 60]),
+                (0u8..=20).prop_map(|e| e * 5),
+                (0u8..7, 0u8..24, 0u8..=100, 0u8..=100),
+                vec(day_program(), 7),
+            )
+                .prop_map(
+                    |(
+                        (comfort, eco, max, min, window),
+                        offset,
+                        duration,
+                        boost_duration,
+                        boost_valve,
+                        (day, hour, max_valve, valve_offset),
+                        program,
+                    )| {
+                        ThermostatSettings {
+                            comfort_temperature: comfort,
+                            eco_temperature: eco,
+                            max_temperature: max,
+                            min_temperature: min,
+                            temperature_offset: offset,
+                            window_open_temperature: window,
+                            window_open_duration: duration,
+                            boost_duration,
+                            boost_valve,
+                            decalcification_day: day,
+                            decalcification_hour: hour,
+                            max_valve,
+                            valve_offset,
+                            weekly_program: program.try_into().unwrap(),
+                        }
+                    },
+                )
+        }
+
+        fn device_settings() -> impl Strategy<Value = DeviceSettings> {
+            (
+                0u32..0x1000000,
+                0u8..=5,
+                any::<u8>(),
+                any::<u8>(),
+                any::<u8>(),
+                "[A-Z0-9]{10}",
+                thermostat_settings(),
+                vec(any::<u8>(), 0..45),
+            )
+                .prop_map(
+                    |(
+                        rf_address,
+                        device_type,
+                        room_id,
+                        firmware,
+                        test_result,
+                        serial,
+                        ts,
+                        data,
+                    )| {
+                        DeviceSettings {
+                            rf_address,
+                            device_type,
+                            room_id,
+                            firmware,
+                            test_result,
+                            serial,
+                            thermostat: (device_type == 1 || device_type == 2).then_some(ts),
+                            data,
+                        }
+                    },
+                )
+        }
+
+        fn cube_status() -> impl Strategy<Value = CubeStatus> {
+            (
+                ("[A-Z0-9]{10}", 0u32..0x1000000, any::<u16>(), any::<u32>()),
+                any::<(u8, u8)>(),
+                (2000u16..=2255, any::<(u8, u8, u8, u8)>()),
+                any::<(u8, u16)>(),
+            )
+                .prop_map(
+                    |(
+                        (serial, rf_address, firmware, http_connection_id),
+                        (duty_cycle, free_memory_slots),
+                        (year, (month, day, hour, minute)),
+                        (state_time, ntp_counter),
+                    )| {
+                        CubeStatus {
+                            serial,
+                            rf_address,
+                            firmware,
+                            http_connection_id,
+                            duty_cycle,
+                            free_memory_slots,
+                            year,
+                            month,
+                            day,
+                            hour,
+                            minute,
+                            state_time,
+                            ntp_counter,
+                        }
+                    },
+                )
+        }
+
+        proptest! {
+            #[test]
+            fn test_roundtrip_m(rooms in rooms(), devices in devices()) {
+                // the M-Message contains the meta data only
+                let devices = devices
+                    .into_iter()
+                    .map(|e| match e {
+                        Device::HeaterThermostat(ts) => Device::HeaterThermostat(HeaterThermostat {
+                            rf_address: ts.rf_address,
+                            serial: ts.serial,
+                            name: ts.name,
+                            room_id: ts.room_id,
+                            ..Default::default()
+                        }),
+                        Device::WallThermostat(wt) => Device::WallThermostat(WallThermostat {
+                            rf_address: wt.rf_address,
+                            serial: wt.serial,
+                            name: wt.name,
+                            room_id: wt.room_id,
+                            ..Default::default()
+                        }),
+                        Device::ShutterContact(sc) => Device::ShutterContact(ShutterContact {
+                            rf_address: sc.rf_address,
+                            serial: sc.serial,
+                            name: sc.name,
+                            room_id: sc.room_id,
+                            ..Default::default()
+                        }),
+                        e => e,
+                    })
+                    .collect::<Devices>();
+                prop_assert_eq!(from_message_m(&to_message_m(&rooms, &devices)).unwrap(), (rooms, devices));
+            }
+
+            #[test]
+            fn test_roundtrip_l(devices in devices()) {
+                let (_, mut decoded) = from_message_m(&to_message_m(&Rooms::new(), &devices)).unwrap();
+                from_message_l(&to_message_l(&devices), &mut decoded).unwrap();
+                prop_assert_eq!(decoded, devices);
+            }
+
+            #[test]
+            fn test_roundtrip_n(rf_address in 0u32..0x1000000, serial in "[A-Z0-9]{10}", kind in 0..3) {
+                let device = match kind {
+                    0 => Device::HeaterThermostat(HeaterThermostat { rf_address, serial, ..Default::default() }),
+                    1 => Device::WallThermostat(WallThermostat { rf_address, serial, ..Default::default() }),
+                    _ => Device::ShutterContact(ShutterContact { rf_address, serial, ..Default::default() }),
+                };
+                prop_assert_eq!(from_message_n(&to_message_n(&device).unwrap()).unwrap(), device);
+            }
+
+            #[test]
+            fn test_roundtrip_c(settings in device_settings()) {
+                prop_assert_eq!(from_message_c(&to_message_c(&settings)).unwrap(), settings);
+            }
+
+            #[test]
+            fn test_roundtrip_h(status in cube_status()) {
+                prop_assert_eq!(from_message_h(&to_message_h(&status)).unwrap(), status);
+            }
+        }
+    }
+
+    #[test]
+    fn test_commands() {
+        let (rooms, devices) = extract_message_m_1();
+
+        // vacation until 29.08.2011 14:00
+        let s = mode_command(
+            &devices,
+            1762771,
+            DeviceMode::Vacation,
+            20.0,
+            Some(DateTime {
+                year: 2011,
+                month: 8,
+                day: 29,
+                hour: 14,
+                minute: 0,
+            }),
+        )
+        .unwrap();
+        let b = general_purpose::STANDARD
+            .decode(&s[2..s.len() - 2])
+            .unwrap();
+        assert_eq!(b[10..], [0xa8, 0x9d, 0x0b, 0x1c]);
+
+        let s = program_command(
+            &devices,
+            1762771,
+            2,
+            &ThermostatSettings::default().weekly_program[0],
+        )
+        .unwrap();
+        let b = general_purpose::STANDARD
+            .decode(&s[2..s.len() - 2])
+            .unwrap();
+        assert_eq!(
+            b[..11],
+            [0x00, 0x04, 0x10, 0x00, 0x00, 0x00, 0x1a, 0xe5, 0xd3, 0x01, 0x02]
+        );
+        assert_eq!(b[11..15], [0x44, 0x48, 0x55, 0x08]);
+        assert_eq!(b.len(), 11 + 26);
+        assert!(matches!(
+            program_command(&devices, 1, 2, &DayProgram::new()),
+            Err(Error::DeviceNotFound(1))
+        ));
+
+        assert_eq!(delete_command(&[0x1ae5d3, 0x1aea01]), "t:02,1,GuXTGuoB\r\n");
+
+        let s = meta_data_command(&rooms, &devices);
+        assert_eq!(
+            from_message_m(&format!("M:00,01,{}", &s[5..s.len() - 2])).unwrap(),
+            (rooms, devices)
+        );
+    }
+
+    #[test]
+    fn test_lookup() {
+        let (rooms, mut devices) = extract_message_m_1();
+
+        assert_eq!(
+            device_by_name(&devices, "Kitchen").unwrap().rf_address,
+            1763118
+        );
+        assert_eq!(room_by_name(&rooms, "Office").unwrap().room_id, 4);
+        assert_eq!(devices_in_room(&devices, 5).len(), 1);
+        assert_eq!(room_of(&rooms, &devices, 1762584).unwrap().name, "Bathroom");
+        assert_eq!(
+            thermostat_by_name(&rooms, &devices, "Bedroom").unwrap(),
+            1762771
+        );
+
+        assert!(matches!(
+            device_by_name(&devices, "Attic"),
+            Err(Error::NameNotFound(name)) if name == "Attic"
+        ));
+        assert!(matches!(
+            room_by_name(&rooms, "Attic"),
+            Err(Error::NameNotFound(_))
+        ));
+        assert!(matches!(
+            thermostat_by_name(&rooms, &devices, "Attic"),
+            Err(Error::NameNotFound(_))
+        ));
+        assert!(matches!(
+            room_of(&rooms, &devices, 1),
+            Err(Error::DeviceNotFound(1))
+        ));
+
+        // the room is used, if no device has the name
+        if let Device::HeaterThermostat(ts) = &mut devices[2] {
+            ts.name = "Oven".to_string();
+        }
+        assert_eq!(
+            thermostat_by_name(&rooms, &devices, "Kitchen").unwrap(),
+            1763118
+        );
+
+        devices.push(devices[0].clone());
+        assert!(matches!(
+            device_by_name(&devices, "Bedroom"),
+            Err(Error::AmbiguousName(_))
+        ));
+        assert!(matches!(
+            thermostat_by_name(&rooms, &devices, "Bedroom"),
+            Err(Error::AmbiguousName(_))
+        ));
+    }
+
     #[test]
     fn test_set_temperature() {
         let (_, d) = extract_message_m_1();
@@ -393,4 +2166,27 @@ mod test {
             .build();
         assert_eq!(s, "s:AARAAAAAGuXTAW4=\r\n");
     }
+
+    #[test]
+    fn test_try_build() {
+        let manual = DeviceConfig::new()
+            .set_address(1762771)
+            .set_room_id(1)
+            .set_mode(DeviceMode::Manual)
+            .set_temperature(23.0);
+        assert_eq!(manual.try_build().unwrap(), manual.build());
+
+        let vacation = DeviceConfig::new()
+            .set_address(1762771)
+            .set_room_id(1)
+            .set_mode(DeviceMode::Vacation)
+            .set_until(DateTime {
+                year: 2024,
+                month: 8,
+                day: 29,
+                hour: 200,
+                minute: 0,
+            });
+        assert!(matches!(vacation.try_build(), Err(Error::InvalidDate(_))));
+    }
 }
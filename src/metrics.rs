@@ -0,0 +1,176 @@
+//! Metrics of the cube and the devices in the text format of Prometheus, available with the feature `metrics`.
+//! The HTTP API answers `GET /metrics` with them, after refreshing the device status.
+//!
+//! | Metric                                | Devices                           |
+//! |---------------------------------------|-----------------------------------|
+//! | `maxcube_temperature_set_celsius`     | heater and wall thermostats       |
+//! | `maxcube_temperature_measured_celsius`| heater and wall thermostats       |
+//! | `maxcube_valve_position_percent`      | heater thermostats                |
+//! | `maxcube_battery_low`                 | all                               |
+//! | `maxcube_error`                       | all                               |
+//! | `maxcube_duty_cycle_percent`          | the cube                          |
+//! | `maxcube_free_memory_slots`           | the cube                          |
+//!
+//! The devices are labelled with `room` and `device`, the names as configured in the cube, and with
+//! `rf_address`, the RF address in hex, which is unique also for devices with the same name.
+//! The measured temperature is left out, as long as the device has not reported one.
+//! The duty cycle and the free memory slots are sent by the cube after connection and with the
+//! answer to every command.
+
+use std::fmt::Write;
+
+use crate::cube::MaxCube;
+use crate::messages::Device;
+use crate::transport::Transport;
+
+/// a metric with the name, help text and the samples
+struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    samples: Vec<(String, f64)>,
+}
+
+impl Gauge {
+    fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            samples: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, labels: &str, value: f64) {
+        self.samples.push((labels.to_string(), value));
+    }
+}
+
+/// returns the metrics of the cube and all devices, as last received from the cube
+/// # Examples
+///
+/// ```no_run
+/// # use eq3_max_cube_rs::{metrics, MaxCube};
+/// # futures::executor::block_on(async {
+/// let mut cube = MaxCube::new("172.22.51.191:62910").await.unwrap();
+/// cube.refresh().await.unwrap();
+/// print!("{}", metrics::encode(&cube));
+/// # });
+/// ```
+pub fn encode<T: Transport>(cube: &MaxCube<T>) -> String {
+    let mut temperature_set = Gauge::new(
+        "maxcube_temperature_set_celsius",
+        "Set point of the thermostat.",
+    );
+    let mut temperature_measured = Gauge::new(
+        "maxcube_temperature_measured_celsius",
+        "Temperature measured by the thermostat.",
+    );
+    let mut valve_position = Gauge::new("maxcube_valve_position_percent", "Position of the valve.");
+    let mut battery_low = Gauge::new("maxcube_battery_low", "1 if the battery is low.");
+    let mut error = Gauge::new("maxcube_error", "1 if the device reports an error.");
+
+    for dev in &cube.devices {
+        let room = cube
+            .rooms
+            .iter()
+            .find(|e| Some(e.room_id) == dev.room_id())
+            .map(|e| e.name.as_str())
+            .unwrap_or_default();
+        // the RF address keeps the series apart, if devices have the same name
+        let labels = format!(
+            "room=\"{}\",device=\"{}\",rf_address=\"{:06x}\"",
+            escape(room),
+            escape(dev.name().unwrap_or_default()),
+            dev.rf_address().unwrap_or_default()
+        );
+
+        let (set, measured, failed) = match dev {
+            Device::HeaterThermostat(ts) => {
+                valve_position.add(&labels, ts.valve_position as f64);
+                (Some(ts.temperature_set), ts.temperature_measured, ts.error)
+            }
+            Device::WallThermostat(wt) => {
+                (Some(wt.temperature_set), wt.temperature_measured, wt.error)
+            }
+            Device::ShutterContact(sc) => (None, 0.0, sc.error),
+            Device::Other(_) | Device::NotSupported => continue,
+        };
+        if let Some(set) = set {
+            temperature_set.add(&labels, set);
+        }
+        if measured > 0.0 {
+            temperature_measured.add(&labels, measured);
+        }
+        battery_low.add(&labels, dev.battery_low() as u8 as f64);
+        error.add(&labels, failed as u8 as f64);
+    }
+
+    let mut duty_cycle = Gauge::new(
+        "maxcube_duty_cycle_percent",
+        "Used duty cycle of the cube, it may only send 1% of the time.",
+    );
+    duty_cycle.add("", cube.status.duty_cycle as f64);
+    let mut free_memory_slots = Gauge::new(
+        "maxcube_free_memory_slots",
+        "Free memory slots of the cube for queued commands.",
+    );
+    free_memory_slots.add("", cube.status.free_memory_slots as f64);
+
+    let mut text = String::new();
+    for gauge in [
+        temperature_set,
+        temperature_measured,
+        valve_position,
+        battery_low,
+        error,
+        duty_cycle,
+        free_memory_slots,
+    ] {
+        let _ = writeln!(text, "# HELP {} {}", gauge.name, gauge.help);
+        let _ = writeln!(text, "# TYPE {} gauge", gauge.name);
+        for (labels, value) in gauge.samples {
+            if labels.is_empty() {
+                let _ = writeln!(text, "{} {}", gauge.name, value);
+            } else {
+                let _ = writeln!(text, "{}{{{}}} {}", gauge.name, labels, value);
+            }
+        }
+    }
+    text
+}
+
+/// escapes the label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::test::trace;
+    use crate::rt;
+    use crate::trace::{read_trace, Replay};
+
+    #[test]
+    fn test_encode() {
+        let replay = Replay::new(read_trace(trace("").as_bytes()).unwrap());
+        let cube = rt::block_on(MaxCube::from_transport(replay)).unwrap();
+        let text = encode(&cube);
+
+        assert!(text.contains("# TYPE maxcube_temperature_set_celsius gauge\n"));
+        assert!(text.contains(
+            "maxcube_temperature_set_celsius{room=\"Kitchen\",device=\"Kitchen\",rf_address=\"1ae72e\"} 19\n"
+        ));
+        assert!(text.contains(
+            "maxcube_temperature_measured_celsius{room=\"Kitchen\",device=\"Kitchen\",rf_address=\"1ae72e\"} 20.5\n"
+        ));
+        assert!(text.contains(
+            "maxcube_battery_low{room=\"Bedroom\",device=\"Bedroom\",rf_address=\"1ae5d3\"} 1\n"
+        ));
+        assert!(text.contains("maxcube_duty_cycle_percent 0\n"));
+        assert!(text.contains("maxcube_free_memory_slots 50\n"));
+        assert_eq!(escape("a \"b\"\\"), "a \\\"b\\\"\\\\");
+    }
+}
@@ -0,0 +1,833 @@
+//! Bridge between the cube and a MQTT broker, available with the feature `mqtt`.
+//!
+//! After each refresh, the state of the rooms and devices is published as retained JSON to
+//! `<prefix>/<room>/state` and `<prefix>/<room>/<device>/state`, if it has changed.
+//! Devices without a known room are published in the room `unassigned`, devices without name by
+//! their RF address in hex. The characters `/`, `+` and `#` in the names are replaced by `_`.
+//!
+//! Messages to `<prefix>/<room>/set` or `<prefix>/<room>/<device>/set` are turned into commands.
+//! The commands are applied to the whole room by the cube. The payload is either a temperature,
+//! e.g. `21.5`, which sets the manual mode, or JSON like `{"mode": "Boost"}`,
+//! `{"mode": "Manual", "temperature": 21.5}` or
+//! `{"mode": "Vacation", "temperature": 16, "until": {"year": 2024, "month": 1, "day": 6, "hour": 18, "minute": 0}}`.
+//! Without `temperature`, the current set point of the room is kept.
+//!
+//! The modes can be sent as plain words as well: `auto`, `manual` (or `heat`) and `boost`.
+//! `none` and `off` leave the boost mode and return to `auto`.
+//!
+//! `<prefix>/status` is `online` while the bridge is running, the broker publishes `offline` as last will.
+//! The topics of devices, which are removed or renamed while the bridge is running, are cleared.
+//! Retained topics of earlier runs are kept.
+//!
+//! With `set_discovery()`, the bridge publishes the discovery configs of Home Assistant as well:
+//! a `climate` entity for every heater and wall thermostat, a `binary_sensor` for every shutter contact
+//! and battery, and a `sensor` for the valve position of every heater thermostat.
+//! The bridge speaks MQTT 3.1.1 with QoS 0.
+
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, Instant};
+
+use futures::io::{AsyncReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::cube::MaxCube;
+use crate::error::{Error, Result};
+use crate::messages::{DateTime, Device, DeviceMode};
+use crate::rt::{self, ToSocketAddrs};
+use crate::transport::Transport;
+use crate::{DEFAULT_COMMAND_TIMEOUT, DEFAULT_CONNECT_TIMEOUT};
+
+/// Default interval of the status refresh
+pub const DEFAULT_MQTT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// MQTT packet types, in the upper 4 bits of the fixed header
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xc0;
+const DISCONNECT: u8 = 0xe0;
+
+/// payload of the `set` topics in JSON
+#[derive(Debug, Default, Deserialize)]
+struct SetRequest {
+    mode: Option<DeviceMode>,
+    temperature: Option<f64>,
+    until: Option<DateTime>,
+}
+
+/// MqttBridge is used to configure the bridge before connecting to the broker
+/// # Examples
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use eq3_max_cube_rs::{mqtt::MqttBridge, MaxCube};
+/// # futures::executor::block_on(async {
+/// let mut cube = MaxCube::new("172.22.51.191:62910").await.unwrap();
+/// MqttBridge::new()
+///     .set_prefix("heating")
+///     .set_interval(Duration::from_secs(60))
+///     .run("127.0.0.1:1883", &mut cube)
+///     .await
+///     .unwrap();
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct MqttBridge {
+    prefix: String,
+    client_id: String,
+    credentials: Option<(String, String)>,
+    interval: Duration,
+    discovery: Option<String>,
+}
+
+impl Default for MqttBridge {
+    fn default() -> Self {
+        Self {
+            prefix: "maxcube".to_string(),
+            client_id: "maxcube-bridge".to_string(),
+            credentials: None,
+            interval: DEFAULT_MQTT_INTERVAL,
+            discovery: None,
+        }
+    }
+}
+
+impl MqttBridge {
+    /// returns a instant of MqttBridge with default values
+    pub fn new() -> Self {
+        Self {
+            ..Default::default()
+        }
+    }
+
+    /// set the prefix of all topics, `maxcube` by default
+    pub fn set_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// set the client id, which is used to connect to the broker
+    pub fn set_client_id(mut self, client_id: &str) -> Self {
+        self.client_id = client_id.to_string();
+        self
+    }
+
+    /// set the user name and password for the broker
+    pub fn set_credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    /// set the interval of the status refresh
+    pub fn set_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// publishes the discovery configs of Home Assistant with the prefix, usually `homeassistant`
+    pub fn set_discovery(mut self, prefix: &str) -> Self {
+        self.discovery = Some(prefix.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// connects to the broker and runs the bridge, until one of the connections is lost
+    pub async fn run<A: ToSocketAddrs, T: Transport>(
+        &self,
+        broker: A,
+        cube: &mut MaxCube<T>,
+    ) -> Result<()> {
+        let stream = rt::timeout(DEFAULT_CONNECT_TIMEOUT, rt::connect(broker))
+            .await
+            .ok_or(Error::Timeout {
+                operation: "Connect to broker",
+                duration: DEFAULT_CONNECT_TIMEOUT,
+            })??;
+        self.run_with_transport(stream, cube).await
+    }
+
+    /// runs the bridge over an already connected transport to the broker, e.g. a TLS tunnel
+    pub async fn run_with_transport<B: Transport, T: Transport>(
+        &self,
+        broker: B,
+        cube: &mut MaxCube<T>,
+    ) -> Result<()> {
+        let mut client = Client::connect(broker, self).await?;
+        info!("Connected to broker.");
+        let result = self.bridge(&mut client, cube).await;
+
+        // the broker publishes the last will only, if the connection is lost without DISCONNECT
+        let _ = client.publish(&self.status_topic(), b"offline", true).await;
+        let _ = client.disconnect().await;
+        result
+    }
+
+    /// refreshes the state periodically and executes the commands in between
+    async fn bridge<B: Transport, T: Transport>(
+        &self,
+        client: &mut Client<B>,
+        cube: &mut MaxCube<T>,
+    ) -> Result<()> {
+        client
+            .subscribe(&[
+                format!("{}/+/set", self.prefix),
+                format!("{}/+/+/set", self.prefix),
+            ])
+            .await?;
+        client
+            .publish(&self.status_topic(), b"online", true)
+            .await?;
+
+        let mut published = HashMap::new();
+        loop {
+            cube.refresh().await?;
+            self.publish_state(client, cube, &mut published).await?;
+
+            let deadline = Instant::now() + self.interval;
+            while let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+                let Some((header, body)) = client.poll(wait).await? else {
+                    continue;
+                };
+                if header & 0xf0 != PUBLISH {
+                    continue;
+                }
+                let (topic, payload) = decode_publish(header, &body)?;
+                debug!("{} {:?}", topic, String::from_utf8_lossy(&payload));
+                match self.command(cube, &topic, &payload).await {
+                    // the new state is published immediately
+                    Ok(()) => break,
+                    Err(e) if !cube.is_connected() => return Err(e),
+                    Err(e) => warn!("Command to {} failed: {}", topic, e),
+                }
+            }
+        }
+    }
+
+    /// publishes the state of the rooms and devices, which has changed since the last call.
+    /// The topics published by an earlier call of this run, which are not used anymore, are cleared.
+    async fn publish_state<B: Transport, T: Transport>(
+        &self,
+        client: &mut Client<B>,
+        cube: &MaxCube<T>,
+        published: &mut HashMap<String, String>,
+    ) -> Result<()> {
+        let mut states = Vec::new();
+        // the discovery configs are published before the states
+        if let Some(discovery) = &self.discovery {
+            for dev in &cube.devices {
+                for (topic, config) in self.discovery_configs(discovery, cube, dev) {
+                    states.push((topic, config.to_string()));
+                }
+            }
+        }
+        for room in &cube.rooms {
+            states.push((
+                format!("{}/{}/state", self.prefix, topic_name(&room.name)),
+                serde_json::to_string(room).map_err(io::Error::from)?,
+            ));
+        }
+        for dev in &cube.devices {
+            let state = match dev {
+                Device::HeaterThermostat(ts) => serde_json::to_string(ts),
+                Device::WallThermostat(wt) => serde_json::to_string(wt),
+                Device::ShutterContact(sc) => serde_json::to_string(sc),
+                Device::Other(_) | Device::NotSupported => continue,
+            };
+            states.push((
+                format!("{}/state", self.device_topic(cube, dev)),
+                state.map_err(io::Error::from)?,
+            ));
+        }
+
+        let mut sent = false;
+        let unused = published
+            .keys()
+            .filter(|e| !states.iter().any(|(topic, _)| topic == *e))
+            .cloned()
+            .collect::<Vec<_>>();
+        for topic in unused {
+            client.publish(&topic, b"", true).await?;
+            published.remove(&topic);
+            sent = true;
+        }
+        for (topic, payload) in states {
+            if published.get(&topic) != Some(&payload) {
+                client.publish(&topic, payload.as_bytes(), true).await?;
+                published.insert(topic, payload);
+                sent = true;
+            }
+        }
+        // the broker expects traffic within the keep alive interval
+        if !sent {
+            client.ping().await?;
+        }
+        Ok(())
+    }
+
+    /// executes the message to a `set` topic
+    async fn command<T: Transport>(
+        &self,
+        cube: &mut MaxCube<T>,
+        topic: &str,
+        payload: &[u8],
+    ) -> Result<()> {
+        let invalid = || Error::protocol("Invalid set topic.", topic);
+        let segments = topic
+            .strip_prefix(&self.prefix)
+            .and_then(|e| e.strip_prefix('/'))
+            .and_then(|e| e.strip_suffix("/set"))
+            .ok_or_else(invalid)?
+            .split('/')
+            .collect::<Vec<_>>();
+
+        let room = cube
+            .rooms
+            .iter()
+            .find(|e| topic_name(&e.name) == segments[0])
+            .ok_or_else(|| Error::NameNotFound(segments[0].to_string()))?;
+        let (room_id, temperature_set) = (room.room_id, room.temperature_set);
+
+        // the commands are sent to a heater thermostat, the cube applies them to the whole room
+        let thermostat = match segments.as_slice() {
+            [_] => None,
+            [_, device] => {
+                let dev = cube
+                    .devices
+                    .iter()
+                    .find(|e| e.room_id() == Some(room_id) && self.device_name(e) == *device)
+                    .ok_or_else(|| Error::NameNotFound(device.to_string()))?;
+                match dev {
+                    Device::HeaterThermostat(ts) => Some(ts.rf_address),
+                    _ => None,
+                }
+            }
+            _ => return Err(invalid()),
+        };
+        let rf_address = match thermostat {
+            Some(rf_address) => rf_address,
+            None => cube
+                .devices_in_room(room_id)
+                .first()
+                .map(|e| e.rf_address)
+                .ok_or(Error::RoomNotFound(room_id))?,
+        };
+
+        let request = parse_set(payload)?;
+        match (request.mode, request.temperature, request.until) {
+            (None, Some(temperature), _) => cube.set_temperature(rf_address, temperature).await,
+            (Some(DeviceMode::Vacation), temperature, Some(until)) => {
+                cube.set_vacation(rf_address, temperature.unwrap_or(temperature_set), until)
+                    .await
+            }
+            // in auto mode, the temperature 0 keeps the time scheduling
+            (Some(DeviceMode::Auto), temperature, _) => {
+                cube.set_mode(rf_address, DeviceMode::Auto, temperature.unwrap_or(0.0))
+                    .await
+            }
+            (Some(mode), temperature, _) if mode != DeviceMode::Vacation => {
+                cube.set_mode(rf_address, mode, temperature.unwrap_or(temperature_set))
+                    .await
+            }
+            _ => Err(Error::protocol(
+                "Invalid set command.",
+                &String::from_utf8_lossy(payload),
+            )),
+        }
+    }
+
+    /// returns the topic of the device, without `/state` or `/set`
+    fn device_topic<T: Transport>(&self, cube: &MaxCube<T>, dev: &Device) -> String {
+        let room = cube
+            .rooms
+            .iter()
+            .find(|e| Some(e.room_id) == dev.room_id())
+            .map(|e| topic_name(&e.name))
+            .unwrap_or_else(|| "unassigned".to_string());
+        format!("{}/{}/{}", self.prefix, room, self.device_name(dev))
+    }
+
+    /// returns the name of the device in the topics
+    fn device_name(&self, dev: &Device) -> String {
+        match dev.name() {
+            Some(name) if !name.is_empty() => topic_name(name),
+            _ => format!("{:06x}", dev.rf_address().unwrap_or_default()),
+        }
+    }
+
+    fn status_topic(&self) -> String {
+        format!("{}/status", self.prefix)
+    }
+
+    /// returns the topics and discovery configs of Home Assistant for the device
+    fn discovery_configs<T: Transport>(
+        &self,
+        discovery: &str,
+        cube: &MaxCube<T>,
+        dev: &Device,
+    ) -> Vec<(String, Value)> {
+        let (model, serial) = match dev {
+            Device::HeaterThermostat(ts) => ("Heater thermostat", &ts.serial),
+            Device::WallThermostat(wt) => ("Wall thermostat", &wt.serial),
+            Device::ShutterContact(sc) => ("Shutter contact", &sc.serial),
+            Device::Other(_) | Device::NotSupported => return Vec::new(),
+        };
+        let id = format!("maxcube_{:06x}", dev.rf_address().unwrap_or_default());
+        let topic = self.device_topic(cube, dev);
+        let state = format!("{}/state", topic);
+        let set = format!("{}/set", topic);
+        let device = json!({
+            "identifiers": [format!("maxcube_{}", serial.trim())],
+            "name": dev.name().filter(|e| !e.is_empty()).unwrap_or(&id),
+            "manufacturer": "eQ-3",
+            "model": model,
+            "serial_number": serial.trim(),
+            "suggested_area": cube
+                .rooms
+                .iter()
+                .find(|e| Some(e.room_id) == dev.room_id())
+                .map(|e| e.name.as_str()),
+        });
+
+        // adds the fields, which are common to all entities
+        let entity = |component: &str, suffix: &str, mut config: Value| {
+            config["unique_id"] = json!(format!("{}{}", id, suffix));
+            config["availability_topic"] = json!(self.status_topic());
+            config["device"] = device.clone();
+            (
+                format!("{}/{}/{}{}/config", discovery, component, id, suffix),
+                config,
+            )
+        };
+
+        let mut configs = Vec::new();
+        match dev {
+            Device::HeaterThermostat(_) | Device::WallThermostat(_) => {
+                configs.push(entity(
+                    "climate",
+                    "",
+                    json!({
+                        "name": null,
+                        "current_temperature_topic": state,
+                        "current_temperature_template": "{{ value_json.temperature_measured }}",
+                        "temperature_state_topic": state,
+                        "temperature_state_template": "{{ value_json.temperature_set }}",
+                        "temperature_command_topic": set,
+                        "mode_state_topic": state,
+                        "mode_state_template": "{{ 'auto' if value_json.mode == 'Auto' else 'heat' }}",
+                        "mode_command_topic": set,
+                        "modes": ["auto", "heat"],
+                        "preset_mode_state_topic": state,
+                        "preset_mode_value_template": "{{ 'boost' if value_json.mode == 'Boost' else 'none' }}",
+                        "preset_mode_command_topic": set,
+                        "preset_modes": ["boost"],
+                        "min_temp": 4.5,
+                        "max_temp": 30.5,
+                        "temp_step": 0.5,
+                        "temperature_unit": "C",
+                    }),
+                ));
+            }
+            Device::ShutterContact(_) => {
+                configs.push(entity(
+                    "binary_sensor",
+                    "",
+                    json!({
+                        "name": null,
+                        "device_class": "window",
+                        "state_topic": state,
+                        "value_template": "{{ 'ON' if value_json.open else 'OFF' }}",
+                    }),
+                ));
+            }
+            Device::Other(_) | Device::NotSupported => (),
+        }
+        if let Device::HeaterThermostat(_) = dev {
+            configs.push(entity(
+                "sensor",
+                "_valve",
+                json!({
+                    "name": "Valve",
+                    "icon": "mdi:valve",
+                    "state_topic": state,
+                    "value_template": "{{ value_json.valve_position }}",
+                    "unit_of_measurement": "%",
+                }),
+            ));
+        }
+        configs.push(entity(
+            "binary_sensor",
+            "_battery",
+            json!({
+                "name": "Battery",
+                "device_class": "battery",
+                "entity_category": "diagnostic",
+                "state_topic": state,
+                "value_template": "{{ 'ON' if value_json.battery_low else 'OFF' }}",
+            }),
+        ));
+        configs
+    }
+}
+
+/// replaces the characters, which are not allowed in topic levels
+fn topic_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if matches!(c, '/' | '+' | '#') { '_' } else { c })
+        .collect()
+}
+
+/// parses the payload of a `set` topic, either a temperature or JSON
+fn parse_set(payload: &[u8]) -> Result<SetRequest> {
+    let text = String::from_utf8_lossy(payload);
+    if let Ok(temperature) = text.trim().parse::<f64>() {
+        return Ok(SetRequest {
+            temperature: Some(temperature),
+            ..Default::default()
+        });
+    }
+    // the modes as plain words, `heat` is sent by Home Assistant. Home Assistant sends `none` to
+    // leave the preset boost, which returns to the auto mode like `off`
+    let mode = match text.trim().to_ascii_lowercase().as_str() {
+        "auto" | "none" | "off" => Some(DeviceMode::Auto),
+        "manual" | "heat" => Some(DeviceMode::Manual),
+        "boost" => Some(DeviceMode::Boost),
+        _ => None,
+    };
+    if mode.is_some() {
+        return Ok(SetRequest {
+            mode,
+            ..Default::default()
+        });
+    }
+    serde_json::from_str(&text)
+        .map_err(|e| Error::protocol(format!("Invalid set command: {}.", e), &text))
+}
+
+/// minimal MQTT 3.1.1 client with QoS 0
+struct Client<B: Transport> {
+    reader: BufReader<ReadHalf<B>>,
+    writer: WriteHalf<B>,
+}
+
+impl<B: Transport> Client<B> {
+    /// sends CONNECT with the last will `offline` and waits for CONNACK
+    async fn connect(transport: B, bridge: &MqttBridge) -> Result<Self> {
+        let (reader, writer) = transport.split();
+        let mut client = Self {
+            reader: BufReader::new(reader),
+            writer,
+        };
+
+        // clean session, will retain, will flag, and user name and password if given
+        let mut flags = 0x02 | 0x04 | 0x20;
+        if bridge.credentials.is_some() {
+            flags |= 0x80 | 0x40;
+        }
+        // the bridge publishes or pings at least once per interval
+        let keep_alive = (bridge.interval.as_secs() * 2).clamp(10, u16::MAX as u64) as u16;
+
+        let mut b = Vec::new();
+        push_string(&mut b, b"MQTT");
+        b.push(4);
+        b.push(flags);
+        b.extend_from_slice(&keep_alive.to_be_bytes());
+        push_string(&mut b, bridge.client_id.as_bytes());
+        push_string(&mut b, bridge.status_topic().as_bytes());
+        push_string(&mut b, b"offline");
+        if let Some((username, password)) = &bridge.credentials {
+            push_string(&mut b, username.as_bytes());
+            push_string(&mut b, password.as_bytes());
+        }
+        client.send(CONNECT, &b).await?;
+
+        match client.poll(DEFAULT_COMMAND_TIMEOUT).await? {
+            Some((CONNACK, body)) if body.len() == 2 => match body[1] {
+                0 => Ok(client),
+                code => Err(Error::BrokerRefused(code)),
+            },
+            Some(_) => Err(Error::protocol("CONNACK expected.", "")),
+            None => Err(Error::Timeout {
+                operation: "Connect to broker",
+                duration: DEFAULT_COMMAND_TIMEOUT,
+            }),
+        }
+    }
+
+    /// subscribes the topic filters, SUBACK is ignored by the caller
+    async fn subscribe(&mut self, filters: &[String]) -> Result<()> {
+        let mut b = vec![0x00, 0x01];
+        for filter in filters {
+            push_string(&mut b, filter.as_bytes());
+            b.push(0x00);
+        }
+        self.send(SUBSCRIBE, &b).await
+    }
+
+    async fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> Result<()> {
+        let mut b = Vec::new();
+        push_string(&mut b, topic.as_bytes());
+        b.extend_from_slice(payload);
+        self.send(PUBLISH | retain as u8, &b).await
+    }
+
+    async fn ping(&mut self) -> Result<()> {
+        self.send(PINGREQ, &[]).await
+    }
+
+    async fn disconnect(&mut self) -> Result<()> {
+        self.send(DISCONNECT, &[]).await?;
+        self.writer.close().await?;
+        Ok(())
+    }
+
+    /// sends the packet with fixed header
+    async fn send(&mut self, header: u8, body: &[u8]) -> Result<()> {
+        self.writer.write_all(&encode_packet(header, body)).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    /// waits for the next packet, returns `None` if nothing arrived within the duration
+    async fn poll(&mut self, wait: Duration) -> Result<Option<(u8, Vec<u8>)>> {
+        // reading a single byte is not interrupted by the timeout halfway
+        let mut header = [0u8];
+        match rt::timeout(wait, self.reader.read_exact(&mut header)).await {
+            Some(result) => result?,
+            None => return Ok(None),
+        }
+
+        // the rest of the packet follows immediately, a stalled broker must not block the bridge
+        let body = rt::timeout(DEFAULT_COMMAND_TIMEOUT, self.read_body())
+            .await
+            .ok_or(Error::Timeout {
+                operation: "MQTT packet",
+                duration: DEFAULT_COMMAND_TIMEOUT,
+            })??;
+        Ok(Some((header[0], body)))
+    }
+
+    /// reads the remaining length and the rest of the packet after the fixed header
+    async fn read_body(&mut self) -> Result<Vec<u8>> {
+        let mut length = 0usize;
+        for (i, shift) in [0, 7, 14, 21].into_iter().enumerate() {
+            let mut byte = [0u8];
+            self.reader.read_exact(&mut byte).await?;
+            length |= ((byte[0] & 0x7f) as usize) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+            if i == 3 {
+                return Err(Error::protocol("Malformed remaining length.", ""));
+            }
+        }
+
+        let mut body = vec![0; length];
+        self.reader.read_exact(&mut body).await?;
+        Ok(body)
+    }
+}
+
+/// encodes the packet with fixed header and remaining length
+fn encode_packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut b = vec![header];
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            b.push(byte | 0x80);
+        } else {
+            b.push(byte);
+            break;
+        }
+    }
+    b.extend_from_slice(body);
+    b
+}
+
+/// decodes topic and payload of a PUBLISH packet
+fn decode_publish(header: u8, body: &[u8]) -> Result<(String, Vec<u8>)> {
+    let invalid = || Error::protocol("Malformed PUBLISH packet.", "");
+    let length = u16::from_be_bytes([
+        *body.first().ok_or_else(invalid)?,
+        *body.get(1).ok_or_else(invalid)?,
+    ]) as usize;
+    let topic = body.get(2..2 + length).ok_or_else(invalid)?;
+    // the packet id is only present for QoS 1 and 2
+    let start = if header & 0x06 > 0 {
+        4 + length
+    } else {
+        2 + length
+    };
+    Ok((
+        String::from_utf8_lossy(topic).to_string(),
+        body.get(start..).ok_or_else(invalid)?.to_vec(),
+    ))
+}
+
+/// appends the string with 2 bytes length
+fn push_string(b: &mut Vec<u8>, s: &[u8]) {
+    b.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    b.extend_from_slice(s);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::test::{trace, MESSAGE_L};
+    use crate::trace::{read_trace, Replay};
+    use crate::transport::test::Script;
+
+    /// returns the session after the handshake,
+    /// the status of the bedroom is changed to 23°C in the last L-Message
+    fn session() -> String {
+        trace(&format!(
+            "0.000 > l:
+0.000 < {}
+0.000 > s:AARAAAAAGuXTAW4=
+0.000 < S:00,0,31
+0.000 > l:
+0.000 < L:CxrnLgkSGQAmAM0ACxrlGAkSGQAKAAAACxrqAQkSGQApAOMACxrp/wkSGRYnAMoACxrl0wkSmQAuAOAA
+",
+            MESSAGE_L
+        ))
+    }
+
+    #[test]
+    fn test_packets() {
+        assert_eq!(encode_packet(PINGREQ, &[]), vec![0xc0, 0x00]);
+        assert_eq!(&encode_packet(PUBLISH, &[0; 200])[..3], &[0x30, 0xc8, 0x01]);
+
+        let mut b = Vec::new();
+        push_string(&mut b, b"a/b");
+        b.extend_from_slice(b"21.5");
+        assert_eq!(
+            decode_publish(PUBLISH, &b).unwrap(),
+            ("a/b".to_string(), b"21.5".to_vec())
+        );
+        // QoS 1 with packet id
+        b.splice(5..5, [0x00, 0x07]);
+        assert_eq!(
+            decode_publish(PUBLISH | 0x02, &b).unwrap(),
+            ("a/b".to_string(), b"21.5".to_vec())
+        );
+        assert!(decode_publish(PUBLISH, &[0x00, 0x09, b'a']).is_err());
+
+        assert_eq!(topic_name("Living room/1+#"), "Living room_1__");
+        assert_eq!(parse_set(b" 21.5 ").unwrap().temperature, Some(21.5));
+        assert_eq!(
+            parse_set(b"{\"mode\":\"Boost\"}").unwrap().mode,
+            Some(DeviceMode::Boost)
+        );
+        assert_eq!(parse_set(b"heat").unwrap().mode, Some(DeviceMode::Manual));
+        assert_eq!(parse_set(b"none").unwrap().mode, Some(DeviceMode::Auto));
+        assert_eq!(parse_set(b"off").unwrap().mode, Some(DeviceMode::Auto));
+        assert!(parse_set(b"warm").is_err());
+    }
+
+    #[test]
+    fn test_poll() {
+        // PINGRESP, then a PUBLISH with 5 bytes of remaining length
+        let (broker, _) = Script::new([0xd0, 0x00, 0x30, 0x80, 0x80, 0x80, 0x80, 0x01]);
+        let (reader, writer) = broker.split();
+        let mut client = Client {
+            reader: BufReader::new(reader),
+            writer,
+        };
+        rt::block_on(async {
+            let wait = Duration::from_millis(100);
+            assert_eq!(client.poll(wait).await.unwrap(), Some((0xd0, Vec::new())));
+            // the remaining length has at most 4 bytes
+            assert!(matches!(
+                client.poll(wait).await,
+                Err(Error::Protocol { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn test_discovery() {
+        let (broker, output) = Script::new([0x20, 0x02, 0x00, 0x00]);
+        let replay = Replay::new(read_trace(session().as_bytes()).unwrap());
+        rt::block_on(async {
+            let mut cube = MaxCube::from_transport(replay).await.unwrap();
+            assert!(MqttBridge::new()
+                .set_discovery("homeassistant")
+                .run_with_transport(broker, &mut cube)
+                .await
+                .is_err());
+        });
+
+        let output = output.lock().unwrap().clone();
+        let text = String::from_utf8_lossy(&output);
+        let config = |topic: &str| -> Value {
+            let start = text.find(topic).unwrap() + topic.len();
+            let mut stream = serde_json::Deserializer::from_str(&text[start..]).into_iter();
+            stream.next().unwrap().unwrap()
+        };
+
+        let climate = config("homeassistant/climate/maxcube_1ae5d3/config");
+        assert_eq!(climate["unique_id"], "maxcube_1ae5d3");
+        assert_eq!(
+            climate["temperature_command_topic"],
+            "maxcube/Bedroom/Bedroom/set"
+        );
+        assert_eq!(climate["availability_topic"], "maxcube/status");
+        assert_eq!(climate["device"]["serial_number"], "OEQ2121644");
+        assert_eq!(climate["device"]["suggested_area"], "Bedroom");
+
+        let valve = config("homeassistant/sensor/maxcube_1ae5d3_valve/config");
+        assert_eq!(valve["state_topic"], "maxcube/Bedroom/Bedroom/state");
+        let battery = config("homeassistant/binary_sensor/maxcube_1ae72e_battery/config");
+        assert_eq!(battery["device_class"], "battery");
+        assert_eq!(battery["device"]["name"], "Kitchen");
+
+        // the discovery configs are published before the states
+        assert!(
+            text.find("homeassistant/climate").unwrap()
+                < text.find("maxcube/Bedroom/state").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_bridge() {
+        let mut set = Vec::new();
+        push_string(&mut set, b"maxcube/Bedroom/set");
+        set.extend_from_slice(b"23");
+        let mut input = vec![0x20, 0x02, 0x00, 0x00, 0x90, 0x04, 0x00, 0x01, 0x00, 0x00];
+        input.extend(encode_packet(PUBLISH, &set));
+        let (broker, output) = Script::new(input);
+
+        let replay = Replay::new(read_trace(session().as_bytes()).unwrap());
+        rt::block_on(async {
+            let mut cube = MaxCube::from_transport(replay).await.unwrap();
+            // the bridge returns, when the broker closes the connection
+            assert!(matches!(
+                MqttBridge::new()
+                    .run_with_transport(broker, &mut cube)
+                    .await,
+                Err(Error::Io(_))
+            ));
+        });
+
+        let output = output.lock().unwrap().clone();
+        let text = String::from_utf8_lossy(&output);
+        assert_eq!(output[0], CONNECT);
+        assert!(text.contains("maxcube/+/+/set"));
+        assert!(text.contains("maxcube/Kitchen/state{\"room_id\":3,\"name\":\"Kitchen\""));
+        // the thermostat is published again with the new set point, the others are unchanged
+        assert_eq!(text.matches("maxcube/Kitchen/Kitchen/state").count(), 1);
+        let bedroom = text.rfind("maxcube/Bedroom/Bedroom/state").unwrap();
+        assert!(text[bedroom..].contains("\"temperature_set\":23.0"));
+
+        let mut offline = Vec::new();
+        push_string(&mut offline, b"maxcube/status");
+        offline.extend_from_slice(b"offline");
+        let mut end = encode_packet(PUBLISH | 0x01, &offline);
+        end.extend(encode_packet(DISCONNECT, &[]));
+        assert!(output.ends_with(&end));
+    }
+}
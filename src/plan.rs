@@ -0,0 +1,638 @@
+//! Declarative configuration: the desired state of the rooms, the device names, the time scheduling and
+//! the comfort and eco temperatures is kept in a file, e.g. as TOML in git.
+//! `MaxCube::plan()` compares it with the configuration of the cube and returns the differences,
+//! `MaxCube::apply()` sends only the commands, which are needed.
+//!
+//! ```toml
+//! [[rooms]]
+//! id = 1
+//! name = "Bedroom"
+//! comfort_temperature = 21.0
+//! eco_temperature = 17.0
+//! devices = [{ serial = "OEQ2121644", name = "Bedroom" }]
+//!
+//! [rooms.schedule]
+//! monday = ["06:00=17", "22:00=21", "24:00=17"]
+//! ```
+//!
+//! The rooms are identified by their id, the devices by their serial number. A device listed in
+//! another room is moved there. Rooms, devices, settings and days, which are not part of the desired
+//! state, are kept as they are. The temperatures and the time scheduling of a room apply to all of its
+//! heater thermostats.
+//!
+//! Every command to a device uses the radio of the cube, which may only send 1% of the time.
+//! `apply()` stops with `Error::DutyCycle`, before the duty cycle reaches `DUTY_CYCLE_LIMIT`.
+//! Planning and applying again later sends the remaining changes only.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::messages::{
+    config_temperatures_command, encode_day_program, group_command, link_partner_command,
+    program_command, unlink_partner_command, DayProgram, Device, DeviceSettings, Devices,
+    ProgramPoint, Room, Rooms, DAYS,
+};
+
+/// `apply()` sends no more commands, when the duty cycle of the cube reaches the limit, in percent
+pub const DUTY_CYCLE_LIMIT: u8 = 90;
+
+/// desired configuration of the rooms and devices
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DesiredState {
+    #[serde(default)]
+    pub rooms: Vec<DesiredRoom>,
+}
+
+/// desired configuration of a room
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DesiredRoom {
+    /// room id (group id)
+    pub id: u8,
+    pub name: String,
+    /// the devices of the room
+    #[serde(default)]
+    pub devices: Vec<DesiredDevice>,
+    /// comfort temperature of the heater thermostats
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comfort_temperature: Option<f64>,
+    /// eco temperature of the heater thermostats
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eco_temperature: Option<f64>,
+    /// time scheduling of the heater thermostats by day, e.g. `monday`, as switch points
+    /// `HH:MM=TEMPERATURE`. The last point shall end at 24:00.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub schedule: BTreeMap<String, Vec<String>>,
+}
+
+/// desired configuration of a device
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DesiredDevice {
+    /// serial number of the device
+    pub serial: String,
+    /// name of the device, the current name is kept by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// one difference between the desired state and the cube
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    AddRoom {
+        room_id: u8,
+        name: String,
+    },
+    RenameRoom {
+        room_id: u8,
+        from: String,
+        to: String,
+    },
+    RenameDevice {
+        rf_address: u32,
+        from: String,
+        to: String,
+    },
+    MoveDevice {
+        rf_address: u32,
+        name: String,
+        from: u8,
+        to: u8,
+    },
+    /// comfort and eco temperatures
+    SetTemperatures {
+        rf_address: u32,
+        name: String,
+        from: (f64, f64),
+        to: (f64, f64),
+    },
+    /// time scheduling of one day, 0 = Saturday ... 6 = Friday
+    SetProgram {
+        rf_address: u32,
+        name: String,
+        day: u8,
+        program: DayProgram,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::AddRoom { room_id, name } => write!(f, "+ room {} {:?}", room_id, name),
+            Change::RenameRoom { room_id, from, to } => {
+                write!(f, "~ room {}: name {:?} -> {:?}", room_id, from, to)
+            }
+            Change::RenameDevice {
+                rf_address,
+                from,
+                to,
+            } => write!(
+                f,
+                "~ device {:06x}: name {:?} -> {:?}",
+                rf_address, from, to
+            ),
+            Change::MoveDevice {
+                rf_address,
+                name,
+                from,
+                to,
+            } => write!(
+                f,
+                "~ device {:06x} {:?}: room {} -> {}",
+                rf_address, name, from, to
+            ),
+            Change::SetTemperatures {
+                rf_address,
+                name,
+                from,
+                to,
+            } => write!(
+                f,
+                "~ device {:06x} {:?}: comfort {:.1} -> {:.1}, eco {:.1} -> {:.1}",
+                rf_address, name, from.0, to.0, from.1, to.1
+            ),
+            Change::SetProgram {
+                rf_address,
+                name,
+                day,
+                program,
+            } => {
+                write!(
+                    f,
+                    "~ device {:06x} {:?}: {}",
+                    rf_address,
+                    name,
+                    DAYS[*day as usize % 7]
+                )?;
+                for point in program {
+                    write!(
+                        f,
+                        " {:02}:{:02}={:.1}",
+                        point.until / 60,
+                        point.until % 60,
+                        point.temperature
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// the changes, which bring the cube to the desired state, with the commands to apply them
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    pub changes: Vec<Change>,
+    /// the meta data after the changes, if rooms or devices are added, renamed or moved
+    pub(crate) meta_data: Option<(Rooms, Devices)>,
+    /// the commands to the devices with their RF addresses
+    pub(crate) commands: Vec<(u32, String)>,
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.changes.is_empty() {
+            return writeln!(f, "No changes.");
+        }
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+impl Plan {
+    /// compares the desired state with the rooms and devices of the cube, and the settings of the heater
+    /// thermostats as received by `device_settings()`
+    pub fn new(
+        desired: &DesiredState,
+        rooms: &Rooms,
+        devices: &Devices,
+        settings: &[DeviceSettings],
+    ) -> Result<Self> {
+        let mut plan = Plan::default();
+        let current = devices;
+        let (mut rooms, mut devices) = (rooms.clone(), devices.clone());
+
+        // meta data: rooms, names and room assignment
+        let mut moved = Vec::new();
+        for room in &desired.rooms {
+            match rooms.iter_mut().find(|e| e.room_id == room.id) {
+                Some(current) if current.name != room.name => {
+                    plan.changes.push(Change::RenameRoom {
+                        room_id: room.id,
+                        from: current.name.clone(),
+                        to: room.name.clone(),
+                    });
+                    current.name = room.name.clone();
+                }
+                Some(_) => (),
+                None => {
+                    plan.changes.push(Change::AddRoom {
+                        room_id: room.id,
+                        name: room.name.clone(),
+                    });
+                    rooms.push(Room {
+                        room_id: room.id,
+                        name: room.name.clone(),
+                        ..Default::default()
+                    });
+                }
+            }
+
+            for wanted in &room.devices {
+                let dev = devices
+                    .iter_mut()
+                    .find(|e| e.serial() == Some(wanted.serial.as_str()))
+                    .ok_or_else(|| Error::DeviceSerialNotFound(wanted.serial.clone()))?;
+                let rf_address = dev.rf_address().unwrap_or_default();
+                let Some((name, room_id)) = meta_mut(dev) else {
+                    continue;
+                };
+                match &wanted.name {
+                    Some(to) if to != name => {
+                        plan.changes.push(Change::RenameDevice {
+                            rf_address,
+                            from: name.clone(),
+                            to: to.clone(),
+                        });
+                        *name = to.clone();
+                    }
+                    _ => (),
+                }
+                if *room_id != room.id {
+                    plan.changes.push(Change::MoveDevice {
+                        rf_address,
+                        name: name.clone(),
+                        from: *room_id,
+                        to: room.id,
+                    });
+                    moved.push((rf_address, *room_id));
+                    *room_id = room.id;
+                }
+            }
+        }
+
+        if !plan.changes.is_empty() {
+            // the RF address of a new room is the one of its first device, also of a room,
+            // whose device with the RF address has moved away
+            for room in rooms.iter_mut() {
+                let left = moved.iter().any(|(rf_address, from)| {
+                    *rf_address == room.rf_address && *from == room.room_id
+                });
+                if room.rf_address == 0 || left {
+                    room.rf_address = devices
+                        .iter()
+                        .filter(|e| e.room_id() == Some(room.room_id))
+                        .find_map(Device::rf_address)
+                        .unwrap_or_default();
+                }
+            }
+            plan.meta_data = Some((rooms, devices.clone()));
+        }
+
+        // the moved devices are unlinked from the devices in the old room, assigned to the new room and
+        // linked with the devices in it
+        for (rf_address, from) in &moved {
+            let Some(dev) = devices.iter().find(|e| e.rf_address() == Some(*rf_address)) else {
+                continue;
+            };
+            let (room_id, device_type) = (
+                dev.room_id().unwrap_or_default(),
+                dev.device_type().unwrap_or_default(),
+            );
+            if *from != 0 {
+                for (partner_address, partner_type) in partners(current, *from, *rf_address) {
+                    plan.commands.push((
+                        *rf_address,
+                        unlink_partner_command(*rf_address, *from, partner_address, partner_type),
+                    ));
+                    plan.commands.push((
+                        partner_address,
+                        unlink_partner_command(partner_address, *from, *rf_address, device_type),
+                    ));
+                }
+            }
+            plan.commands
+                .push((*rf_address, group_command(*rf_address, room_id)));
+            for (partner_address, partner_type) in partners(&devices, room_id, *rf_address) {
+                plan.commands.push((
+                    *rf_address,
+                    link_partner_command(*rf_address, room_id, partner_address, partner_type),
+                ));
+                plan.commands.push((
+                    partner_address,
+                    link_partner_command(partner_address, room_id, *rf_address, device_type),
+                ));
+            }
+        }
+
+        // settings and time scheduling of the heater thermostats
+        for room in &desired.rooms {
+            let schedule = room
+                .schedule
+                .iter()
+                .map(|(day, points)| {
+                    let index = DAYS
+                        .iter()
+                        .position(|e| e.eq_ignore_ascii_case(day))
+                        .ok_or_else(|| Error::InvalidProgram(format!("Unknown day {:?}.", day)))?;
+                    Ok((index as u8, parse_program(points)?))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            for dev in &devices {
+                let Device::HeaterThermostat(ts) = dev else {
+                    continue;
+                };
+                if ts.room_id != room.id {
+                    continue;
+                }
+                let Some(current) = settings
+                    .iter()
+                    .find(|e| e.rf_address == ts.rf_address)
+                    .and_then(|e| e.thermostat.as_ref())
+                else {
+                    continue;
+                };
+
+                let mut wanted = current.clone();
+                wanted.comfort_temperature = room
+                    .comfort_temperature
+                    .unwrap_or(current.comfort_temperature);
+                wanted.eco_temperature = room.eco_temperature.unwrap_or(current.eco_temperature);
+                // compared as sent to the device, with steps of 0.5°C
+                let half = |e: f64| (e * 2.0) as u8;
+                if half(wanted.comfort_temperature) != half(current.comfort_temperature)
+                    || half(wanted.eco_temperature) != half(current.eco_temperature)
+                {
+                    plan.changes.push(Change::SetTemperatures {
+                        rf_address: ts.rf_address,
+                        name: ts.name.clone(),
+                        from: (current.comfort_temperature, current.eco_temperature),
+                        to: (wanted.comfort_temperature, wanted.eco_temperature),
+                    });
+                    plan.commands.push((
+                        ts.rf_address,
+                        config_temperatures_command(ts.rf_address, ts.room_id, &wanted),
+                    ));
+                }
+
+                for (day, program) in &schedule {
+                    if encode_day_program(program)
+                        == encode_day_program(&current.weekly_program[*day as usize])
+                    {
+                        continue;
+                    }
+                    plan.changes.push(Change::SetProgram {
+                        rf_address: ts.rf_address,
+                        name: ts.name.clone(),
+                        day: *day,
+                        program: program.clone(),
+                    });
+                    plan.commands.push((
+                        ts.rf_address,
+                        program_command(&devices, ts.rf_address, *day, program)?,
+                    ));
+                }
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// returns if the cube is in the desired state already
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// returns the RF addresses and types of the devices in the room, which the device is linked with
+fn partners(devices: &Devices, room_id: u8, rf_address: u32) -> Vec<(u32, u8)> {
+    devices
+        .iter()
+        .filter(|e| e.room_id() == Some(room_id) && e.rf_address() != Some(rf_address))
+        .filter(|e| !matches!(e, Device::Other(_)))
+        .filter_map(|e| Some((e.rf_address()?, e.device_type()?)))
+        .collect()
+}
+
+/// returns the name and the room id of the device to change them
+fn meta_mut(dev: &mut Device) -> Option<(&mut String, &mut u8)> {
+    match dev {
+        Device::HeaterThermostat(ts) => Some((&mut ts.name, &mut ts.room_id)),
+        Device::WallThermostat(wt) => Some((&mut wt.name, &mut wt.room_id)),
+        Device::ShutterContact(sc) => Some((&mut sc.name, &mut sc.room_id)),
+        Device::Other(_) | Device::NotSupported => None,
+    }
+}
+
+/// parses the switch points `HH:MM=TEMPERATURE` of one day, e.g. `22:00=21`.
+/// The points shall be in steps of 5 minutes in ascending order, the last one shall end at 24:00.
+pub fn parse_program<S: AsRef<str>>(points: &[S]) -> Result<DayProgram> {
+    let mut program = DayProgram::new();
+    for point in points {
+        let point = point.as_ref();
+        let invalid = || {
+            Error::InvalidProgram(format!(
+                "Invalid switch point {:?}, e.g. 22:00=21 expected.",
+                point
+            ))
+        };
+        let (time, temperature) = point.split_once('=').ok_or_else(invalid)?;
+        let (hour, minute) = time.split_once(':').ok_or_else(invalid)?;
+        let (hour, minute) = (
+            hour.parse::<u16>().map_err(|_| invalid())?,
+            minute.parse::<u16>().map_err(|_| invalid())?,
+        );
+        if minute >= 60 || hour > 24 || (hour == 24 && minute > 0) {
+            return Err(invalid());
+        }
+        let until = hour * 60 + minute;
+        if !until.is_multiple_of(5) || program.last().is_some_and(|e| e.until >= until) {
+            return Err(Error::InvalidProgram(format!(
+                "The switch point {:?} is not in steps of 5 minutes in ascending order.",
+                point
+            )));
+        }
+        program.push(ProgramPoint {
+            temperature: temperature.parse().map_err(|_| invalid())?,
+            until,
+        });
+    }
+
+    match program.last() {
+        Some(last) if last.until == 24 * 60 && program.len() <= 13 => Ok(program),
+        _ => Err(Error::InvalidProgram(
+            "Up to 13 switch points are supported, the last one shall end at 24:00.".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::test::MESSAGE_M;
+    use crate::messages::{from_message_m, ShutterContact, ThermostatSettings};
+
+    fn house() -> (Rooms, Devices, Vec<DeviceSettings>) {
+        let (rooms, devices) = from_message_m(MESSAGE_M).unwrap();
+        let settings = devices
+            .iter()
+            .filter_map(Device::rf_address)
+            .map(|rf_address| DeviceSettings {
+                rf_address,
+                device_type: 1,
+                thermostat: Some(ThermostatSettings::default()),
+                ..Default::default()
+            })
+            .collect();
+        (rooms, devices, settings)
+    }
+
+    fn room(id: u8, name: &str) -> DesiredRoom {
+        DesiredRoom {
+            id,
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let (rooms, devices, settings) = house();
+
+        // the current state, the factory settings are kept
+        let mut bedroom = room(1, "Bedroom");
+        bedroom.comfort_temperature = Some(21.0);
+        bedroom.schedule.insert(
+            "Monday".to_string(),
+            vec!["06:00=17".into(), "22:00=21".into(), "24:00=17".into()],
+        );
+        let mut desired = DesiredState {
+            rooms: vec![bedroom, room(2, "Living room")],
+        };
+        let plan = Plan::new(&desired, &rooms, &devices, &settings).unwrap();
+        assert!(plan.is_empty());
+        assert_eq!(plan.to_string(), "No changes.\n");
+
+        desired.rooms[0].name = "Sleeping room".to_string();
+        desired.rooms[0].eco_temperature = Some(16.5);
+        desired.rooms[0].schedule.insert(
+            "sunday".to_string(),
+            vec!["08:00=17".into(), "24:00=20".into()],
+        );
+        // the office thermostat moves to a new room
+        let mut attic = room(6, "Attic");
+        attic.devices.push(DesiredDevice {
+            serial: "OEQ2122353".to_string(),
+            name: Some("Attic".to_string()),
+        });
+        desired.rooms.push(attic);
+
+        let plan = Plan::new(&desired, &rooms, &devices, &settings).unwrap();
+        assert_eq!(
+            plan.to_string(),
+            "~ room 1: name \"Bedroom\" -> \"Sleeping room\"\n\
+             + room 6 \"Attic\"\n\
+             ~ device 1ae9ff: name \"Office\" -> \"Attic\"\n\
+             ~ device 1ae9ff \"Attic\": room 4 -> 6\n\
+             ~ device 1ae5d3 \"Bedroom\": comfort 21.0 -> 21.0, eco 17.0 -> 16.5\n\
+             ~ device 1ae5d3 \"Bedroom\": sunday 08:00=17.0 24:00=20.0\n"
+        );
+        let (rooms, devices) = plan.meta_data.clone().unwrap();
+        assert_eq!(rooms[5].rf_address, 0x1ae9ff);
+        // the office is empty now
+        assert_eq!(rooms[3].rf_address, 0);
+        assert_eq!(devices[3].room_id(), Some(6));
+        // group of the moved device, temperatures and one day
+        assert_eq!(plan.commands.len(), 3);
+        assert_eq!(plan.commands[0], (0x1ae9ff, group_command(0x1ae9ff, 6)));
+        let temperatures = ThermostatSettings {
+            eco_temperature: 16.5,
+            ..Default::default()
+        };
+        assert_eq!(
+            plan.commands[1],
+            (
+                0x1ae5d3,
+                config_temperatures_command(0x1ae5d3, 1, &temperatures)
+            )
+        );
+        let sunday = parse_program(&["08:00=17", "24:00=20"]).unwrap();
+        assert_eq!(
+            plan.commands[2],
+            (
+                0x1ae5d3,
+                program_command(&devices, 0x1ae5d3, 1, &sunday).unwrap()
+            )
+        );
+
+        desired.rooms[2].devices[0].serial = "OEQ0000000".to_string();
+        assert!(matches!(
+            Plan::new(&desired, &rooms, &devices, &settings),
+            Err(Error::DeviceSerialNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_plan_move() {
+        let (rooms, mut devices, settings) = house();
+        // a shutter contact in the office, which is linked with the office thermostat
+        devices.push(Device::ShutterContact(ShutterContact {
+            rf_address: 0x1b0001,
+            serial: "OEQ0000001".to_string(),
+            room_id: 4,
+            ..Default::default()
+        }));
+
+        // the office thermostat moves to the kitchen
+        let mut kitchen = room(3, "Kitchen");
+        kitchen.devices.push(DesiredDevice {
+            serial: "OEQ2122353".to_string(),
+            name: None,
+        });
+        let desired = DesiredState {
+            rooms: vec![kitchen],
+        };
+        let plan = Plan::new(&desired, &rooms, &devices, &settings).unwrap();
+
+        let (rooms, _) = plan.meta_data.clone().unwrap();
+        // the room keeps the RF address of the device left in it
+        assert_eq!(rooms[2].rf_address, 0x1ae72e);
+        assert_eq!(rooms[3].rf_address, 0x1b0001);
+        assert_eq!(
+            plan.commands,
+            vec![
+                (0x1ae9ff, "s:AAAhAAAAGun/BBsAAQQ=\r\n".to_string()),
+                (0x1b0001, unlink_partner_command(0x1b0001, 4, 0x1ae9ff, 1)),
+                (0x1ae9ff, group_command(0x1ae9ff, 3)),
+                (0x1ae9ff, link_partner_command(0x1ae9ff, 3, 0x1ae72e, 1)),
+                (0x1ae72e, link_partner_command(0x1ae72e, 3, 0x1ae9ff, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_program() {
+        assert_eq!(
+            parse_program(&["06:00=17", "24:00=21.5"]).unwrap(),
+            vec![
+                ProgramPoint {
+                    temperature: 17.0,
+                    until: 360
+                },
+                ProgramPoint {
+                    temperature: 21.5,
+                    until: 1440
+                }
+            ]
+        );
+        assert!(parse_program(&["06:00=17"]).is_err());
+        assert!(parse_program(&["06:03=17", "24:00=17"]).is_err());
+        assert!(parse_program(&["22:00=17", "06:00=17", "24:00=17"]).is_err());
+        assert!(parse_program(&["24:30=17"]).is_err());
+        assert!(parse_program::<&str>(&[]).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Runtime specific parts, selected by the features `runtime-async-std` and `runtime-tokio`.
+//! All other modules use the runtime only through this module.
+//! If both features are enabled, tokio is used.
+
+#[cfg(feature = "runtime-tokio")]
+mod imp {
+    use std::{future::Future, io, time::Duration};
+
+    use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+    pub use tokio::net::{ToSocketAddrs, UdpSocket};
+
+    /// TCP connection to the cube
+    pub type TcpStream = Compat<tokio::net::TcpStream>;
+
+    /// opens a TCP connection
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpStream> {
+        Ok(tokio::net::TcpStream::connect(addr).await?.compat())
+    }
+
+    /// listens for TCP connections
+    #[cfg(any(feature = "sim", feature = "http"))]
+    pub async fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<tokio::net::TcpListener> {
+        tokio::net::TcpListener::bind(addr).await
+    }
+
+    /// accepts the next TCP connection
+    #[cfg(any(feature = "sim", feature = "http"))]
+    pub async fn accept(
+        listener: &tokio::net::TcpListener,
+    ) -> io::Result<(TcpStream, std::net::SocketAddr)> {
+        let (stream, addr) = listener.accept().await?;
+        Ok((stream.compat(), addr))
+    }
+
+    /// waits for the duration
+    pub async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+
+    /// runs the future, returns `None` if it is not finished within the duration
+    pub async fn timeout<F: Future>(duration: Duration, fut: F) -> Option<F::Output> {
+        tokio::time::timeout(duration, fut).await.ok()
+    }
+
+    /// runs the future to completion, only used by tests
+    #[cfg(test)]
+    pub fn block_on<F: Future>(fut: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(fut)
+    }
+
+    /// runs the future in the background, only used by tests
+    #[cfg(test)]
+    pub fn spawn<F>(fut: F) -> impl Future<Output = F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let handle = tokio::spawn(fut);
+        async move { handle.await.unwrap() }
+    }
+}
+
+#[cfg(all(feature = "runtime-async-std", not(feature = "runtime-tokio")))]
+mod imp {
+    use std::{future::Future, io, time::Duration};
+
+    pub use async_std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+
+    /// opens a TCP connection
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpStream> {
+        TcpStream::connect(addr).await
+    }
+
+    /// listens for TCP connections
+    #[cfg(any(feature = "sim", feature = "http"))]
+    pub async fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<async_std::net::TcpListener> {
+        async_std::net::TcpListener::bind(addr).await
+    }
+
+    /// accepts the next TCP connection
+    #[cfg(any(feature = "sim", feature = "http"))]
+    pub async fn accept(
+        listener: &async_std::net::TcpListener,
+    ) -> io::Result<(TcpStream, std::net::SocketAddr)> {
+        listener.accept().await
+    }
+
+    /// waits for the duration
+    pub async fn sleep(duration: Duration) {
+        async_std::task::sleep(duration).await
+    }
+
+    /// runs the future, returns `None` if it is not finished within the duration
+    pub async fn timeout<F: Future>(duration: Duration, fut: F) -> Option<F::Output> {
+        async_std::future::timeout(duration, fut).await.ok()
+    }
+
+    /// runs the future to completion, only used by tests
+    #[cfg(test)]
+    pub fn block_on<F: Future>(fut: F) -> F::Output {
+        async_std::task::block_on(fut)
+    }
+
+    /// runs the future in the background, only used by tests
+    #[cfg(test)]
+    pub fn spawn<F>(fut: F) -> impl Future<Output = F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        async_std::task::spawn(fut)
+    }
+}
+
+pub use imp::*;
@@ -0,0 +1,649 @@
+//! The protocol of the TCP connection to the cube, without any IO. It is shared by the async `MaxCube`
+//! and the blocking client, which only send the requests and read the answers with their own runtime.
+//!
+//! Every operation is an `Operation`: it returns the next request to the cube, takes the answer to it,
+//! and updates the data of the cube, until its result is ready.
+
+use std::time::Duration;
+
+use log::info;
+
+use crate::backup::Backup;
+use crate::error::{Error, Result};
+use crate::history::HistoryLogger;
+use crate::messages::{
+    delete_command, device_by_name, from_message_c, from_message_h, from_message_l, from_message_m,
+    from_message_n, from_message_s, meta_data_command, mode_command, program_command, room_by_name,
+    set_temperature_command, thermostat_by_name, update_rooms, update_status, CubeStatus, DateTime,
+    DayProgram, Device, DeviceMode, DeviceSettings, Devices, PairingConfig, Rooms,
+};
+use crate::plan::{DesiredState, Plan, DUTY_CYCLE_LIMIT};
+use crate::trace::Recorder;
+use crate::{
+    DEFAULT_COMMAND_TIMEOUT, DEFAULT_CONNECT_TIMEOUT, DEFAULT_DISCOVERY_TIMEOUT,
+    DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_KEEP_ALIVE,
+};
+
+/// the configuration of the connection, which is set by the builders of the async and the blocking client
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) connect_timeout: Duration,
+    pub(crate) handshake_timeout: Duration,
+    pub(crate) command_timeout: Duration,
+    pub(crate) discovery_timeout: Duration,
+    pub(crate) keep_alive: Duration,
+    pub(crate) recorder: Option<Recorder>,
+    pub(crate) history: Option<HistoryLogger>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            discovery_timeout: DEFAULT_DISCOVERY_TIMEOUT,
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            recorder: None,
+            history: None,
+        }
+    }
+}
+
+/// declares the setters of the builders of the async and the blocking client, which set the `config`
+macro_rules! builder_setters {
+    () => {
+        /// set the timeout for establishing the TCP connection
+        pub fn set_connect_timeout(mut self, connect_timeout: std::time::Duration) -> Self {
+            self.config.connect_timeout = connect_timeout;
+            self
+        }
+
+        /// set the timeout for receiving the meta and status data after connection
+        pub fn set_handshake_timeout(mut self, handshake_timeout: std::time::Duration) -> Self {
+            self.config.handshake_timeout = handshake_timeout;
+            self
+        }
+
+        /// set the timeout for the answer of every single command
+        pub fn set_command_timeout(mut self, command_timeout: std::time::Duration) -> Self {
+            self.config.command_timeout = command_timeout;
+            self
+        }
+
+        /// set the time to wait for the answer of the cube to the discovery by `connect_serial()`
+        pub fn set_discovery_timeout(mut self, discovery_timeout: std::time::Duration) -> Self {
+            self.config.discovery_timeout = discovery_timeout;
+            self
+        }
+
+        /// set the interval, after which an idle connection will be refreshed by `keep_alive()`.
+        /// Nothing is sent by itself, the application has to call `keep_alive()` periodically.
+        pub fn set_keep_alive(mut self, keep_alive: std::time::Duration) -> Self {
+            self.config.keep_alive = keep_alive;
+            self
+        }
+
+        /// records the whole session, including the data received after connection, to the trace
+        pub fn set_recorder(mut self, recorder: $crate::trace::Recorder) -> Self {
+            self.config.recorder = Some(recorder);
+            self
+        }
+
+        /// writes a snapshot of the heater thermostats to the history after every refresh of the device status
+        pub fn set_history(mut self, history: $crate::history::HistoryLogger) -> Self {
+            self.config.history = Some(history);
+            self
+        }
+    };
+}
+pub(crate) use builder_setters;
+
+/// the data of the cube, which is read and updated by the operations
+pub(crate) struct State<'a> {
+    pub(crate) status: &'a mut CubeStatus,
+    pub(crate) rooms: &'a mut Rooms,
+    pub(crate) devices: &'a mut Devices,
+    pub(crate) history: Option<&'a HistoryLogger>,
+}
+
+impl State<'_> {
+    /// takes one line, which the cube sends after connection: the H-, M- and L-Message are proceed,
+    /// other lines are ignored. Returns true after the L-Message, which completes the handshake.
+    pub(crate) fn handshake(&mut self, received: &str) -> Result<bool> {
+        if received.starts_with('L') {
+            self.update_devices(received)?;
+            return Ok(true);
+        } else if received.starts_with('H') {
+            *self.status = from_message_h(received)?;
+        } else if received.starts_with('M') {
+            (*self.rooms, *self.devices) = from_message_m(received)?;
+        }
+        Ok(false)
+    }
+
+    /// takes the answer to the pairing, the new device is added
+    pub(crate) fn paired(&mut self, received: &str) {
+        if let Ok(dev) = from_message_n(received) {
+            info!("New device found: {:?}", dev);
+            self.devices.push(dev);
+        }
+    }
+
+    /// updates the devices and the state of the rooms by the L-Message, and writes the history
+    fn update_devices(&mut self, received: &str) -> Result<()> {
+        from_message_l(received, self.devices)?;
+        update_rooms(self.rooms, self.devices);
+        if let Some(history) = self.history {
+            history.log(self.rooms, self.devices);
+        }
+        Ok(())
+    }
+
+    /// returns the RF addresses of the heater thermostats
+    fn thermostat_addresses(&self) -> Vec<u32> {
+        self.devices
+            .iter()
+            .filter_map(|e| match e {
+                Device::HeaterThermostat(ts) => Some(ts.rf_address),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// a message to the cube and the prefix of its answer
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub(crate) payload: String,
+    prefix: char,
+}
+
+impl Request {
+    fn new(payload: String, prefix: char) -> Self {
+        Self { payload, prefix }
+    }
+
+    /// returns if the received line is the answer to the request, other lines are ignored.
+    /// The answers to the commands update the duty cycle and the free memory slots.
+    pub(crate) fn is_answer(&self, received: &str, status: &mut CubeStatus) -> bool {
+        if !received.starts_with(self.prefix) {
+            return false;
+        }
+        if self.prefix == 'S' {
+            update_status(received, status);
+        }
+        true
+    }
+}
+
+/// next step of an operation
+pub(crate) enum Step<T> {
+    /// the request shall be sent, its answer is passed to the next step
+    Request(Request),
+    /// the operation is done with the result
+    Done(T),
+}
+
+/// Operation is a sequence of requests to the cube
+pub(crate) trait Operation {
+    type Output;
+
+    /// returns the next request or the result. The answer to the last request is passed,
+    /// it is `None` on the first step.
+    fn step(&mut self, state: &mut State<'_>, answer: Option<String>)
+        -> Result<Step<Self::Output>>;
+
+    /// runs the next operation after this one, the result of the next one is returned
+    fn then<B: Operation>(self, next: B) -> Then<Self, B>
+    where
+        Self: Sized,
+    {
+        Then {
+            first: Some(self),
+            next,
+        }
+    }
+}
+
+/// two operations one after another, see `Operation::then()`
+pub(crate) struct Then<A, B> {
+    first: Option<A>,
+    next: B,
+}
+
+impl<A: Operation, B: Operation> Operation for Then<A, B> {
+    type Output = B::Output;
+
+    fn step(&mut self, state: &mut State<'_>, answer: Option<String>) -> Result<Step<B::Output>> {
+        match &mut self.first {
+            Some(first) => match first.step(state, answer)? {
+                Step::Request(request) => Ok(Step::Request(request)),
+                Step::Done(_) => {
+                    self.first = None;
+                    self.next.step(state, None)
+                }
+            },
+            None => self.next.step(state, answer),
+        }
+    }
+}
+
+/// operation with a single request, built by `build` from the current data.
+/// `handle` takes the answer and returns the result.
+pub(crate) struct Exchange<B, H> {
+    build: Option<B>,
+    handle: Option<H>,
+}
+
+impl<B, H, T> Operation for Exchange<B, H>
+where
+    B: FnOnce(&State<'_>) -> Result<Request>,
+    H: FnOnce(&mut State<'_>, &str) -> Result<T>,
+{
+    type Output = T;
+
+    fn step(&mut self, state: &mut State<'_>, answer: Option<String>) -> Result<Step<T>> {
+        match answer {
+            None => {
+                let build = self.build.take().expect("request built twice");
+                Ok(Step::Request(build(state)?))
+            }
+            Some(answer) => {
+                let handle = self.handle.take().expect("answer handled twice");
+                Ok(Step::Done(handle(state, &answer)?))
+            }
+        }
+    }
+}
+
+fn exchange<B, H, T>(build: B, handle: H) -> Exchange<B, H>
+where
+    B: FnOnce(&State<'_>) -> Result<Request>,
+    H: FnOnce(&mut State<'_>, &str) -> Result<T>,
+{
+    Exchange {
+        build: Some(build),
+        handle: Some(handle),
+    }
+}
+
+/// runs the operation for a device or room with the name: `resolve` looks up the name in the data of
+/// the cube before the first request, `build` returns the operation for the result
+pub(crate) struct ByName<R, B, O> {
+    resolve: Option<(R, B)>,
+    operation: Option<O>,
+}
+
+impl<R, B, O, K> Operation for ByName<R, B, O>
+where
+    R: FnOnce(&State<'_>) -> Result<K>,
+    B: FnOnce(K) -> O,
+    O: Operation,
+{
+    type Output = O::Output;
+
+    fn step(&mut self, state: &mut State<'_>, answer: Option<String>) -> Result<Step<O::Output>> {
+        if let Some((resolve, build)) = self.resolve.take() {
+            self.operation = Some(build(resolve(state)?));
+        }
+        let operation = self.operation.as_mut().expect("name not resolved");
+        operation.step(state, answer)
+    }
+}
+
+fn by_name<R, B, O, K>(resolve: R, build: B) -> ByName<R, B, O>
+where
+    R: FnOnce(&State<'_>) -> Result<K>,
+    B: FnOnce(K) -> O,
+    O: Operation,
+{
+    ByName {
+        resolve: Some((resolve, build)),
+        operation: None,
+    }
+}
+
+/// runs the operation for the thermostat with the name, or the first thermostat in the room with the name
+pub(crate) fn thermostat_named<B, O>(name: &str, build: B) -> impl Operation<Output = O::Output>
+where
+    B: FnOnce(u32) -> O,
+    O: Operation,
+{
+    let name = name.to_string();
+    by_name(
+        move |state: &State<'_>| thermostat_by_name(state.rooms, state.devices, &name),
+        build,
+    )
+}
+
+/// runs the operation for the thermostat with the name
+pub(crate) fn device_named<B, O>(name: &str, build: B) -> impl Operation<Output = O::Output>
+where
+    B: FnOnce(u32) -> O,
+    O: Operation,
+{
+    let name = name.to_string();
+    by_name(
+        move |state: &State<'_>| Ok(device_by_name(state.devices, &name)?.rf_address),
+        build,
+    )
+}
+
+/// runs the operation for the room with the name
+pub(crate) fn room_named<B, O>(name: &str, build: B) -> impl Operation<Output = O::Output>
+where
+    B: FnOnce(u8) -> O,
+    O: Operation,
+{
+    let name = name.to_string();
+    by_name(
+        move |state: &State<'_>| Ok(room_by_name(state.rooms, &name)?.room_id),
+        build,
+    )
+}
+
+/// requests the current status of all devices (`l:`), see `MaxCube::refresh()`
+pub(crate) fn refresh() -> impl Operation<Output = ()> {
+    exchange(
+        |_| Ok(Request::new("l:\r\n".to_string(), 'L')),
+        |state, answer| state.update_devices(answer),
+    )
+}
+
+/// sets the thermostat to the manual mode and the temperature
+pub(crate) fn set_temperature(rf_address: u32, temperature: f64) -> impl Operation<Output = ()> {
+    exchange(
+        move |state| {
+            let cmd = set_temperature_command(state.devices, rf_address, temperature)?;
+            Ok(Request::new(cmd, 'S'))
+        },
+        move |_, answer| from_message_s(answer, rf_address),
+    )
+}
+
+/// sets the thermostat to the mode and the temperature, the vacation mode with the end of the vacation
+pub(crate) fn set_mode(
+    rf_address: u32,
+    mode: DeviceMode,
+    temperature: f64,
+    until: Option<DateTime>,
+) -> impl Operation<Output = ()> {
+    exchange(
+        move |state| {
+            let cmd = mode_command(state.devices, rf_address, mode, temperature, until)?;
+            Ok(Request::new(cmd, 'S'))
+        },
+        move |_, answer| from_message_s(answer, rf_address),
+    )
+}
+
+/// requests the configuration of the device
+pub(crate) fn device_settings(rf_address: u32) -> impl Operation<Output = DeviceSettings> {
+    exchange(
+        move |_| Ok(Request::new(format!("c:{:06x}\r\n", rf_address), 'C')),
+        |_, answer| from_message_c(answer),
+    )
+}
+
+/// sets the time scheduling of the thermostat for one day
+pub(crate) fn set_program(
+    rf_address: u32,
+    day: u8,
+    program: &DayProgram,
+) -> impl Operation<Output = ()> {
+    let program = program.clone();
+    exchange(
+        move |state| {
+            let cmd = program_command(state.devices, rf_address, day, &program)?;
+            Ok(Request::new(cmd, 'S'))
+        },
+        move |_, answer| from_message_s(answer, rf_address),
+    )
+}
+
+/// renames the device in the meta data, the devices are updated after the cube has answered
+pub(crate) fn rename_device(rf_address: u32, name: &str) -> impl Operation<Output = ()> {
+    let renamed = name.to_string();
+    let name = name.to_string();
+    exchange(
+        move |state| {
+            let devices = renamed_device(state.devices, rf_address, &renamed)?;
+            Ok(Request::new(meta_data_command(state.rooms, &devices), 'A'))
+        },
+        move |state, _| {
+            *state.devices = renamed_device(state.devices, rf_address, &name)?;
+            Ok(())
+        },
+    )
+}
+
+/// returns the devices with the renamed device
+fn renamed_device(devices: &Devices, rf_address: u32, name: &str) -> Result<Devices> {
+    let mut devices = devices.clone();
+    match devices
+        .iter_mut()
+        .find(|e| e.rf_address() == Some(rf_address))
+    {
+        Some(Device::HeaterThermostat(ts)) => ts.name = name.to_string(),
+        Some(Device::WallThermostat(wt)) => wt.name = name.to_string(),
+        Some(Device::ShutterContact(sc)) => sc.name = name.to_string(),
+        Some(Device::Other(od)) => od.name = name.to_string(),
+        _ => return Err(Error::DeviceNotFound(rf_address)),
+    }
+    Ok(devices)
+}
+
+/// renames the room in the meta data, the rooms are updated after the cube has answered
+pub(crate) fn rename_room(room_id: u8, name: &str) -> impl Operation<Output = ()> {
+    let renamed = name.to_string();
+    let name = name.to_string();
+    exchange(
+        move |state| {
+            let rooms = renamed_room(state.rooms, room_id, &renamed)?;
+            Ok(Request::new(meta_data_command(&rooms, state.devices), 'A'))
+        },
+        move |state, _| {
+            *state.rooms = renamed_room(state.rooms, room_id, &name)?;
+            Ok(())
+        },
+    )
+}
+
+/// returns the rooms with the renamed room
+fn renamed_room(rooms: &Rooms, room_id: u8, name: &str) -> Result<Rooms> {
+    let mut rooms = rooms.clone();
+    match rooms.iter_mut().find(|e| e.room_id == room_id) {
+        Some(room) => room.name = name.to_string(),
+        None => return Err(Error::RoomNotFound(room_id)),
+    }
+    Ok(rooms)
+}
+
+/// deletes the device from the cube
+pub(crate) fn delete_device(rf_address: u32) -> impl Operation<Output = ()> {
+    let paired = move |e: &Device| e.rf_address() == Some(rf_address);
+    exchange(
+        move |state| {
+            if !state.devices.iter().any(paired) {
+                return Err(Error::DeviceNotFound(rf_address));
+            }
+            Ok(Request::new(delete_command(&[rf_address]), 'A'))
+        },
+        move |state, _| {
+            state.devices.retain(|e| !paired(e));
+            update_rooms(state.rooms, state.devices);
+            Ok(())
+        },
+    )
+}
+
+/// requests the settings of all heater thermostats one after another,
+/// `finish` returns the result from them
+pub(crate) struct ReadSettings<F> {
+    addresses: Option<Vec<u32>>,
+    settings: Vec<DeviceSettings>,
+    finish: Option<F>,
+}
+
+impl<F, T> Operation for ReadSettings<F>
+where
+    F: FnOnce(&State<'_>, &[DeviceSettings]) -> Result<T>,
+{
+    type Output = T;
+
+    fn step(&mut self, state: &mut State<'_>, answer: Option<String>) -> Result<Step<T>> {
+        if let Some(answer) = answer {
+            self.settings.push(from_message_c(&answer)?);
+        }
+        let addresses = self
+            .addresses
+            .get_or_insert_with(|| state.thermostat_addresses());
+
+        match addresses.get(self.settings.len()) {
+            Some(rf_address) => Ok(Step::Request(Request::new(
+                format!("c:{:06x}\r\n", rf_address),
+                'C',
+            ))),
+            None => {
+                let finish = self.finish.take().expect("settings read twice");
+                Ok(Step::Done(finish(state, &self.settings)?))
+            }
+        }
+    }
+}
+
+fn read_settings<F, T>(finish: F) -> ReadSettings<F>
+where
+    F: FnOnce(&State<'_>, &[DeviceSettings]) -> Result<T>,
+{
+    ReadSettings {
+        addresses: None,
+        settings: Vec::new(),
+        finish: Some(finish),
+    }
+}
+
+/// returns the backup of the configuration, see `MaxCube::export_backup()`
+pub(crate) fn export_backup() -> impl Operation<Output = Backup> {
+    read_settings(|state, settings| {
+        Ok(Backup::new(
+            &state.status.serial,
+            state.rooms,
+            state.devices,
+            settings,
+        ))
+    })
+}
+
+/// compares the desired state with the configuration of the cube, see `MaxCube::plan()`
+pub(crate) fn plan(desired: &DesiredState) -> impl Operation<Output = Plan> {
+    let desired = desired.clone();
+    read_settings(move |state, settings| Plan::new(&desired, state.rooms, state.devices, settings))
+}
+
+/// sends the meta data, if set, and then the commands to the devices
+pub(crate) struct SendCommands {
+    /// the meta data, which is not sent yet or not answered yet
+    meta_data: Option<(Rooms, Devices)>,
+    commands: Vec<(u32, String)>,
+    /// the number of commands sent
+    sent: usize,
+    /// the refusal by the duty cycle is reported as `Error::BackupIncomplete`
+    backup: bool,
+}
+
+impl SendCommands {
+    /// returns the error of the command with the index, which is not sent or refused
+    fn stopped(&self, e: Error, index: usize) -> Error {
+        match e {
+            Error::DutyCycle { duty_cycle, .. } if self.backup => {
+                let mut remaining = Vec::new();
+                for (rf_address, _) in &self.commands[index..] {
+                    if !remaining.contains(rf_address) {
+                        remaining.push(*rf_address);
+                    }
+                }
+                Error::BackupIncomplete {
+                    duty_cycle,
+                    remaining,
+                }
+            }
+            e => e,
+        }
+    }
+}
+
+impl Operation for SendCommands {
+    type Output = ();
+
+    fn step(&mut self, state: &mut State<'_>, answer: Option<String>) -> Result<Step<()>> {
+        match (answer, &self.meta_data) {
+            (None, Some((rooms, devices))) => {
+                return Ok(Step::Request(Request::new(
+                    meta_data_command(rooms, devices),
+                    'A',
+                )));
+            }
+            (Some(_), Some(_)) => {
+                // the meta data is answered
+                if let Some((rooms, devices)) = self.meta_data.take() {
+                    (*state.rooms, *state.devices) = (rooms, devices);
+                }
+            }
+            (Some(answer), None) => {
+                let index = self.sent - 1;
+                if let Err(e) = from_message_s(&answer, self.commands[index].0) {
+                    return Err(self.stopped(e, index));
+                }
+            }
+            (None, None) => {}
+        }
+
+        let Some((rf_address, cmd)) = self.commands.get(self.sent) else {
+            return Ok(Step::Done(()));
+        };
+        if state.status.duty_cycle >= DUTY_CYCLE_LIMIT {
+            let e = Error::DutyCycle {
+                rf_address: *rf_address,
+                duty_cycle: state.status.duty_cycle,
+            };
+            return Err(self.stopped(e, self.sent));
+        }
+        let request = Request::new(cmd.clone(), 'S');
+        self.sent += 1;
+        Ok(Step::Request(request))
+    }
+}
+
+/// re-creates the configuration of the backup and refreshes the devices, see `MaxCube::import_backup()`
+pub(crate) fn import_backup(backup: &Backup) -> Result<impl Operation<Output = ()>> {
+    backup.check_version()?;
+    let (rooms, devices) = backup.meta_data();
+    let commands = backup.commands(&devices)?;
+
+    Ok(SendCommands {
+        meta_data: Some((rooms, devices)),
+        commands,
+        sent: 0,
+        backup: true,
+    }
+    .then(refresh()))
+}
+
+/// sends the commands of the plan and refreshes the devices, see `MaxCube::apply()`
+pub(crate) fn apply(plan: &Plan) -> impl Operation<Output = ()> {
+    SendCommands {
+        meta_data: plan.meta_data.clone(),
+        commands: plan.commands.clone(),
+        sent: 0,
+        backup: false,
+    }
+    .then(refresh())
+}
+
+/// returns the message, which starts the pairing for the timeout.
+/// The answer shall only be read after the timeout, and is passed to `State::paired()`.
+pub(crate) fn pairing(timeout: Duration) -> String {
+    PairingConfig::new(timeout).build()
+}
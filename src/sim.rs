@@ -0,0 +1,392 @@
+//! Simulator of the MAX! Cube, which speaks the TCP protocol of the cube.
+//! It keeps a virtual house of thermostats, whose valves and temperatures react to the commands.
+//! It is available with the feature `sim`, and as binary `max-cube-sim`.
+
+use base64::{engine::general_purpose, Engine as _};
+use futures::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use log::{debug, info};
+
+use crate::error::{Error, Result};
+use crate::messages::{
+    from_message_m, to_message_c, to_message_h, to_message_l, to_message_m, to_message_n,
+    CubeStatus, Device, DeviceMode, DeviceSettings, Devices, HeaterThermostat, Room, Rooms,
+    ThermostatSettings,
+};
+use crate::rt::{self, ToSocketAddrs};
+use crate::transport::Transport;
+
+/// Simulator represents a virtual cube with its rooms and devices
+/// # Examples
+///
+/// ```no_run
+/// # use eq3_max_cube_rs::sim::Simulator;
+/// # futures::executor::block_on(async {
+/// let mut sim = Simulator::new("SIM0000001", 0x0b5e9b)
+///     .add_room(1, "Living room")
+///     .add_thermostat(0x1aea01, "SIM0000002", "Living room", 1);
+/// sim.serve("127.0.0.1:62910").await.unwrap();
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    serial: String,
+    rf_address: u32,
+    rooms: Rooms,
+    devices: Devices,
+    /// RF address of the next paired device
+    next_rf_address: u32,
+}
+
+impl Default for Simulator {
+    /// returns a simulator with a small house of three rooms
+    fn default() -> Self {
+        Self::new("SIM0000001", 0x0b5e9b)
+            .add_room(1, "Bedroom")
+            .add_room(2, "Living room")
+            .add_room(3, "Kitchen")
+            .add_thermostat(0x1ae5d3, "SIM0000011", "Bedroom", 1)
+            .add_thermostat(0x1aea01, "SIM0000021", "Living room", 2)
+            .add_thermostat(0x1aea02, "SIM0000022", "Living room window", 2)
+            .add_thermostat(0x1ae72e, "SIM0000031", "Kitchen", 3)
+    }
+}
+
+impl Simulator {
+    /// returns a simulator of an empty house
+    pub fn new(serial: &str, rf_address: u32) -> Self {
+        Self {
+            serial: serial.to_string(),
+            rf_address,
+            rooms: Rooms::new(),
+            devices: Devices::new(),
+            next_rf_address: 0x100001,
+        }
+    }
+
+    /// adds a room to the house
+    pub fn add_room(mut self, room_id: u8, name: &str) -> Self {
+        self.rooms.push(Room {
+            room_id,
+            name: name.to_string(),
+            rf_address: 0,
+            ..Default::default()
+        });
+        self
+    }
+
+    /// adds a thermostat to the room. The thermostat starts with 18°C measured and 20°C set point.
+    pub fn add_thermostat(
+        mut self,
+        rf_address: u32,
+        serial: &str,
+        name: &str,
+        room_id: u8,
+    ) -> Self {
+        if let Some(room) = self
+            .rooms
+            .iter_mut()
+            .find(|e| e.room_id == room_id && e.rf_address == 0)
+        {
+            room.rf_address = rf_address;
+        }
+        self.devices
+            .push(Device::HeaterThermostat(HeaterThermostat {
+                rf_address,
+                serial: serial.to_string(),
+                name: name.to_string(),
+                room_id,
+                temperature_set: 20.0,
+                temperature_measured: 18.0,
+                valid: true,
+                ..Default::default()
+            }));
+        self
+    }
+
+    /// returns the rooms of the house
+    pub fn rooms(&self) -> &Rooms {
+        &self.rooms
+    }
+
+    /// returns the devices of the house, with their current state
+    pub fn devices(&self) -> &Devices {
+        &self.devices
+    }
+
+    /// listens on the address and handles the clients one after another, like the cube does.
+    /// The state of the house is kept between the connections.
+    pub async fn serve<A: ToSocketAddrs>(&mut self, addr: A) -> Result<()> {
+        let listener = rt::listen(addr).await?;
+        loop {
+            let (stream, addr) = rt::accept(&listener).await?;
+            info!("Client {} connected.", addr);
+            if let Err(e) = self.handle(stream).await {
+                info!("Client {} disconnected: {}", addr, e);
+            }
+        }
+    }
+
+    /// handles the session of one client, until it quits or closes the connection
+    pub async fn handle<T: Transport>(&mut self, transport: T) -> Result<()> {
+        let (reader, mut writer) = transport.split();
+        let mut reader = BufReader::new(reader);
+
+        // the cube sends the meta data and status data immediately after connection
+        let mut hello = vec![self.message_h(), to_message_m(&self.rooms, &self.devices)];
+        hello.extend(self.devices.iter().filter_map(message_c));
+        self.tick();
+        hello.push(to_message_l(&self.devices));
+        for line in hello {
+            send(&mut writer, &line).await?;
+        }
+
+        loop {
+            let mut received = String::new();
+            if reader.read_line(&mut received).await? == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+            let received = received.trim_end();
+            debug!("{:?}", received);
+
+            let (command, payload) = received.split_once(':').unwrap_or((received, ""));
+            let answer = match command {
+                "l" => {
+                    self.tick();
+                    Some(to_message_l(&self.devices))
+                }
+                "s" => Some(self.command_s(payload)),
+                "n" => self.command_n(),
+                // unknown devices get a C-Message without configuration, so the client fails immediately
+                "c" => Some(
+                    u32::from_str_radix(payload, 16)
+                        .ok()
+                        .and_then(|rf_address| self.device(rf_address))
+                        .and_then(message_c)
+                        .unwrap_or_else(|| format!("C:{},", payload)),
+                ),
+                "m" => self.command_m(payload),
+                "t" => self.command_t(payload),
+                "q" => {
+                    writer.close().await?;
+                    return Ok(());
+                }
+                _ => {
+                    debug!("Unknown command ignored: {:?}", received);
+                    None
+                }
+            };
+
+            if let Some(answer) = answer {
+                send(&mut writer, &answer).await?;
+            }
+        }
+    }
+
+    /// simulates the heating since the last status request: the valves open according to the
+    /// difference of the temperatures, and the rooms approach their set points.
+    fn tick(&mut self) {
+        for dev in self.devices.iter_mut() {
+            if let Device::HeaterThermostat(ts) = dev {
+                let diff = ts.temperature_set - ts.temperature_measured;
+                ts.valve_position = (diff * 25.0).clamp(0.0, 100.0) as u8;
+                ts.temperature_measured =
+                    ((ts.temperature_measured + diff * 0.1) * 10.0).round() / 10.0;
+            }
+        }
+    }
+
+    /// returns the device with the RF address
+    fn device(&self, rf_address: u32) -> Option<&Device> {
+        self.devices
+            .iter()
+            .find(|e| e.rf_address() == Some(rf_address))
+    }
+
+    /// H-Message with the state of the cube
+    fn message_h(&self) -> String {
+        to_message_h(&CubeStatus {
+            serial: self.serial.clone(),
+            rf_address: self.rf_address,
+            firmware: 0x0113,
+            http_connection_id: 0x477719c0,
+            free_memory_slots: 0x32,
+            year: 2013,
+            month: 12,
+            day: 9,
+            hour: 20,
+            minute: 4,
+            state_time: 3,
+            ..Default::default()
+        })
+    }
+
+    /// s: sends a command to a device. Only setting the temperature is simulated,
+    /// all other commands are acknowledged.
+    fn command_s(&mut self, payload: &str) -> String {
+        let b = match general_purpose::STANDARD.decode(payload) {
+            Ok(b) if b.len() >= 10 => b,
+            _ => return "S:00,1,32".to_string(),
+        };
+
+        if b[2] == 0x40 && b.len() >= 11 {
+            let rf_address = u32::from_be_bytes([0, b[6], b[7], b[8]]);
+            let room_id = b[9];
+            let group = (b[1] & 0x04) > 0;
+            let temperature = (b[10] & 0x3f) as f64 / 2.0;
+            // the temperature 0 in the auto mode keeps the set point of the time scheduling
+            let keep = b[10] >> 6 == 0 && temperature == 0.0;
+
+            let mut found = false;
+            for dev in self.devices.iter_mut() {
+                if let Device::HeaterThermostat(ts) = dev {
+                    // the group flag applies the command to the whole room, room 0 means all rooms
+                    if ts.rf_address == rf_address
+                        || (group && (room_id == 0 || ts.room_id == room_id))
+                    {
+                        if !keep {
+                            ts.temperature_set = temperature;
+                        }
+                        ts.mode = DeviceMode::from_bits(b[10] >> 6);
+                        found = true;
+                    }
+                }
+            }
+            if !found {
+                return "S:00,1,32".to_string();
+            }
+        }
+
+        "S:00,0,32".to_string()
+    }
+
+    /// n: pairs a new thermostat, which is added to the house without room
+    fn command_n(&mut self) -> Option<String> {
+        let rf_address = self.next_rf_address;
+        self.next_rf_address += 1;
+
+        let device = Device::HeaterThermostat(HeaterThermostat {
+            rf_address,
+            serial: format!("SIM{:07}", rf_address & 0xfffff),
+            temperature_set: 20.0,
+            temperature_measured: 18.0,
+            valid: true,
+            ..Default::default()
+        });
+        let answer = to_message_n(&device).ok();
+        self.devices.push(device);
+        answer
+    }
+
+    /// m: writes the meta data, the rooms and the names and rooms of the devices are taken over
+    fn command_m(&mut self, payload: &str) -> Option<String> {
+        let data = payload.split(',').nth(1)?;
+        let (rooms, devices) = from_message_m(&format!("M:00,01,{}", data)).ok()?;
+
+        self.rooms = rooms;
+        for new in devices {
+            if let Device::HeaterThermostat(new) = new {
+                for dev in self.devices.iter_mut() {
+                    if let Device::HeaterThermostat(ts) = dev {
+                        if ts.rf_address == new.rf_address {
+                            ts.name = new.name.clone();
+                            ts.room_id = new.room_id;
+                        }
+                    }
+                }
+            }
+        }
+        Some("A:".to_string())
+    }
+
+    /// t: deletes the devices with the RF addresses
+    fn command_t(&mut self, payload: &str) -> Option<String> {
+        let data = payload.split(',').nth(2)?;
+        let b = general_purpose::STANDARD.decode(data).ok()?;
+
+        for rf in b.chunks_exact(3) {
+            let rf_address = u32::from_be_bytes([0, rf[0], rf[1], rf[2]]);
+            self.devices.retain(|e| e.rf_address() != Some(rf_address));
+        }
+        Some("A:".to_string())
+    }
+}
+
+/// C-Message with the configuration of the device, the thermostats have the factory settings
+fn message_c(device: &Device) -> Option<String> {
+    match device {
+        Device::HeaterThermostat(ts) => Some(to_message_c(&DeviceSettings {
+            rf_address: ts.rf_address,
+            device_type: 1,
+            room_id: ts.room_id,
+            firmware: 0x10,
+            serial: ts.serial.clone(),
+            thermostat: Some(ThermostatSettings::default()),
+            ..Default::default()
+        })),
+        _ => None,
+    }
+}
+
+/// sends one line to the client
+async fn send<W: futures::io::AsyncWrite + Unpin>(writer: &mut W, line: &str) -> Result<()> {
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\r\n").await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::MaxCube;
+
+    #[test]
+    fn test_simulator() {
+        rt::block_on(async {
+            let listener = rt::listen("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = rt::spawn(async move {
+                let mut sim = Simulator::default();
+                let (stream, _) = rt::accept(&listener).await.unwrap();
+                sim.handle(stream).await.unwrap();
+                sim
+            });
+
+            let mut cube = MaxCube::new(addr).await.unwrap();
+            assert_eq!(cube.rooms.len(), 3);
+            assert_eq!(cube.devices.len(), 4);
+
+            cube.set_temperature(0x1ae5d3, 25.0).await.unwrap();
+            cube.refresh().await.unwrap();
+            cube.refresh().await.unwrap();
+            match &cube.devices[0] {
+                Device::HeaterThermostat(ts) => {
+                    assert_eq!(ts.temperature_set, 25.0);
+                    assert!(ts.valve_position > 0);
+                    assert!(ts.temperature_measured > 18.0);
+                }
+                _ => panic!("Wrong device type!"),
+            }
+
+            // thermostats in other rooms keep their set point
+            match &cube.devices[1] {
+                Device::HeaterThermostat(ts) => assert_eq!(ts.temperature_set, 20.0),
+                _ => panic!("Wrong device type!"),
+            }
+
+            assert!(matches!(
+                cube.device_settings(0x123456).await,
+                Err(Error::DeviceNotFound(0x123456))
+            ));
+
+            cube.pair(Duration::from_millis(10)).await.unwrap();
+            assert_eq!(cube.devices.len(), 5);
+            cube.close().await.unwrap();
+
+            let sim = server.await;
+            assert_eq!(sim.devices().len(), 5);
+        });
+    }
+}
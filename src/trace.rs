@@ -0,0 +1,457 @@
+//! Recording and replay of the sessions with the cube.
+//! A `Recorder` writes every line sent to and received from the cube with a timestamp to a trace,
+//! `Replay` plays back a trace as if it were the cube, e.g. to reproduce issues of an installation in tests.
+//! The `Replay` is only available with an async runtime.
+//!
+//! Every line of a trace is one entry: the seconds since the start of the recording,
+//! the direction (`>` sent to the cube, `<` received from the cube) and the line itself.
+//!
+//! ```text
+//! 0.012 < H:KEQ0523864,0b5e9b,0113,00000000,477719c0,00,32,0d0c09,1404,03,0000
+//! 0.015 < L:CxrnLgkSGQAmAM0A
+//! 5.001 > l:
+//! 5.020 < L:CxrnLgkSGQAmAM0A
+//! ```
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::error::{Error, Result};
+
+cfg_async! {
+    use std::collections::VecDeque;
+    use std::future::Future;
+    use std::io::{self, BufReader};
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+
+    use futures::io::{AsyncRead, AsyncWrite};
+
+    use crate::rt;
+}
+
+/// direction of a line in the trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// sent from the client to the cube
+    Sent,
+    /// received from the cube
+    Received,
+}
+
+/// one line of the trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// time since the start of the recording, in milliseconds resolution
+    pub elapsed: Duration,
+    pub direction: Direction,
+    /// the line without the trailing line break
+    pub line: String,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::Sent => '>',
+            Direction::Received => '<',
+        };
+        write!(
+            f,
+            "{}.{:03} {} {}",
+            self.elapsed.as_secs(),
+            self.elapsed.subsec_millis(),
+            direction,
+            self.line
+        )
+    }
+}
+
+impl FromStr for TraceEntry {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::protocol("Trace entry not well-formatted.", s);
+
+        let mut parts = s.splitn(3, ' ');
+        let elapsed = parts
+            .next()
+            .and_then(|e| e.parse::<f64>().ok())
+            .filter(|e| e.is_finite() && *e >= 0.0)
+            .ok_or_else(invalid)?;
+        let direction = match parts.next() {
+            Some(">") => Direction::Sent,
+            Some("<") => Direction::Received,
+            _ => return Err(invalid()),
+        };
+
+        Ok(Self {
+            elapsed: Duration::from_millis((elapsed * 1000.0).round() as u64),
+            direction,
+            line: parts.next().unwrap_or_default().to_string(),
+        })
+    }
+}
+
+/// reads all entries of a trace, empty lines are skipped
+pub fn read_trace<R: BufRead>(reader: R) -> Result<Vec<TraceEntry>> {
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            entries.push(line.trim_end_matches('\r').parse()?);
+        }
+    }
+    Ok(entries)
+}
+
+/// Recorder writes the lines of a session to a trace.
+/// It can be cloned, all clones write to the same trace.
+/// The cube writes to it, when it is set by `MaxCubeBuilder::set_recorder()` or the same function
+/// of the blocking client.
+/// # Examples
+///
+/// ```no_run
+/// # use eq3_max_cube_rs::trace::Recorder;
+/// let file = Recorder::create("cube.trace").unwrap();
+/// let stderr = Recorder::new(std::io::stderr());
+/// ```
+#[derive(Clone)]
+pub struct Recorder {
+    start: Instant,
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("start", &self.start)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Recorder {
+    /// returns a recorder, which writes the trace to the writer
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            start: Instant::now(),
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+        }
+    }
+
+    /// returns a recorder, which writes the trace to the file. An existing file is overwritten.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(File::create(path)?))
+    }
+
+    /// writes one line to the trace. Failures are logged only, the session shall not be
+    /// interrupted by the recording.
+    pub(crate) fn record(&self, direction: Direction, line: &str) {
+        let entry = TraceEntry {
+            elapsed: self.start.elapsed(),
+            direction,
+            line: line.trim_end_matches(['\r', '\n']).to_string(),
+        };
+
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(e) => e.into_inner(),
+        };
+        if let Err(e) = writeln!(writer, "{}", entry).and_then(|_| writer.flush()) {
+            warn!("Recording failed: {}", e);
+        }
+    }
+}
+
+cfg_async! {
+    /// Replay is a transport, which plays back a trace as if it were the cube.
+    /// The received lines are answered in the order of the trace, the lines after a sent line are only
+    /// answered after the client has sent the same line. If the client sends another line than recorded,
+    /// the write fails with `InvalidData`. The end of the trace closes the connection.
+    /// By default, the timestamps are ignored and the trace is played back as fast as possible.
+    /// With `set_realtime()`, the recorded delays are kept, e.g. to reproduce timeouts.
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use eq3_max_cube_rs::{trace::Replay, MaxCube};
+    /// # futures::executor::block_on(async {
+    /// let mut cube = MaxCube::from_transport(Replay::open("cube.trace").unwrap()).await.unwrap();
+    /// cube.refresh().await.unwrap();
+    /// # });
+    /// ```
+    pub struct Replay {
+        entries: VecDeque<TraceEntry>,
+        /// received lines, which are ready to be read
+        pending: VecDeque<u8>,
+        /// sent data, which is not a complete line yet
+        sent: Vec<u8>,
+        /// reader waiting for the next sent line
+        waker: Option<Waker>,
+        /// the recorded delays are kept
+        realtime: bool,
+        /// timestamp of the last played entry and the instant it was played
+        played: Option<(Duration, Instant)>,
+        /// timer of the next received line, which is not due yet
+        delay: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    }
+
+    impl fmt::Debug for Replay {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Replay")
+                .field("entries", &self.entries)
+                .field("realtime", &self.realtime)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl Replay {
+        /// returns a replay of the trace entries
+        pub fn new(entries: Vec<TraceEntry>) -> Self {
+            Self {
+                entries: entries.into(),
+                pending: VecDeque::new(),
+                sent: Vec::new(),
+                waker: None,
+                realtime: false,
+                played: None,
+                delay: None,
+            }
+        }
+
+        /// keeps the recorded delays: a received line is played back as long after the entry before, as it
+        /// was recorded. Lines are never delayed in favour of a line sent by the client.
+        pub fn set_realtime(mut self, realtime: bool) -> Self {
+            self.realtime = realtime;
+            self
+        }
+
+        /// returns a replay of the trace file
+        pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+            Ok(Self::new(read_trace(BufReader::new(File::open(path)?))?))
+        }
+
+        /// returns if all entries of the trace are played back
+        pub fn is_finished(&self) -> bool {
+            self.entries.is_empty() && self.pending.is_empty()
+        }
+
+        /// moves the received lines up to the next sent line to the pending data.
+        /// If `delayed` is set, it stops at the first line, which is not due yet, and returns the time to wait.
+        fn receive(&mut self, delayed: bool) -> Option<Duration> {
+            let now = Instant::now();
+            while let Some(entry) = self.entries.front() {
+                if entry.direction == Direction::Sent {
+                    break;
+                }
+                let due = match self.played {
+                    Some((elapsed, instant)) => instant + entry.elapsed.saturating_sub(elapsed),
+                    None => now,
+                };
+                if delayed && due > now {
+                    return Some(due - now);
+                }
+                self.played = Some((entry.elapsed, due.min(now)));
+                self.pending.extend(entry.line.as_bytes());
+                self.pending.extend(b"\r\n");
+                self.entries.pop_front();
+            }
+            None
+        }
+    }
+
+    impl AsyncRead for Replay {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            while self.pending.is_empty() {
+                if let Some(delay) = self.delay.as_mut() {
+                    if delay.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                    self.delay = None;
+                }
+                let realtime = self.realtime;
+                match self.receive(realtime) {
+                    Some(wait) => self.delay = Some(Box::pin(rt::sleep(wait))),
+                    None => break,
+                }
+            }
+
+            if !self.pending.is_empty() {
+                let length = buf.len().min(self.pending.len());
+                for (dst, src) in buf.iter_mut().zip(self.pending.drain(..length)) {
+                    *dst = src;
+                }
+                Poll::Ready(Ok(length))
+            } else if self.entries.is_empty() {
+                // end of the trace
+                Poll::Ready(Ok(0))
+            } else {
+                // the cube waits for the next line of the client
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    impl AsyncWrite for Replay {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.sent.extend_from_slice(buf);
+
+            while let Some(end) = self.sent.iter().position(|e| *e == b'\n') {
+                let line = String::from_utf8_lossy(&self.sent[..end])
+                    .trim_end_matches('\r')
+                    .to_string();
+                self.sent.drain(..=end);
+
+                // the cube has sent the lines before, even if the client did not read them yet
+                self.receive(false);
+                self.delay = None;
+                match self.entries.front() {
+                    Some(entry) if entry.line == line => {
+                        self.played = Some((entry.elapsed, Instant::now()));
+                        self.entries.pop_front();
+                    }
+                    entry => {
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "Line {:?} sent, but {:?} recorded.",
+                                line,
+                                entry.map(|e| e.line.as_str()).unwrap_or("end of trace")
+                            ),
+                        )));
+                    }
+                }
+            }
+
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::io;
+
+    use super::*;
+
+    cfg_async! {
+        use crate::messages::test::trace;
+        use crate::rt;
+        use crate::MaxCubeBuilder;
+    }
+
+    /// the recorded session of the test house with a refused command
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    const SESSION: &str = "1.200 > s:AARAAAAAGuXTAW4=
+1.350 < S:64,1,0
+2.000 > q:
+";
+
+    /// writer, which can be read after the recording
+    #[derive(Clone, Default)]
+    pub(crate) struct Shared(pub(crate) Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace_entry() {
+        let entry: TraceEntry = "1.350 < S:64,1,0".parse().unwrap();
+        assert_eq!(entry.elapsed, Duration::from_millis(1350));
+        assert_eq!(entry.direction, Direction::Received);
+        assert_eq!(entry.line, "S:64,1,0");
+        assert_eq!(entry.to_string(), "1.350 < S:64,1,0");
+
+        assert!("1.350 S:64,1,0".parse::<TraceEntry>().is_err());
+        assert!("< S:64,1,0".parse::<TraceEntry>().is_err());
+    }
+
+    #[test]
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    fn test_record_replay() {
+        let output = Shared::default();
+        let replay = Replay::new(read_trace(trace(SESSION).as_bytes()).unwrap());
+
+        rt::block_on(async {
+            let mut cube = MaxCubeBuilder::new()
+                .set_recorder(Recorder::new(output.clone()))
+                .with_transport(replay)
+                .await
+                .unwrap();
+            assert_eq!(cube.devices.len(), 5);
+            assert!(matches!(
+                cube.set_temperature(1762771, 23.0).await,
+                Err(Error::DutyCycle { .. })
+            ));
+            cube.close().await.unwrap();
+        });
+
+        // the session is recorded with the same lines, but other timestamps
+        let recorded = read_trace(output.0.lock().unwrap().as_slice()).unwrap();
+        let expected = read_trace(trace(SESSION).as_bytes()).unwrap();
+        assert_eq!(recorded.len(), expected.len());
+        for (recorded, expected) in recorded.iter().zip(expected) {
+            assert_eq!(recorded.direction, expected.direction);
+            assert_eq!(recorded.line, expected.line);
+        }
+    }
+
+    #[test]
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    fn test_replay_realtime() {
+        let replay = Replay::new(read_trace(trace(SESSION).as_bytes()).unwrap()).set_realtime(true);
+        rt::block_on(async {
+            let mut cube = MaxCubeBuilder::new().with_transport(replay).await.unwrap();
+            // the answer is recorded 150 ms after the command
+            let start = Instant::now();
+            assert!(matches!(
+                cube.set_temperature(1762771, 23.0).await,
+                Err(Error::DutyCycle { .. })
+            ));
+            assert!(start.elapsed() >= Duration::from_millis(150));
+        });
+    }
+
+    #[test]
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    fn test_replay_mismatch() {
+        let replay = Replay::new(read_trace(trace(SESSION).as_bytes()).unwrap());
+        rt::block_on(async {
+            let mut cube = MaxCubeBuilder::new().with_transport(replay).await.unwrap();
+            assert!(matches!(cube.refresh().await, Err(Error::Io(_))));
+        });
+    }
+}
@@ -0,0 +1,63 @@
+use futures::io::{AsyncRead, AsyncWrite};
+
+/// Transport is a duplex byte stream to the cube.
+/// It is implemented for all streams of the `futures` IO traits, e.g. the TCP stream of the runtime,
+/// but also in-memory streams for testing.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Transport for T {}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+
+    use futures::io::{AsyncRead, AsyncWrite, Cursor};
+
+    /// in-memory transport, which plays the scripted answers and records all sent data
+    pub(crate) struct Script {
+        input: Cursor<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Script {
+        pub(crate) fn new(answers: impl AsRef<[u8]>) -> (Self, Arc<Mutex<Vec<u8>>>) {
+            let output = Arc::new(Mutex::new(Vec::new()));
+            let script = Self {
+                input: Cursor::new(answers.as_ref().to_vec()),
+                output: output.clone(),
+            };
+            (script, output)
+        }
+    }
+
+    impl AsyncRead for Script {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Pin::new(&mut self.input).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Script {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.output.lock().unwrap().extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+}
@@ -0,0 +1,598 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+cfg_async! {
+    use std::time::Instant;
+
+    use crate::rt::{self, ToSocketAddrs, UdpSocket};
+}
+
+/// UDP port of the cube
+pub const UDP_PORT: u16 = 23272;
+
+/// header of all requests to the cube
+const REQUEST_HEADER: &[u8; 8] = b"eQ3Max*\0";
+/// header of all responses from the cube
+const RESPONSE_HEADER: &[u8; 8] = b"eQ3MaxAp";
+/// serial number placeholder, addressing all cubes
+const ANY_SERIAL: &[u8; 10] = b"**********";
+
+/// represents a cube, which answered the discovery
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CubeInfo {
+    /// Serial number of the cube
+    pub serial: String,
+    /// RF address of the cube
+    pub rf_address: u32,
+    /// firmware version, e.g. 0x0113 for 1.1.3
+    pub firmware: u16,
+    /// IP address, from which the cube answered
+    pub ip: IpAddr,
+}
+
+/// represents the IP configuration of the cube
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    /// if the address is obtained by DHCP. All addresses are sent as 0.0.0.0 in this case.
+    pub dhcp: bool,
+    /// IP address of the cube
+    pub ip: Ipv4Addr,
+    /// netmask of the network
+    pub netmask: Ipv4Addr,
+    /// default gateway
+    pub gateway: Ipv4Addr,
+    /// primary DNS server
+    pub dns1: Ipv4Addr,
+    /// secondary DNS server
+    pub dns2: Ipv4Addr,
+}
+
+impl NetworkConfig {
+    /// returns a configuration, which lets the cube obtain its address by DHCP
+    pub fn dhcp() -> Self {
+        Self {
+            dhcp: true,
+            ip: Ipv4Addr::UNSPECIFIED,
+            netmask: Ipv4Addr::UNSPECIFIED,
+            gateway: Ipv4Addr::UNSPECIFIED,
+            dns1: Ipv4Addr::UNSPECIFIED,
+            dns2: Ipv4Addr::UNSPECIFIED,
+        }
+    }
+
+    /// build the payload of the set command
+    #[cfg_attr(
+        not(any(
+            feature = "runtime-async-std",
+            feature = "runtime-tokio",
+            feature = "blocking"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn build(&self) -> Vec<u8> {
+        if self.dhcp {
+            return vec![0; 20];
+        }
+        [self.ip, self.gateway, self.netmask, self.dns1, self.dns2]
+            .iter()
+            .flat_map(|e| e.octets())
+            .collect()
+    }
+}
+
+cfg_async! {
+    /// discovers all cubes in the local network by a UDP broadcast.
+    /// All answers received within the timeout are returned.
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # futures::executor::block_on(async {
+    /// let cubes = eq3_max_cube_rs::discover(Duration::from_secs(2)).await.unwrap();
+    /// for cube in cubes {
+    ///     println!("{} at {}", cube.serial, cube.ip);
+    /// }
+    /// # });
+    /// ```
+    pub async fn discover(timeout: Duration) -> Result<Vec<CubeInfo>> {
+        discover_at(SocketAddr::from((Ipv4Addr::BROADCAST, UDP_PORT)), timeout).await
+    }
+
+    /// sends the discovery to the given address, e.g. the broadcast address of another subnet
+    /// or the address of a known cube.
+    pub async fn discover_at<A>(addr: A, timeout: Duration) -> Result<Vec<CubeInfo>>
+    where
+        A: ToSocketAddrs,
+    {
+        query(addr, timeout, Discovery::default()).await
+    }
+
+    /// discovers the cube with the serial number in the local network. It returns as soon as the cube has
+    /// answered, `Error::CubeNotFound` is returned, if it did not answer within the timeout.
+    pub async fn discover_serial(serial: &str, timeout: Duration) -> Result<CubeInfo> {
+        discover_serial_at(SocketAddr::from((Ipv4Addr::BROADCAST, UDP_PORT)), serial, timeout).await
+    }
+
+    /// discovers the cube with the serial number, the request is sent to the given address
+    pub async fn discover_serial_at<A>(addr: A, serial: &str, timeout: Duration) -> Result<CubeInfo>
+    where
+        A: ToSocketAddrs,
+    {
+        query(addr, timeout, SerialDiscovery::new(serial)).await
+    }
+
+    /// reads the IP configuration of the cube with the serial number
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use eq3_max_cube_rs::udp;
+    /// # futures::executor::block_on(async {
+    /// let config = udp::network_config("KEQ0523864", Duration::from_secs(2)).await.unwrap();
+    /// println!("{:?}", config);
+    /// # });
+    /// ```
+    pub async fn network_config(serial: &str, timeout: Duration) -> Result<NetworkConfig> {
+        network_config_at(SocketAddr::from((Ipv4Addr::BROADCAST, UDP_PORT)), serial, timeout).await
+    }
+
+    /// reads the IP configuration of the cube with the serial number, the request is sent to the given address
+    pub async fn network_config_at<A>(addr: A, serial: &str, timeout: Duration) -> Result<NetworkConfig>
+    where
+        A: ToSocketAddrs,
+    {
+        query(addr, timeout, NetworkRequest::new(serial)).await
+    }
+
+    /// changes the IP configuration of the cube with the serial number.
+    /// The cube does not answer this request, the new configuration is applied after a reboot.
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use eq3_max_cube_rs::udp::{self, NetworkConfig};
+    /// # futures::executor::block_on(async {
+    /// udp::set_network_config("KEQ0523864", &NetworkConfig::dhcp()).await.unwrap();
+    /// udp::reboot("KEQ0523864").await.unwrap();
+    /// # });
+    /// ```
+    pub async fn set_network_config(serial: &str, config: &NetworkConfig) -> Result<()> {
+        set_network_config_at(SocketAddr::from((Ipv4Addr::BROADCAST, UDP_PORT)), serial, config).await
+    }
+
+    /// changes the IP configuration of the cube with the serial number, the request is sent to the given address
+    pub async fn set_network_config_at<A>(addr: A, serial: &str, config: &NetworkConfig) -> Result<()>
+    where
+        A: ToSocketAddrs,
+    {
+        send(addr, &set_network_config_request(serial, config)).await
+    }
+
+    /// reboots the cube with the serial number. The cube does not answer this request.
+    pub async fn reboot(serial: &str) -> Result<()> {
+        reboot_at(SocketAddr::from((Ipv4Addr::BROADCAST, UDP_PORT)), serial).await
+    }
+
+    /// reboots the cube with the serial number, the request is sent to the given address
+    pub async fn reboot_at<A>(addr: A, serial: &str) -> Result<()>
+    where
+        A: ToSocketAddrs,
+    {
+        send(addr, &build_request(Some(serial), b'R')).await
+    }
+
+    /// binds the socket for the communication with the cube.
+    /// Some firmware versions answer to the UDP port of the cube instead of the sending port,
+    /// therefore this port is preferred.
+    pub(crate) async fn bind() -> Result<UdpSocket> {
+        let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, UDP_PORT)).await {
+            Ok(socket) => socket,
+            Err(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?,
+        };
+        socket.set_broadcast(true)?;
+        Ok(socket)
+    }
+
+    /// sends the request of the query and passes the answers to it, until it has all answers
+    /// or the timeout is elapsed
+    async fn query<A, Q>(addr: A, timeout: Duration, mut query: Q) -> Result<Q::Output>
+    where
+        A: ToSocketAddrs,
+        Q: Query,
+    {
+        let socket = bind().await?;
+        socket.send_to(&query.request(), addr).await?;
+
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; 1024];
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match rt::timeout(remaining, socket.recv_from(&mut buf)).await {
+                Some(result) => {
+                    let (len, from) = result?;
+                    debug!("{}: {:?}", from, &buf[..len]);
+                    if query.handle(&buf[..len], from) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        query.finish(timeout)
+    }
+
+    /// sends a request, which the cube does not answer
+    async fn send<A: ToSocketAddrs>(addr: A, data: &[u8]) -> Result<()> {
+        let socket = bind().await?;
+        socket.send_to(data, addr).await?;
+        Ok(())
+    }
+}
+
+/// a request to the cubes and the handling of their answers, without any IO. It is shared by the
+/// async functions and the blocking client, which only send the request and receive the answers.
+pub(crate) trait Query {
+    type Output;
+
+    /// returns the request, which is sent to the cubes
+    fn request(&self) -> Vec<u8>;
+
+    /// takes a received datagram, returns true, if no more answers are needed
+    fn handle(&mut self, data: &[u8], from: SocketAddr) -> bool;
+
+    /// returns the result, after the last answer was taken or the timeout is elapsed
+    fn finish(self, timeout: Duration) -> Result<Self::Output>;
+}
+
+/// discovers all cubes, which answer within the timeout
+#[derive(Default)]
+pub(crate) struct Discovery {
+    cubes: Vec<CubeInfo>,
+}
+
+impl Query for Discovery {
+    type Output = Vec<CubeInfo>;
+
+    fn request(&self) -> Vec<u8> {
+        build_request(None, b'I')
+    }
+
+    fn handle(&mut self, data: &[u8], from: SocketAddr) -> bool {
+        match from_discovery_response(data, from.ip()) {
+            Ok(cube) if !self.cubes.contains(&cube) => self.cubes.push(cube),
+            Ok(_) => {}
+            Err(e) => debug!("Ignored UDP message from {}: {}", from, e),
+        }
+        false
+    }
+
+    fn finish(self, _: Duration) -> Result<Vec<CubeInfo>> {
+        Ok(self.cubes)
+    }
+}
+
+/// discovers the cube with the serial number, it is done as soon as the cube has answered
+pub(crate) struct SerialDiscovery {
+    serial: String,
+    found: Option<CubeInfo>,
+}
+
+impl SerialDiscovery {
+    pub(crate) fn new(serial: &str) -> Self {
+        Self {
+            serial: serial.to_string(),
+            found: None,
+        }
+    }
+}
+
+impl Query for SerialDiscovery {
+    type Output = CubeInfo;
+
+    fn request(&self) -> Vec<u8> {
+        build_request(None, b'I')
+    }
+
+    fn handle(&mut self, data: &[u8], from: SocketAddr) -> bool {
+        match from_discovery_response(data, from.ip()) {
+            Ok(cube) if cube.serial == self.serial => self.found = Some(cube),
+            Ok(_) => {}
+            Err(e) => debug!("Ignored UDP message from {}: {}", from, e),
+        }
+        self.found.is_some()
+    }
+
+    fn finish(self, _: Duration) -> Result<CubeInfo> {
+        self.found.ok_or(Error::CubeNotFound(self.serial))
+    }
+}
+
+/// reads the IP configuration of the cube with the serial number
+pub(crate) struct NetworkRequest {
+    serial: String,
+    found: Option<NetworkConfig>,
+}
+
+impl NetworkRequest {
+    pub(crate) fn new(serial: &str) -> Self {
+        Self {
+            serial: serial.to_string(),
+            found: None,
+        }
+    }
+}
+
+impl Query for NetworkRequest {
+    type Output = NetworkConfig;
+
+    fn request(&self) -> Vec<u8> {
+        build_request(Some(&self.serial), b'N')
+    }
+
+    fn handle(&mut self, data: &[u8], from: SocketAddr) -> bool {
+        match from_network_response(data) {
+            Ok((serial, config)) if serial == self.serial => self.found = Some(config),
+            Ok(_) => {}
+            Err(e) => debug!("Ignored UDP message from {}: {}", from, e),
+        }
+        self.found.is_some()
+    }
+
+    fn finish(self, timeout: Duration) -> Result<NetworkConfig> {
+        self.found.ok_or(Error::Timeout {
+            operation: "Network configuration request",
+            duration: timeout,
+        })
+    }
+}
+
+/// builds a request to the cube with the serial number, or to all cubes
+pub(crate) fn build_request(serial: Option<&str>, command: u8) -> Vec<u8> {
+    let mut data = REQUEST_HEADER.to_vec();
+    match serial {
+        Some(serial) => data.extend(format!("{:*<10.10}", serial).as_bytes()),
+        None => data.extend(ANY_SERIAL),
+    }
+    data.push(command);
+    data
+}
+
+/// builds the request, which changes the IP configuration of the cube with the serial number
+pub(crate) fn set_network_config_request(serial: &str, config: &NetworkConfig) -> Vec<u8> {
+    let mut data = build_request(Some(serial), b'c');
+    data.extend(config.build());
+    data
+}
+
+/// checks the header and the command of the response and returns the serial number and the payload
+pub(crate) fn split_response(data: &[u8], command: u8) -> Result<(String, &[u8])> {
+    let err = |reason: &str| Error::protocol(reason, &String::from_utf8_lossy(data));
+
+    if data.len() < 20 || &data[..8] != RESPONSE_HEADER {
+        return Err(err("Response of the cube expected."));
+    }
+    if data[19] != command {
+        return Err(err(&format!("Response `{}` expected.", command as char)));
+    }
+
+    Ok((
+        String::from_utf8_lossy(&data[8..18]).to_string(),
+        &data[20..],
+    ))
+}
+
+/// decodes the answer of a cube to the discovery
+pub(crate) fn from_discovery_response(data: &[u8], ip: IpAddr) -> Result<CubeInfo> {
+    let (serial, payload) = split_response(data, b'I')?;
+
+    if payload.len() < 6 {
+        return Err(Error::protocol(
+            "Unexpected data length.",
+            &String::from_utf8_lossy(data),
+        ));
+    }
+
+    Ok(CubeInfo {
+        serial,
+        rf_address: u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]),
+        firmware: u16::from_be_bytes([payload[4], payload[5]]),
+        ip,
+    })
+}
+
+/// decodes the answer of a cube to the network configuration request
+#[cfg_attr(
+    not(any(
+        feature = "runtime-async-std",
+        feature = "runtime-tokio",
+        feature = "blocking"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn from_network_response(data: &[u8]) -> Result<(String, NetworkConfig)> {
+    let (serial, payload) = split_response(data, b'N')?;
+
+    if payload.len() < 20 {
+        return Err(Error::protocol(
+            "Unexpected data length.",
+            &String::from_utf8_lossy(data),
+        ));
+    }
+
+    let addr = |i: usize| Ipv4Addr::new(payload[i], payload[i + 1], payload[i + 2], payload[i + 3]);
+    let ip = addr(0);
+
+    Ok((
+        serial,
+        NetworkConfig {
+            dhcp: ip.is_unspecified(),
+            ip,
+            gateway: addr(4),
+            netmask: addr(8),
+            dns1: addr(12),
+            dns2: addr(16),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RESPONSE_I: &[u8] = b"eQ3MaxApKEQ0523864>I\x00\x09\x3a\x41\x01\x13";
+
+    #[test]
+    fn test_discovery_response() {
+        let cube = from_discovery_response(RESPONSE_I, IpAddr::from([192, 168, 0, 222])).unwrap();
+        assert_eq!(cube.serial, "KEQ0523864");
+        assert_eq!(cube.rf_address, 0x093a41);
+        assert_eq!(cube.firmware, 0x0113);
+
+        assert!(from_discovery_response(&RESPONSE_I[..22], cube.ip).is_err());
+        assert!(from_discovery_response(&build_request(None, b'I'), cube.ip).is_err());
+    }
+
+    #[test]
+    fn test_network_config() {
+        let config = NetworkConfig {
+            dhcp: false,
+            ip: Ipv4Addr::new(192, 168, 0, 222),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            gateway: Ipv4Addr::new(192, 168, 0, 1),
+            dns1: Ipv4Addr::new(192, 168, 0, 1),
+            dns2: Ipv4Addr::new(8, 8, 8, 8),
+        };
+
+        let mut response = b"eQ3MaxApKEQ0523864>N".to_vec();
+        response.extend(config.build());
+        assert_eq!(
+            from_network_response(&response).unwrap(),
+            ("KEQ0523864".to_string(), config)
+        );
+
+        let mut response = b"eQ3MaxApKEQ0523864>N".to_vec();
+        response.extend(NetworkConfig::dhcp().build());
+        assert!(from_network_response(&response).unwrap().1.dhcp);
+
+        assert_eq!(
+            build_request(Some("KEQ0523864"), b'R'),
+            b"eQ3Max*\0KEQ0523864R"
+        );
+    }
+
+    #[test]
+    fn test_query() {
+        let from = SocketAddr::from(([192, 168, 0, 222], UDP_PORT));
+        let other = b"eQ3MaxApKEQ0000001>I\x00\x09\x3a\x42\x01\x13";
+
+        let mut discovery = SerialDiscovery::new("KEQ0523864");
+        assert_eq!(discovery.request(), b"eQ3Max*\0**********I");
+        assert!(!discovery.handle(b"garbage", from));
+        assert!(!discovery.handle(other, from));
+        assert!(discovery.handle(RESPONSE_I, from));
+        assert_eq!(
+            discovery.finish(Duration::ZERO).unwrap().serial,
+            "KEQ0523864"
+        );
+
+        let mut discovery = Discovery::default();
+        assert!(!discovery.handle(RESPONSE_I, from));
+        assert!(!discovery.handle(other, from));
+        assert!(!discovery.handle(RESPONSE_I, from));
+        assert_eq!(discovery.finish(Duration::ZERO).unwrap().len(), 2);
+
+        assert!(matches!(
+            SerialDiscovery::new("KEQ0523864").finish(Duration::ZERO),
+            Err(Error::CubeNotFound(_))
+        ));
+        let request = NetworkRequest::new("KEQ0523864");
+        assert_eq!(request.request(), b"eQ3Max*\0KEQ0523864N");
+        assert!(matches!(
+            request.finish(Duration::ZERO),
+            Err(Error::Timeout { .. })
+        ));
+    }
+
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    #[test]
+    fn test_network_config_at() {
+        rt::block_on(async {
+            let responder = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let addr = responder.local_addr().unwrap();
+            let handle = rt::spawn(async move {
+                let mut buf = [0u8; 64];
+                let (len, from) = responder.recv_from(&mut buf).await.unwrap();
+                assert_eq!(&buf[..len], b"eQ3Max*\0KEQ0523864N");
+                let mut response = b"eQ3MaxApKEQ0523864>N".to_vec();
+                response.extend([192, 168, 0, 222, 192, 168, 0, 1, 255, 255, 255, 0]);
+                response.extend([192, 168, 0, 1, 0, 0, 0, 0]);
+                responder.send_to(&response, from).await.unwrap();
+            });
+
+            let config = network_config_at(addr, "KEQ0523864", Duration::from_millis(500))
+                .await
+                .unwrap();
+            handle.await;
+
+            assert!(!config.dhcp);
+            assert_eq!(config.ip, Ipv4Addr::new(192, 168, 0, 222));
+            assert_eq!(config.netmask, Ipv4Addr::new(255, 255, 255, 0));
+        });
+    }
+
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    #[test]
+    fn test_discover() {
+        rt::block_on(async {
+            // local responder, answering like a cube
+            let responder = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let addr = responder.local_addr().unwrap();
+            let handle = rt::spawn(async move {
+                let mut buf = [0u8; 64];
+                let (len, from) = responder.recv_from(&mut buf).await.unwrap();
+                assert_eq!(&buf[..len], b"eQ3Max*\0**********I");
+                responder.send_to(RESPONSE_I, from).await.unwrap();
+            });
+
+            let cubes = discover_at(addr, Duration::from_millis(500)).await.unwrap();
+            handle.await;
+
+            assert_eq!(cubes.len(), 1);
+            assert_eq!(cubes[0].serial, "KEQ0523864");
+            assert_eq!(cubes[0].ip, IpAddr::from([127, 0, 0, 1]));
+        });
+    }
+
+    #[cfg(any(feature = "runtime-async-std", feature = "runtime-tokio"))]
+    #[test]
+    fn test_discover_serial() {
+        rt::block_on(async {
+            let responder = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let addr = responder.local_addr().unwrap();
+            let handle = rt::spawn(async move {
+                let mut buf = [0u8; 64];
+                let (_, from) = responder.recv_from(&mut buf).await.unwrap();
+                responder.send_to(RESPONSE_I, from).await.unwrap();
+            });
+
+            // the answer is returned without waiting for the timeout
+            let start = Instant::now();
+            let cube = discover_serial_at(addr, "KEQ0523864", Duration::from_secs(10))
+                .await
+                .unwrap();
+            handle.await;
+            assert_eq!(cube.serial, "KEQ0523864");
+            assert!(start.elapsed() < Duration::from_secs(5));
+
+            assert!(matches!(
+                discover_serial_at(addr, "KEQ0523864", Duration::from_millis(100)).await,
+                Err(Error::CubeNotFound(_))
+            ));
+        });
+    }
+}